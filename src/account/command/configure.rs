@@ -40,6 +40,12 @@ impl ConfigureAccountCommand {
                 BackendConfig::Imap(config) => Result::<_, Report>::Ok(config.auth.reset().await?),
                 #[cfg(feature = "maildir")]
                 BackendConfig::Maildir(_) => Result::<_, Report>::Ok(()),
+                #[cfg(feature = "notmuch")]
+                BackendConfig::Notmuch(_) => Result::<_, Report>::Ok(()),
+                #[cfg(feature = "jmap")]
+                BackendConfig::Jmap(config) => Result::<_, Report>::Ok(config.auth.reset().await?),
+                #[cfg(feature = "pop3")]
+                BackendConfig::Pop3(config) => Result::<_, Report>::Ok(config.auth.reset().await?),
             };
 
             if let Err(err) = reset {
@@ -69,6 +75,25 @@ impl ConfigureAccountCommand {
             BackendConfig::Maildir(_) => {
                 //
             }
+            #[cfg(feature = "notmuch")]
+            BackendConfig::Notmuch(_) => {
+                //
+            }
+            #[cfg(feature = "jmap")]
+            BackendConfig::Jmap(config) => {
+                let name = config.auth.secret_name();
+                config
+                    .auth
+                    .configure(|| prompt::password(name).map_err(Into::into))
+                    .await?;
+            }
+            #[cfg(feature = "pop3")]
+            BackendConfig::Pop3(config) => {
+                config
+                    .auth
+                    .configure(|| prompt::password("POP3 password").map_err(Into::into))
+                    .await?;
+            }
         };
 
         let re = if self.reset { "re" } else { "" };
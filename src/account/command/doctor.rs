@@ -3,59 +3,65 @@
 //! This module contains the [`clap`] command for checking up left and
 //! right backends integrity of a given account.
 
-use std::sync::Arc;
-
 use clap::Parser;
-use color_eyre::eyre::Result;
-use email::backend::context::BackendContextBuilder;
-#[cfg(feature = "imap")]
-use email::imap::ImapContextBuilder;
-#[cfg(feature = "maildir")]
-use email::maildir::MaildirContextBuilder;
-use pimalaya_tui::terminal::config::TomlConfig as _;
+use color_eyre::eyre::{bail, Result};
+use pimalaya_tui::terminal::{
+    cli::printer::{OutputFmt, Printer, StdoutPrinter},
+    config::TomlConfig as _,
+};
 use tracing::instrument;
 
 use crate::{
-    account::arg::name::OptionalAccountNameArg, backend::config::BackendConfig, config::TomlConfig,
+    account::{
+        arg::name::OptionalAccountNameArg,
+        doctor::{CheckStatus, DoctorReport},
+    },
+    config::TomlConfig,
 };
 
 /// Check up the given account.
 ///
 /// This command performs a checkup of the given account. It checks if
 /// the configuration is valid, if backend can be created and if
-/// sessions work as expected.
+/// sessions work as expected. It also checks watch prerequisites:
+/// watched folders must exist, programs executed by hooks must be
+/// found in PATH and a desktop notification daemon must be reachable.
+///
+/// For IMAP accounts, NOTIFY and IDLE support is checked against the
+/// watch mode: the `notify` and `idle` modes fail when the server
+/// lacks the matching extension, the `auto` mode reports the method
+/// it falls back to (IDLE then polling), and the `poll` mode needs
+/// neither.
+///
+/// The command exits with a non-zero code if at least one check
+/// fails.
 #[derive(Debug, Parser)]
 pub struct DoctorAccountCommand {
     #[command(flatten)]
     pub account: OptionalAccountNameArg,
+
+    /// The output format of the report.
+    ///
+    /// Using the JSON format, the report lists every check with its
+    /// status, its duration in milliseconds and its error chain.
+    #[arg(long, short, value_name = "FORMAT", default_value = "plain")]
+    pub output: OutputFmt,
 }
 
 impl DoctorAccountCommand {
     #[instrument(skip_all)]
     pub async fn execute(self, config: &TomlConfig) -> Result<()> {
         let (name, config) = config.to_toml_account_config(self.account.name.as_deref())?;
-        let (backend, config) = config.into_account_config(name.clone());
-
-        match backend {
-            #[cfg(feature = "imap")]
-            BackendConfig::Imap(imap_config) => {
-                println!("Checking IMAP integrity…");
-                ImapContextBuilder::new(config.clone(), Arc::new(imap_config))
-                    .with_prebuilt_credentials()
-                    .await?
-                    .check()
-                    .await
-            }
-            #[cfg(feature = "maildir")]
-            BackendConfig::Maildir(maildir_config) => {
-                println!("Checking Maildir integrity…");
-                MaildirContextBuilder::new(config.clone(), Arc::new(maildir_config))
-                    .check()
-                    .await
-            }
-        }?;
-
-        println!("Account {name} is well configured!");
+        let mut printer = StdoutPrinter::new(self.output);
+
+        printer.log(format!("Checking account {name}…\n"))?;
+        let report = DoctorReport::new(&name, config).await;
+        let failures = report.count(CheckStatus::Fail);
+        printer.out(report)?;
+
+        if failures > 0 {
+            bail!("{failures} check(s) failed for account {name}");
+        }
 
         Ok(())
     }
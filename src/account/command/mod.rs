@@ -2,9 +2,11 @@
 //!
 //! This module gathers CLI commands dedicated to accounts:
 //! [`check_up`] to check up the validity of a given account,
-//! [`configure`] to configure secrets of a given account, and
+//! [`configure`] to configure secrets of a given account,
+//! [`test_hook`] to execute hooks of a given account once, and
 //! [`watch`] to synchronize two backends of a given account.
 
 pub mod configure;
 pub mod doctor;
+pub mod test_hook;
 pub mod watch;
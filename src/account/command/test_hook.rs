@@ -0,0 +1,332 @@
+//! # Test hook command
+//!
+//! This module contains the [`clap`] command for executing hooks of
+//! a given account once, without watching.
+
+use std::sync::Arc;
+
+use chrono::Local;
+use clap::Parser;
+use color_eyre::{eyre::OptionExt, Result};
+#[cfg(any(feature = "imap", feature = "maildir"))]
+use email::backend::context::BackendContextBuilder;
+#[cfg(feature = "imap")]
+use email::imap::ImapContextBuilder;
+use email::{account::config::AccountConfig, envelope::Envelope, flag::Flags, message::Message};
+#[cfg(feature = "maildir")]
+use email::{envelope::Envelopes, maildir::MaildirContextBuilder};
+#[cfg(feature = "imap")]
+use imap_client::imap_next::imap_types::sequence::SequenceSet;
+use pimalaya_tui::terminal::config::TomlConfig as _;
+use tracing::instrument;
+#[cfg(feature = "imap")]
+use utf7_imap::encode_utf7_imap as encode_utf7;
+
+use crate::{
+    account::arg::name::OptionalAccountNameArg,
+    backend::config::BackendConfig,
+    config::TomlConfig,
+    watch::{
+        event::{MessageDetails, WatchEvent, WatchEventKind},
+        handler::WatchHandler,
+        template::TemplateContext,
+    },
+};
+
+/// Execute the hooks of the given account once.
+///
+/// This command helps to check hook templates without waiting for a
+/// real change. Hooks are executed for a fake envelope built from
+/// --subject and --sender, or for the most recent envelope of the
+/// folder if none of them is given. Rendered notifications, commands
+/// and webhooks are shown before being executed.
+#[derive(Debug, Parser)]
+pub struct TestHookCommand {
+    #[command(flatten)]
+    pub account: OptionalAccountNameArg,
+
+    /// The name of the mailbox the change comes from.
+    ///
+    /// If omitted, the first folder of the account configuration is
+    /// used.
+    #[arg(value_name = "FOLDER")]
+    pub folder: Option<String>,
+
+    /// The kind of change to simulate.
+    #[arg(long, short, value_name = "EVENT", default_value = "message-added")]
+    pub event: WatchEventKind,
+
+    /// The subject of the fake envelope.
+    #[arg(long, short, value_name = "SUBJECT", value_parser = header_parser)]
+    pub subject: Option<String>,
+
+    /// The sender of the fake envelope.
+    ///
+    /// Accepts both a bare address and a named address, for example
+    /// "Alice <alice@localhost>".
+    #[arg(long, value_name = "SENDER", value_parser = header_parser)]
+    pub sender: Option<String>,
+}
+
+impl TestHookCommand {
+    pub const DEFAULT_SUBJECT: &'static str = "Test message";
+    pub const DEFAULT_SENDER: &'static str = "Mirador <mirador@localhost>";
+
+    #[instrument(skip_all)]
+    pub async fn execute(self, config: &TomlConfig) -> Result<()> {
+        let (name, config) = config.to_toml_account_config(self.account.name.as_deref())?;
+
+        let folder = match self.folder {
+            Some(folder) => folder,
+            None => config.get_folders().remove(0),
+        };
+
+        let hooks = config.get_watch_hooks();
+        let (backend, account_config) = config.into_account_config(name.clone());
+
+        let event = if self.subject.is_some() || self.sender.is_some() {
+            let subject = self.subject.as_deref().unwrap_or(Self::DEFAULT_SUBJECT);
+            let sender = self.sender.as_deref().unwrap_or(Self::DEFAULT_SENDER);
+            WatchEvent::new(self.event, fake_envelope(subject, sender))
+        } else {
+            println!("Fetching most recent message of folder {folder}…");
+            newest_event(self.event, account_config.clone(), backend, &folder)
+                .await?
+                .ok_or_eyre(format!(
+                    "cannot find any envelope in folder {folder}, use --subject or --sender"
+                ))?
+        };
+
+        let handler = WatchHandler::new(account_config.clone(), &folder, hooks.clone());
+        let hooks = hooks.matching(&folder, &event);
+
+        if hooks.is_empty() {
+            println!("No hook matches {} events of account {name}.", self.event);
+            return Ok(());
+        }
+
+        let from = &event.envelope.from;
+        let sender = match &from.name {
+            Some(name) => format!("{name} <{}>", from.addr),
+            None => from.addr.clone(),
+        };
+
+        println!("Envelope: {} (from {sender})", event.envelope.subject);
+
+        let events = [&event];
+        let ctx = TemplateContext::new(&name, &folder, &events);
+
+        for (i, hook) in hooks.into_iter().enumerate() {
+            let rendered = hook.render(&ctx);
+
+            println!();
+            println!("Hook #{}:", i + 1);
+
+            if let Some(notify) = &rendered.notify {
+                println!("  notification summary: {}", notify.summary);
+                println!("  notification body: {}", notify.body);
+            }
+
+            if let Some(cmd) = &rendered.cmd {
+                println!("  command: {cmd}");
+            }
+
+            if let Some(webhook) = &rendered.webhook {
+                let method = webhook.method()?;
+                println!("  webhook: {method} {}", webhook.url);
+
+                if let Some(body) = &webhook.body {
+                    println!("  webhook body: {body}");
+                }
+            }
+
+            println!("Executing hook #{}…", i + 1);
+
+            match handler.exec_hook(hook, &events).await {
+                Ok(()) => println!("Hook #{} succeeded.", i + 1),
+                Err(err) => println!("Hook #{} failed: {err}", i + 1),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a header value of the fake envelope.
+///
+/// Control characters are rejected, since line breaks would inject
+/// headers into the fake message.
+fn header_parser(value: &str) -> Result<String, String> {
+    match value.chars().find(|c| c.is_control()) {
+        Some(c) => Err(format!("control character {c:?} is not allowed")),
+        None => Ok(value.to_owned()),
+    }
+}
+
+/// Build a fake envelope from the given subject and sender.
+fn fake_envelope(subject: &str, sender: &str) -> Envelope {
+    let date = Local::now();
+    let msg = format!(
+        "Message-ID: <{}@mirador>\r\nFrom: {sender}\r\nSubject: {subject}\r\nDate: {}\r\n\r\n",
+        date.timestamp(),
+        date.to_rfc2822(),
+    );
+
+    let msg = Message::from(msg.into_bytes());
+    Envelope::from_msg("test", Flags::default(), msg)
+}
+
+/// Build an event from the most recent message of the given folder.
+///
+/// Message details are loaded, so that all placeholders can be
+/// checked.
+async fn newest_event(
+    kind: WatchEventKind,
+    #[cfg_attr(
+        not(any(feature = "imap", feature = "maildir")),
+        allow(unused_variables)
+    )]
+    account_config: Arc<AccountConfig>,
+    backend: BackendConfig,
+    folder: &str,
+) -> Result<Option<WatchEvent>> {
+    let (envelope, details) = match backend {
+        #[cfg(feature = "imap")]
+        BackendConfig::Imap(imap_config) => {
+            let ctx = ImapContextBuilder::new(account_config.clone(), Arc::new(imap_config))
+                .with_pool_size(1)
+                .build()
+                .await?;
+            let mut client = ctx.client().await;
+            let folder = encode_utf7(account_config.get_folder_alias(folder));
+            let data = client.examine_mailbox(folder).await?;
+
+            if data.exists.unwrap_or_default() == 0 {
+                return Ok(None);
+            }
+
+            let envelopes = client.fetch_all_envelopes().await?;
+            let Some(envelope) = envelopes.into_iter().max_by_key(|e| e.date) else {
+                return Ok(None);
+            };
+
+            let uid = SequenceSet::try_from(envelope.id.as_str())?;
+            let msgs = client.peek_messages(uid).await?;
+            let details = msgs.first().map(MessageDetails::from_msg).transpose()?;
+            (envelope, details)
+        }
+        #[cfg(feature = "maildir")]
+        BackendConfig::Maildir(maildir_config) => {
+            let ctx = MaildirContextBuilder::new(account_config, Arc::new(maildir_config))
+                .build()
+                .await?;
+            let session = ctx.lock().await;
+            let mdir = session.get_maildir_from_folder_alias(folder)?;
+            let entries = mdir.read()?;
+            let envelopes = Envelopes::from_mdir_entries(entries, None);
+            let Some(envelope) = envelopes.into_iter().max_by_key(|e| e.date) else {
+                return Ok(None);
+            };
+
+            let details = match mdir.find(&envelope.id)? {
+                Some(entry) => Some(MessageDetails::from_msg(&Message::from(entry.read()?))?),
+                None => None,
+            };
+
+            (envelope, details)
+        }
+        #[cfg(feature = "notmuch")]
+        BackendConfig::Notmuch(notmuch_config) => {
+            use crate::watch::notmuch;
+
+            let db = notmuch::open_db(&notmuch_config)?;
+            let Some(envelope) = notmuch::find_newest(&db, &notmuch::to_query(folder))? else {
+                return Ok(None);
+            };
+
+            let raw = notmuch::read_msg(&db, &envelope.id)?;
+            let details = MessageDetails::from_msg(&Message::from(raw))?;
+            (envelope, Some(details))
+        }
+        #[cfg(feature = "jmap")]
+        BackendConfig::Jmap(jmap_config) => {
+            use crate::jmap::client::JmapClientBuilder;
+
+            let client = JmapClientBuilder::new(Arc::new(jmap_config))
+                .build()
+                .await?;
+            let mailbox = client.find_mailbox(folder).await?;
+            let Some(envelope) = client.find_newest(&mailbox.id).await? else {
+                return Ok(None);
+            };
+
+            let raw = client.read_msg(&envelope.id).await?;
+            let details = MessageDetails::from_msg(&Message::from(raw))?;
+            (envelope, Some(details))
+        }
+        #[cfg(feature = "pop3")]
+        BackendConfig::Pop3(pop3_config) => {
+            use color_eyre::eyre::bail;
+
+            use crate::{pop3::client::Pop3ClientBuilder, watch::pop3::INBOX};
+
+            if !folder.eq_ignore_ascii_case(INBOX) {
+                bail!("POP3 mailboxes only have an {INBOX} folder");
+            }
+
+            let mut client = Pop3ClientBuilder::new(Arc::new(pop3_config))
+                .build()
+                .await?;
+
+            // message numbers follow the arrival order
+            let Some((num, uid)) = client.list_uids().await?.into_iter().max() else {
+                client.quit().await;
+                return Ok(None);
+            };
+
+            let raw = client.read_msg(num).await?;
+            client.quit().await;
+
+            let envelope = Envelope::from_msg(uid, Flags::default(), Message::from(raw.as_slice()));
+            let details = MessageDetails::from_msg(&Message::from(raw))?;
+            (envelope, Some(details))
+        }
+    };
+
+    let mut event = WatchEvent::new(kind, envelope);
+    event.details = details;
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> clap::error::Result<TestHookCommand> {
+        let args = ["test-hook"].iter().chain(args);
+        TestHookCommand::try_parse_from(args)
+    }
+
+    #[test]
+    fn header_args_reject_control_characters() {
+        let cmd = command(&["--subject", "Hello", "--sender", "Alice <alice@localhost>"]).unwrap();
+        assert_eq!(cmd.subject.as_deref(), Some("Hello"));
+        assert_eq!(cmd.sender.as_deref(), Some("Alice <alice@localhost>"));
+
+        let err = command(&["--subject", "Hello\r\nBcc: eve@localhost"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("control character '\\r' is not allowed"));
+
+        assert!(command(&["--sender", "alice@localhost\n"]).is_err());
+        assert!(command(&["--subject", "tab\there"]).is_err());
+    }
+
+    #[test]
+    fn fake_envelope_headers() {
+        let envelope = fake_envelope("Hello", "Alice <alice@localhost>");
+        assert_eq!(envelope.subject, "Hello");
+        assert_eq!(envelope.from.name.as_deref(), Some("Alice"));
+        assert_eq!(envelope.from.addr, "alice@localhost");
+    }
+}
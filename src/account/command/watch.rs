@@ -1,86 +1,817 @@
 //! # Watch mailbox command
 //!
 //! This module contains the [`clap`] command for watching mailbox
-//! changes of a given account.
+//! changes of one or many accounts.
 
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use async_ctrlc::CtrlC;
 use clap::Parser;
-use color_eyre::{Report, Result};
+use color_eyre::{eyre::bail, Result};
+#[cfg(feature = "maildir")]
 use email::backend::context::BackendContextBuilder;
 #[cfg(feature = "imap")]
-use email::{envelope::watch::imap::WatchImapEnvelopes, imap::ImapContextBuilder};
+use email::imap::ImapContextBuilder;
 #[cfg(feature = "maildir")]
-use email::{envelope::watch::maildir::WatchMaildirEnvelopes, maildir::MaildirContextBuilder};
-use pimalaya_tui::terminal::config::TomlConfig as _;
-use tokio::sync::oneshot;
-use tracing::instrument;
+use email::maildir::MaildirContextBuilder;
+#[cfg(feature = "notmuch")]
+use email::notmuch::config::NotmuchConfig;
+use http::Client;
+use pimalaya_tui::terminal::{
+    cli::printer::{OutputFmt, Printer, StdoutPrinter},
+    config::TomlConfig as _,
+};
+use tokio::{
+    sync::{mpsc, watch, Semaphore},
+    task::JoinSet,
+    time::sleep,
+};
+use tracing::{debug, error, info, instrument, warn};
 
+#[cfg(feature = "maildir")]
+use crate::watch::maildir::WatchMaildirChanges;
+#[cfg(feature = "notmuch")]
+use crate::watch::notmuch::WatchNotmuchChanges;
+#[cfg(feature = "imap")]
+use crate::watch::{
+    config::WatchConfig,
+    imap::{ImapNotifyListener, WatchImapChanges},
+};
 use crate::{
-    account::arg::name::OptionalAccountNameArg, backend::config::BackendConfig, config::TomlConfig,
+    account::{arg::name::OptionalAccountNameArg, config::TomlAccountConfig},
+    backend::config::BackendConfig,
+    config::TomlConfig,
+    service::notify::ServiceNotifier,
+    watch::{
+        config::{ReconnectConfig, WatchHooks},
+        handler::{DebounceWindow, WatchHandler},
+        shutdown_requested,
+        state::WatchStateStore,
+        status::WatchStatus,
+        WatchChanges,
+    },
 };
+#[cfg(feature = "jmap")]
+use crate::{jmap::client::JmapClientBuilder, watch::jmap::WatchJmapChanges};
+#[cfg(feature = "pop3")]
+use crate::{pop3::client::Pop3ClientBuilder, watch::pop3::WatchPop3Changes};
+
+/// The interval between two checks of configuration files, when
+/// `--watch-config` is given.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
-/// Watch changes of the given mailbox.
+/// Watch changes of the given mailboxes.
+///
+/// All the given folders are watched at the same time, within the
+/// same process. Hooks can use the `{folder}` placeholder to know
+/// which folder an event comes from.
+///
+/// Multiple accounts can also be watched at the same time, using
+/// either `--all` or `--account`. In this case, each account watches
+/// its configured folders, and a failing account does not stop the
+/// other ones.
+///
+/// When running as a systemd service, readiness, status and
+/// watchdog pings are sent to the service manager. See `mirador
+/// service generate`.
+///
+/// The configuration is reloaded on SIGHUP. Hooks and rules changes
+/// apply without interrupting watch sessions, while other changes
+/// restart the sessions of the concerned account. Accounts removed
+/// from the configuration stop being watched, and accounts added to
+/// it start being watched when using `--all`. Changing
+/// `max-concurrent-hooks` requires a restart.
 #[derive(Debug, Parser)]
 pub struct WatchCommand {
     #[command(flatten)]
     pub account: OptionalAccountNameArg,
 
-    #[arg(default_value = "INBOX")]
-    pub folder: String,
+    /// Watch all the configured accounts.
+    #[arg(long, conflicts_with_all = ["account_name", "accounts"])]
+    pub all: bool,
+
+    /// Watch the given account, alongside other ones.
+    ///
+    /// This argument can be repeated in order to watch a subset of
+    /// the configured accounts.
+    #[arg(long = "account", value_name = "ACCOUNT")]
+    #[arg(conflicts_with = "account_name")]
+    pub accounts: Vec<String>,
+
+    /// The names of the mailboxes to watch changes for.
+    ///
+    /// If omitted, the folders defined in the account configuration
+    /// are used. If the account configuration does not define any
+    /// folder, INBOX is used.
+    #[arg(value_name = "FOLDER", conflicts_with_all = ["all", "accounts"])]
+    pub folders: Vec<String>,
+
+    /// The output format of watch events.
+    ///
+    /// Using the JSON format, every event is printed to the standard
+    /// output as a JSON object, one per line. Other messages are not
+    /// printed.
+    #[arg(long, short, value_name = "FORMAT", default_value = "plain")]
+    pub output: OutputFmt,
+
+    /// Reload the configuration when one of its files changes.
+    ///
+    /// The configuration is also reloaded when the process receives
+    /// the SIGHUP signal, with or without this flag.
+    #[arg(long)]
+    pub watch_config: bool,
 }
 
 impl WatchCommand {
     #[instrument(skip_all)]
-    pub async fn execute(self, config: &TomlConfig) -> Result<()> {
-        let (request_shutdown, wait_for_shutdown_request) = oneshot::channel();
-        let (shutdown, wait_for_shutdown) = oneshot::channel();
-
-        let watch = async {
-            let (name, config) = config.to_toml_account_config(self.account.name.as_deref())?;
-            let (backend, config) = config.into_account_config(name.clone());
-
-            let feature = match backend {
-                #[cfg(feature = "imap")]
-                BackendConfig::Imap(imap_config) => {
-                    let ctx = ImapContextBuilder::new(config.clone(), Arc::new(imap_config))
-                        .with_prebuilt_credentials()
-                        .await?
-                        .build()
-                        .await?;
-
-                    WatchImapEnvelopes::new_boxed(&ctx)
+    pub async fn execute(self, config: &TomlConfig, config_paths: &[PathBuf]) -> Result<()> {
+        let multi = self.all || !self.accounts.is_empty();
+
+        let accounts = if self.all {
+            let mut accounts: Vec<_> = config
+                .accounts
+                .iter()
+                .map(|(name, config)| (name.clone(), config.clone()))
+                .collect();
+
+            if accounts.is_empty() {
+                bail!("cannot find any account to watch");
+            }
+
+            accounts.sort_by(|(a, _), (b, _)| a.cmp(b));
+            accounts
+        } else if multi {
+            self.accounts
+                .iter()
+                .map(|name| config.to_toml_account_config(Some(name)))
+                .collect::<Result<_, _>>()?
+        } else {
+            vec![config.to_toml_account_config(self.account.name.as_deref())?]
+        };
+
+        let mut printer = StdoutPrinter::new(self.output.clone());
+        let status = WatchStatus::default();
+        let notifier = ServiceNotifier::from_env()?.map(Arc::new);
+
+        if let Some(notifier) = notifier.clone() {
+            notifier.spawn(&status);
+        }
+
+        let max_concurrent_hooks = config.max_concurrent_hooks();
+        let shared = SharedState {
+            output: self.output.clone(),
+            hooks_limit: Arc::new(Semaphore::new(max_concurrent_hooks)),
+            status: status.clone(),
+            http: Client::new(),
+        };
+        let mut watchers = JoinSet::new();
+        let mut running = HashMap::new();
+
+        for (name, config) in accounts {
+            let account = self.spawn_account(&mut watchers, &name, config, &shared);
+            printer.log(format!(
+                "Watching folder(s) {} of account {name}…\n",
+                account.folders.join(", ")
+            ))?;
+            running.insert(name, account);
+        }
+
+        printer.log("Press CTRL+C to exit…\n")?;
+
+        let paths = match config_paths {
+            [] => TomlConfig::first_valid_default_path().into_iter().collect(),
+            paths => paths.to_vec(),
+        };
+
+        let mut wait_for_reload_request = spawn_reload_listeners(paths.clone(), self.watch_config)?;
+        let interrupt = wait_for_interruption();
+        tokio::pin!(interrupt);
+        let mut interrupted = false;
+        let mut restarting = HashSet::new();
+        let mut stopping = HashSet::new();
+        let mut failures = 0;
+
+        loop {
+            tokio::select! {
+                res = &mut interrupt, if !interrupted => {
+                    res?;
+                    printer.log("Received interruption signal, stop watching…\n")?;
+
+                    if let Some(notifier) = notifier.as_ref() {
+                        notifier.notify_or_warn("STOPPING=1");
+                    }
+
+                    for account in running.values() {
+                        account.request_shutdown.send_replace(true);
+                    }
+
+                    interrupted = true;
                 }
-                #[cfg(feature = "maildir")]
-                BackendConfig::Maildir(maildir_config) => {
-                    let ctx = MaildirContextBuilder::new(config.clone(), Arc::new(maildir_config))
-                        .build()
-                        .await?;
+                Some(reason) = wait_for_reload_request.recv(), if !interrupted => {
+                    info!("{reason}, reloading configuration…");
 
-                    WatchMaildirEnvelopes::new_boxed(&ctx)
+                    let config = match TomlConfig::from_paths(&paths) {
+                        Ok(config) => config,
+                        Err(err) => {
+                            warn!("cannot reload configuration, keeping the current one: {err}");
+                            debug!("{err:?}");
+                            continue;
+                        }
+                    };
+
+                    if config.max_concurrent_hooks() != max_concurrent_hooks {
+                        warn!(max_concurrent_hooks, "max-concurrent-hooks cannot be changed without restarting, keeping the current one");
+                    }
+
+                    for (name, account) in running.iter_mut() {
+                        let Some(next_config) = config.accounts.get(name) else {
+                            if stopping.insert(name.clone()) {
+                                info!(account = name, "account removed from configuration, stop watching");
+                                restarting.remove(name);
+                                account.request_shutdown.send_replace(true);
+                            }
+                            continue;
+                        };
+
+                        // the account was added back before its
+                        // watch session stopped
+                        if stopping.remove(name) {
+                            info!(account = name, "account added back to configuration, restarting watch session");
+                            account.config = next_config.clone();
+                            restarting.insert(name.clone());
+                            continue;
+                        }
+
+                        match account.reload(next_config) {
+                            AccountReload::Unchanged => {
+                                debug!(account = name, "account configuration unchanged");
+                            }
+                            AccountReload::HooksUpdated => {
+                                info!(account = name, "hooks reloaded");
+                            }
+                            AccountReload::RestartRequested => {
+                                info!(account = name, "configuration changed, restarting watch session");
+                                restarting.insert(name.clone());
+                            }
+                        }
+                    }
+
+                    if !self.all {
+                        continue;
+                    }
+
+                    let mut added: Vec<_> = config
+                        .accounts
+                        .iter()
+                        .filter(|(name, _)| !running.contains_key(*name))
+                        .map(|(name, config)| (name.clone(), config.clone()))
+                        .collect();
+                    added.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+                    for (name, config) in added {
+                        info!(account = name, "account added to configuration, start watching");
+                        let account = self.spawn_account(&mut watchers, &name, config, &shared);
+                        printer.log(format!(
+                            "Watching folder(s) {} of account {name}…\n",
+                            account.folders.join(", ")
+                        ))?;
+                        running.insert(name, account);
+                    }
                 }
-            };
+                res = watchers.join_next() => {
+                    let Some(res) = res else {
+                        break;
+                    };
 
-            feature
-                .watch_envelopes(self.folder.as_str(), wait_for_shutdown_request, shutdown)
-                .await?;
+                    let (name, res) = res?;
+
+                    if stopping.remove(&name) {
+                        if let Err(err) = res {
+                            debug!(account = name, "watch session stopped: {err:?}");
+                        }
 
-            Result::<(), Report>::Ok(())
+                        running.remove(&name);
+                        printer.log(format!("Stopped watching account {name}\n"))?;
+                        continue;
+                    }
+
+                    if restarting.remove(&name) && !interrupted {
+                        if let Err(err) = res {
+                            debug!(account = name, "watch session stopped: {err:?}");
+                        }
+
+                        let config = running[&name].config.clone();
+                        let account = self.spawn_account(&mut watchers, &name, config, &shared);
+                        printer.log(format!(
+                            "Watching folder(s) {} of account {name}…\n",
+                            account.folders.join(", ")
+                        ))?;
+                        running.insert(name, account);
+                        continue;
+                    }
+
+                    if let Err(err) = res {
+                        if !multi {
+                            return Err(err);
+                        }
+
+                        error!(account = name, "{err}");
+                        debug!("{err:?}");
+                        failures += 1;
+                    }
+                }
+            }
+        }
+
+        if failures > 0 {
+            bail!("cannot watch {failures} account(s)");
+        }
+
+        Ok(())
+    }
+
+    /// Start watching the given account in the background.
+    fn spawn_account(
+        &self,
+        watchers: &mut JoinSet<(String, Result<()>)>,
+        name: &str,
+        config: TomlAccountConfig,
+        shared: &SharedState,
+    ) -> RunningAccount {
+        let folders = if self.folders.is_empty() {
+            config.get_folders()
+        } else {
+            self.folders.clone()
         };
 
-        let interrupt = async {
-            println!("Watching folder {}, press CTRL+C to exit…", self.folder);
-            CtrlC::new().expect("cannot create Ctrl+C handler").await;
-            println!("Received interruption signal, stop watching…");
-            request_shutdown.send(()).unwrap();
-            wait_for_shutdown.await.unwrap();
-            Result::<(), Report>::Ok(())
+        let (request_shutdown, wait_for_shutdown_request) = watch::channel(false);
+        let (update_hooks, wait_for_hooks_update) =
+            watch::channel(Arc::new(config.get_watch_hooks()));
+
+        let account = RunningAccount {
+            config: config.clone(),
+            folders: folders.clone(),
+            request_shutdown,
+            update_hooks,
         };
 
+        let name = name.to_owned();
+        let shared = shared.clone();
+
+        // folders are registered before being watched, so that the
+        // service manager is not told that the process is ready
+        // before all the folders are being watched
+        let status: Vec<_> = folders
+            .iter()
+            .map(|folder| shared.status.register(&name, folder))
+            .collect();
+
+        watchers.spawn(async move {
+            let res = watch_account(
+                &name,
+                config,
+                folders,
+                shared,
+                wait_for_hooks_update,
+                wait_for_shutdown_request,
+            )
+            .await;
+            drop(status);
+            (name, res)
+        });
+
+        account
+    }
+}
+
+/// The state shared by all the watched accounts.
+#[derive(Clone)]
+struct SharedState {
+    /// The output format of watch events.
+    output: OutputFmt,
+
+    /// Limits the number of hooks executed at the same time.
+    hooks_limit: Arc<Semaphore>,
+
+    /// The status of all the watched folders.
+    status: WatchStatus,
+
+    /// The HTTP client used by webhooks.
+    http: Client,
+}
+
+/// An account being watched.
+struct RunningAccount {
+    /// The current configuration of the account.
+    config: TomlAccountConfig,
+
+    /// The watched folders.
+    folders: Vec<String>,
+
+    /// Stops all the watchers of the account.
+    request_shutdown: watch::Sender<bool>,
+
+    /// Sends updated hooks to all the watchers of the account.
+    update_hooks: watch::Sender<Arc<WatchHooks>>,
+}
+
+/// The outcome of an account configuration reload.
+enum AccountReload {
+    Unchanged,
+    HooksUpdated,
+    RestartRequested,
+}
+
+impl RunningAccount {
+    /// Apply the given configuration.
+    ///
+    /// Hooks and rules are applied to running watchers. Other
+    /// changes (backend, folders, state…) require to restart the
+    /// watch session, which is requested here.
+    fn reload(&mut self, config: &TomlAccountConfig) -> AccountReload {
+        if *config == self.config {
+            return AccountReload::Unchanged;
+        }
+
+        let restart = config.without_hooks() != self.config.without_hooks();
+        self.config = config.clone();
+
+        if restart {
+            self.request_shutdown.send_replace(true);
+            AccountReload::RestartRequested
+        } else {
+            let hooks = self.config.get_watch_hooks();
+            self.update_hooks.send_replace(Arc::new(hooks));
+            AccountReload::HooksUpdated
+        }
+    }
+}
+
+/// Wait for an interruption signal.
+///
+/// Signals are handled by Tokio rather than by a dedicated signal
+/// handler, so that SIGHUP remains available for reloading the
+/// configuration.
+async fn wait_for_interruption() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+
         tokio::select! {
-            res = interrupt => res,
-            res = watch => res,
+            res = tokio::signal::ctrl_c() => res?,
+            _ = sigterm.recv() => (),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// Spawn the tasks requesting configuration reloads.
+///
+/// On Unix systems, a reload is requested when the process receives
+/// `SIGHUP`. When `on_change` is true, a reload is also requested
+/// when one of the given configuration files changes.
+fn spawn_reload_listeners(
+    paths: Vec<PathBuf>,
+    on_change: bool,
+) -> Result<mpsc::UnboundedReceiver<&'static str>> {
+    let (request_reload, wait_for_reload_request) = mpsc::unbounded_channel();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sighup = signal(SignalKind::hangup())?;
+        let request_reload = request_reload.clone();
+
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                if request_reload.send("received SIGHUP").is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    if on_change {
+        // files are polled rather than watched, since editors often
+        // replace files instead of writing them, which breaks file
+        // system watchers
+        let modified = move || -> Vec<Option<SystemTime>> {
+            let modified = |path: &PathBuf| path.metadata().and_then(|m| m.modified()).ok();
+            paths.iter().map(modified).collect()
+        };
+
+        tokio::spawn(async move {
+            let mut last_modified = modified();
+
+            loop {
+                sleep(CONFIG_POLL_INTERVAL).await;
+                let next_modified = modified();
+
+                if next_modified == last_modified {
+                    continue;
+                }
+
+                last_modified = next_modified;
+
+                if request_reload.send("configuration file changed").is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok(wait_for_reload_request)
+}
+
+/// Watch the given folders of the given account.
+///
+/// Each folder is watched by its own backend watcher, except that
+/// IMAP watchers share the same NOTIFY listener when the server
+/// supports it. The function returns as soon as one of the folder
+/// watchers fails, or when all of them gracefully stopped after a
+/// shutdown request.
+async fn watch_account(
+    name: &str,
+    config: TomlAccountConfig,
+    folders: Vec<String>,
+    shared: SharedState,
+    wait_for_hooks_update: watch::Receiver<Arc<WatchHooks>>,
+    wait_for_shutdown_request: watch::Receiver<bool>,
+) -> Result<()> {
+    // credentials are built once, then shared by all folder
+    // watchers
+    #[cfg(feature = "imap")]
+    let imap_ctx_builder = match &config.backend {
+        BackendConfig::Imap(imap_config) => {
+            let (_, account_config) = config.clone().into_account_config(name.to_owned());
+            let imap_config = Arc::new(imap_config.clone());
+            let ctx_builder = ImapContextBuilder::new(account_config, imap_config)
+                .with_prebuilt_credentials()
+                .await?;
+            Some(ctx_builder)
+        }
+        #[allow(unreachable_patterns)]
+        _ => None,
+    };
+
+    #[cfg(feature = "imap")]
+    let imap_notify_listener = imap_ctx_builder
+        .clone()
+        .map(|ctx_builder| ImapNotifyListener::new(ctx_builder, &folders));
+
+    #[cfg(feature = "jmap")]
+    let jmap_client_builder = match &config.backend {
+        BackendConfig::Jmap(jmap_config) => {
+            let jmap_config = Arc::new(jmap_config.clone());
+            let client_builder = JmapClientBuilder::new(jmap_config)
+                .with_prebuilt_credentials()
+                .await?;
+            Some(client_builder)
+        }
+        #[allow(unreachable_patterns)]
+        _ => None,
+    };
+
+    #[cfg(feature = "pop3")]
+    let pop3_client_builder = match &config.backend {
+        BackendConfig::Pop3(pop3_config) => {
+            let pop3_config = Arc::new(pop3_config.clone());
+            let client_builder = Pop3ClientBuilder::new(pop3_config)
+                .with_prebuilt_credentials()
+                .await?;
+            Some(client_builder)
+        }
+        #[allow(unreachable_patterns)]
+        _ => None,
+    };
+
+    let mut watchers = JoinSet::new();
+
+    // the debounce window is shared by all the folder watchers, so
+    // that a burst of changes spread over multiple folders executes
+    // hooks only once
+    let window = DebounceWindow::default();
+    let mut debounced_handler = None;
+    let mut handlers = Vec::new();
+
+    for folder in folders {
+        let hooks = config.get_watch_hooks();
+        let (_, account_config) = config.clone().into_account_config(name.to_owned());
+        let mut handler = WatchHandler::new(account_config.clone(), &folder, hooks)
+            .with_hooks_updates(wait_for_hooks_update.clone())
+            .with_output(shared.output.clone())
+            .with_hooks_limit(shared.hooks_limit.clone())
+            .with_http_client(shared.http.clone())
+            .with_status(shared.status.clone());
+
+        if let Some(state_config) = config.state.as_ref() {
+            let Some(dir) = state_config.dir() else {
+                bail!("cannot find state directory, please define it using state.dir");
+            };
+
+            let store = WatchStateStore::new(dir, name, &folder)?;
+            handler = handler.with_state_store(store, state_config.max_missed());
+        }
+
+        if let Some(debounce) = config.debounce.clone() {
+            handler = handler.with_debounce(debounce, window.clone());
+            debounced_handler = Some(handler.clone());
+        }
+
+        handlers.push(handler.clone());
+
+        let builder = match &config.backend {
+            #[cfg(feature = "imap")]
+            BackendConfig::Imap(_) => {
+                let mut ctx_builder = imap_ctx_builder.clone().unwrap();
+                ctx_builder.account_config = account_config;
+                let listener = imap_notify_listener.clone().unwrap();
+                let watch_config = config.watch.clone().unwrap_or_default();
+                WatcherBuilder::Imap(ctx_builder, listener, watch_config)
+            }
+            #[cfg(feature = "maildir")]
+            BackendConfig::Maildir(maildir_config) => {
+                let maildir_config = Arc::new(maildir_config.clone());
+                WatcherBuilder::Maildir(MaildirContextBuilder::new(account_config, maildir_config))
+            }
+            #[cfg(feature = "notmuch")]
+            BackendConfig::Notmuch(notmuch_config) => {
+                WatcherBuilder::Notmuch(Arc::new(notmuch_config.clone()))
+            }
+            #[cfg(feature = "jmap")]
+            BackendConfig::Jmap(_) => WatcherBuilder::Jmap(jmap_client_builder.clone().unwrap()),
+            #[cfg(feature = "pop3")]
+            BackendConfig::Pop3(pop3_config) => WatcherBuilder::Pop3(
+                pop3_client_builder.clone().unwrap(),
+                pop3_config.poll_interval(),
+            ),
+        };
+
+        let reconnect = config.reconnect.clone();
+        let wait_for_shutdown_request = wait_for_shutdown_request.clone();
+
+        watchers.spawn(async move {
+            watch_folder(
+                builder,
+                handler,
+                &folder,
+                reconnect,
+                wait_for_shutdown_request,
+            )
+            .await
+            .map_err(|err| err.wrap_err(format!("cannot watch folder {folder}")))
+        });
+    }
+
+    let res = async {
+        while let Some(res) = watchers.join_next().await {
+            res??;
         }
+
+        Ok(())
+    }
+    .await;
+
+    // events of the current debounce window would be lost otherwise
+    if let Some(handler) = debounced_handler {
+        handler.flush().await;
+    }
+
+    // hooks are executed in the background, they are given a chance
+    // to complete before exiting
+    let mut hooks = JoinSet::new();
+
+    for handler in handlers {
+        hooks.spawn(async move { handler.wait_for_hooks().await });
+    }
+
+    while hooks.join_next().await.is_some() {}
+
+    res
+}
+
+/// The backend watcher builder.
+///
+/// Wraps backend context builders, so that a new backend watcher
+/// can be built for every (re)connection attempt.
+#[derive(Clone)]
+enum WatcherBuilder {
+    #[cfg(feature = "imap")]
+    Imap(ImapContextBuilder, ImapNotifyListener, WatchConfig),
+    #[cfg(feature = "maildir")]
+    Maildir(MaildirContextBuilder),
+    #[cfg(feature = "notmuch")]
+    Notmuch(Arc<NotmuchConfig>),
+    #[cfg(feature = "jmap")]
+    Jmap(JmapClientBuilder),
+    #[cfg(feature = "pop3")]
+    Pop3(Pop3ClientBuilder, Duration),
+}
+
+impl WatcherBuilder {
+    async fn build(&self, handler: WatchHandler) -> Result<Box<dyn WatchChanges>> {
+        match self.clone() {
+            #[cfg(feature = "imap")]
+            Self::Imap(ctx_builder, listener, watch_config) => Ok(WatchImapChanges::new_boxed(
+                ctx_builder,
+                listener,
+                handler,
+                watch_config,
+            )),
+            #[cfg(feature = "maildir")]
+            Self::Maildir(ctx_builder) => {
+                let ctx = ctx_builder.build().await?;
+                Ok(WatchMaildirChanges::new_boxed(&ctx, handler))
+            }
+            #[cfg(feature = "notmuch")]
+            Self::Notmuch(config) => Ok(WatchNotmuchChanges::new_boxed(config, handler)),
+            #[cfg(feature = "jmap")]
+            Self::Jmap(client_builder) => {
+                let client = client_builder.build().await?;
+                Ok(WatchJmapChanges::new_boxed(client, handler))
+            }
+            #[cfg(feature = "pop3")]
+            Self::Pop3(client_builder, poll_interval) => Ok(WatchPop3Changes::new_boxed(
+                client_builder,
+                poll_interval,
+                handler,
+            )),
+        }
+    }
+}
+
+/// Watch the given folder, reconnecting on failure.
+///
+/// Without reconnection configuration, the first failure is
+/// returned. Otherwise, a new watch session is started after a
+/// backoff delay, until the maximum number of attempts is reached.
+async fn watch_folder(
+    builder: WatcherBuilder,
+    handler: WatchHandler,
+    folder: &str,
+    reconnect: Option<ReconnectConfig>,
+    mut wait_for_shutdown_request: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut attempt = 0;
+
+    loop {
+        let started_at = Instant::now();
+
+        let res = async {
+            let watcher = builder.build(handler.clone()).await?;
+
+            if *wait_for_shutdown_request.borrow() {
+                return Ok(());
+            }
+
+            watcher
+                .watch_changes(folder, wait_for_shutdown_request.clone())
+                .await
+        };
+
+        let err = match res.await {
+            Ok(()) => break Ok(()),
+            Err(err) => err,
+        };
+
+        handler.set_ready(false);
+
+        let Some(reconnect) = &reconnect else {
+            break Err(err);
+        };
+
+        attempt = reconnect.next_attempt(attempt, started_at.elapsed());
+
+        if reconnect.is_exhausted(attempt) {
+            let attempts = attempt - 1;
+            break Err(err.wrap_err(format!("cannot reconnect after {attempts} attempt(s)")));
+        }
+
+        let delay = reconnect.delay(attempt);
+        warn!(
+            folder,
+            attempt,
+            ?delay,
+            "watch session failed, reconnecting: {err}"
+        );
+        debug!("{err:?}");
+
+        tokio::select! {
+            _ = sleep(delay) => (),
+            _ = shutdown_requested(&mut wait_for_shutdown_request) => break Ok(()),
+        };
+
+        info!(folder, attempt, "reconnecting watch session…");
     }
 }
@@ -5,10 +5,20 @@
 use std::sync::Arc;
 
 use color_eyre::eyre::Result;
-use email::watch::config::WatchHook;
 use serde::{Deserialize, Serialize};
 
-use crate::backend::config::BackendConfig;
+use crate::{
+    backend::config::BackendConfig,
+    watch::{
+        config::{
+            DebounceConfig, ReconnectConfig, StateConfig, WatchConfig, WatchHooks, WatchRuleConfig,
+        },
+        hook::WatchHookConfig,
+    },
+};
+
+/// The default folder being watched when none is given.
+pub const DEFAULT_FOLDER: &str = "INBOX";
 
 /// The account configuration.
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -22,19 +32,81 @@ pub struct TomlAccountConfig {
 
     /// The name of the mailbox to watch changes for.
     ///
-    /// One account configuration allows to watch only one folder. If
-    /// you need to watch multiple folder, then create multiple
-    /// accounts (one per folder).
+    /// This is a shortcut for a [`TomlAccountConfig::folders`] list
+    /// containing only one folder. Both options can be used
+    /// together.
     pub folder: Option<String>,
 
+    /// The names of the mailboxes to watch changes for.
+    ///
+    /// All folders are watched at the same time, within the same
+    /// process.
+    pub folders: Option<Vec<String>>,
+
     /// The backend configuration.
     pub backend: BackendConfig,
 
+    /// The reconnection configuration.
+    ///
+    /// When omitted, a failing watch session makes the watch command
+    /// exit.
+    pub reconnect: Option<ReconnectConfig>,
+
+    /// The watch state configuration.
+    ///
+    /// When omitted, messages that arrived while Mirador was not
+    /// running are not reported.
+    pub state: Option<StateConfig>,
+
+    /// The debounce configuration.
+    ///
+    /// When omitted, hooks are executed as soon as a change is
+    /// detected.
+    pub debounce: Option<DebounceConfig>,
+
+    /// The watch configuration.
+    ///
+    /// Only applies to IMAP accounts. When omitted, IDLE is used if
+    /// the server supports it, otherwise the folders are polled.
+    pub watch: Option<WatchConfig>,
+
     /// The message added watch hook.
     ///
-    /// Hook to execute when a new message arrives in the configured
-    /// mailbox.
-    pub on_message_added: Option<WatchHook>,
+    /// Hook to execute when a new message arrives in one of the
+    /// configured mailboxes.
+    pub on_message_added: Option<WatchHookConfig>,
+
+    /// The message removed watch hook.
+    ///
+    /// Hook to execute when a message disappears from one of the
+    /// configured mailboxes (deleted, expunged or moved away).
+    pub on_message_removed: Option<WatchHookConfig>,
+
+    /// The flags changed watch hook.
+    ///
+    /// Hook to execute when flags of a message change in one of the
+    /// configured mailboxes (seen, flagged…).
+    pub on_flags_changed: Option<WatchHookConfig>,
+
+    /// The any change watch hook.
+    ///
+    /// Hook to execute on any of the changes above, in addition to
+    /// their dedicated hook.
+    pub on_any_change: Option<WatchHookConfig>,
+
+    /// The hook failed watch hook.
+    ///
+    /// Hook to execute when one of the hooks above fails or times
+    /// out, so that a broken hook does not fail silently. The error
+    /// is available through the `{error}` placeholder.
+    pub on_hook_failed: Option<WatchHookConfig>,
+
+    /// The watch rules.
+    ///
+    /// Rules allow to execute different hooks depending on the
+    /// envelope sender, recipient or subject. They are evaluated in
+    /// order, before the hooks above.
+    pub rules: Option<Vec<WatchRuleConfig>>,
 }
 
 impl TomlAccountConfig {
@@ -51,6 +123,59 @@ impl TomlAccountConfig {
         Ok(())
     }
 
+    /// Get the names of the mailboxes to watch changes for.
+    ///
+    /// The list is made of [`TomlAccountConfig::folder`] followed by
+    /// [`TomlAccountConfig::folders`], without duplicates. If both
+    /// are undefined, the list only contains [`DEFAULT_FOLDER`].
+    pub fn get_folders(&self) -> Vec<String> {
+        let mut folders: Vec<String> = Vec::new();
+
+        let configured = self.folder.iter().chain(self.folders.iter().flatten());
+
+        for folder in configured {
+            if !folders.contains(folder) {
+                folders.push(folder.clone())
+            }
+        }
+
+        if folders.is_empty() {
+            folders.push(DEFAULT_FOLDER.to_owned());
+        }
+
+        folders
+    }
+
+    /// Get the watch hooks, one per kind of envelope change.
+    pub fn get_watch_hooks(&self) -> WatchHooks {
+        WatchHooks {
+            on_message_added: self.on_message_added.clone(),
+            on_message_removed: self.on_message_removed.clone(),
+            on_flags_changed: self.on_flags_changed.clone(),
+            on_any_change: self.on_any_change.clone(),
+            rules: self.rules.clone().unwrap_or_default(),
+            on_hook_failed: self.on_hook_failed.clone(),
+        }
+    }
+
+    /// Get a copy of the current configuration without hooks and
+    /// rules.
+    ///
+    /// Used to tell whether a configuration change can be applied
+    /// to running watchers, or requires to restart them.
+    pub fn without_hooks(&self) -> Self {
+        Self {
+            default: None,
+            on_message_added: None,
+            on_message_removed: None,
+            on_flags_changed: None,
+            on_any_change: None,
+            on_hook_failed: None,
+            rules: None,
+            ..self.clone()
+        }
+    }
+
     pub fn into_account_config(
         self,
         name: String,
@@ -59,13 +184,6 @@ impl TomlAccountConfig {
             self.backend,
             Arc::new(email::account::config::AccountConfig {
                 name,
-                envelope: Some(email::envelope::config::EnvelopeConfig {
-                    watch: Some(email::envelope::watch::config::WatchEnvelopeConfig {
-                        received: self.on_message_added,
-                        any: None,
-                    }),
-                    ..Default::default()
-                }),
                 ..Default::default()
             }),
         )
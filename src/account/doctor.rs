@@ -0,0 +1,827 @@
+//! # Account doctor
+//!
+//! Module dedicated to account checks. Checks are executed by the
+//! doctor command, in order to tell whether a watch setup will
+//! actually work: backend integrity, existence of watched folders,
+//! backend capabilities and hooks prerequisites.
+
+use std::{
+    env, fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+#[cfg(any(feature = "imap", feature = "maildir"))]
+use email::account::config::AccountConfig;
+#[cfg(feature = "maildir")]
+use email::backend::context::BackendContextBuilder;
+#[cfg(feature = "imap")]
+use email::imap::{config::ImapConfig, ImapClientBuilder};
+#[cfg(feature = "maildir")]
+use email::maildir::{config::MaildirConfig, MaildirContextBuilder};
+#[cfg(feature = "notmuch")]
+use email::notmuch::config::NotmuchConfig;
+use serde::{Serialize, Serializer};
+use shellexpand_utils::expand;
+#[cfg(feature = "imap")]
+use utf7_imap::encode_utf7_imap as encode_utf7;
+
+#[cfg(feature = "jmap")]
+use crate::jmap::{client::JmapClientBuilder, config::JmapConfig};
+#[cfg(feature = "pop3")]
+use crate::pop3::{client::Pop3ClientBuilder, config::Pop3Config};
+#[cfg(feature = "pop3")]
+use crate::watch::pop3::INBOX;
+use crate::{
+    backend::config::BackendConfig,
+    watch::{config::WatchHooks, hook::WatchCmdConfig},
+};
+#[cfg(feature = "imap")]
+use crate::{
+    imap::notify::notify_supported,
+    watch::config::{WatchConfig, WatchMode},
+};
+
+use super::config::TomlAccountConfig;
+
+/// Shell builtins commonly used in hooks.
+///
+/// Those programs do not need to be found in `PATH`.
+const SHELL_BUILTINS: &[&str] = &[
+    ":", ".", "[", "cd", "command", "echo", "eval", "exec", "export", "false", "printf", "read",
+    "set", "source", "test", "true",
+];
+
+/// Shell keywords, which are interpreted by the shell itself.
+const SHELL_KEYWORDS: &[&str] = &[
+    "!", "[[", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in",
+    "select", "then", "time", "until", "while",
+];
+
+/// The status of a check.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass => write!(f, "pass"),
+            Self::Warn => write!(f, "warn"),
+            Self::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// The outcome of a check that did not fail.
+pub enum CheckOutcome {
+    Pass(String),
+    Warn(String),
+}
+
+/// The result of a check.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Check {
+    /// The name of the check.
+    pub name: String,
+
+    /// The status of the check.
+    pub status: CheckStatus,
+
+    /// A human-readable description of the result.
+    pub message: String,
+
+    /// The error chain, from the outermost error to the root cause,
+    /// in case the check failed.
+    pub error: Option<Vec<String>>,
+
+    /// How long the check took.
+    #[serde(rename = "duration-ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+}
+
+impl Check {
+    /// Run the given check.
+    ///
+    /// A check resolving to an error is considered failed.
+    pub async fn run(
+        name: impl ToString,
+        check: impl Future<Output = Result<CheckOutcome>>,
+    ) -> Self {
+        let started_at = Instant::now();
+        let res = check.await;
+        let duration = started_at.elapsed();
+
+        let (status, message, error) = match res {
+            Ok(CheckOutcome::Pass(message)) => (CheckStatus::Pass, message, None),
+            Ok(CheckOutcome::Warn(message)) => (CheckStatus::Warn, message, None),
+            Err(err) => {
+                let chain: Vec<_> = err.chain().map(ToString::to_string).collect();
+                (CheckStatus::Fail, chain.join(": "), Some(chain))
+            }
+        };
+
+        Self {
+            name: name.to_string(),
+            status,
+            message,
+            error,
+            duration,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = self.status.to_string().to_uppercase();
+        write!(f, "[{status}] {}: {}", self.name, self.message)
+    }
+}
+
+/// The report of all the checks of an account.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DoctorReport {
+    /// The name of the checked account.
+    pub account: String,
+
+    /// The folders to watch.
+    pub folders: Vec<String>,
+
+    /// The worst status of all the checks.
+    pub status: CheckStatus,
+
+    /// The checks, in order of execution.
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    /// Run all the checks of the given account.
+    pub async fn new(name: &str, config: TomlAccountConfig) -> Self {
+        let folders = config.get_folders();
+        let checks = check_account(name, config, &folders).await;
+        let status = checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass);
+
+        Self {
+            account: name.to_owned(),
+            folders,
+            status,
+            checks,
+        }
+    }
+
+    /// Count the checks matching the given status.
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{check}")?;
+        }
+
+        let name = &self.account;
+
+        match self.status {
+            CheckStatus::Fail => return Ok(()),
+            CheckStatus::Warn => {
+                let warnings = self.count(CheckStatus::Warn);
+                writeln!(
+                    f,
+                    "Account {name} is configured, with {warnings} warning(s)."
+                )?;
+            }
+            CheckStatus::Pass => {
+                writeln!(f, "Account {name} is well configured!")?;
+            }
+        }
+
+        writeln!(f, "Folder(s) to watch: {}", self.folders.join(", "))
+    }
+}
+
+/// Run all the checks of the given account.
+pub async fn check_account(
+    name: &str,
+    config: TomlAccountConfig,
+    folders: &[String],
+) -> Vec<Check> {
+    let hooks = config.get_watch_hooks();
+    #[cfg(feature = "imap")]
+    let watch_config = config.watch.clone().unwrap_or_default();
+    #[cfg_attr(
+        not(any(feature = "imap", feature = "maildir")),
+        allow(unused_variables)
+    )]
+    let (backend, account_config) = config.into_account_config(name.to_owned());
+
+    let mut checks = match backend {
+        #[cfg(feature = "imap")]
+        BackendConfig::Imap(imap_config) => {
+            check_imap(account_config, imap_config, watch_config, folders).await
+        }
+        #[cfg(feature = "maildir")]
+        BackendConfig::Maildir(maildir_config) => {
+            check_maildir(account_config, maildir_config, folders).await
+        }
+        #[cfg(feature = "notmuch")]
+        BackendConfig::Notmuch(notmuch_config) => check_notmuch(notmuch_config, folders).await,
+        #[cfg(feature = "jmap")]
+        BackendConfig::Jmap(jmap_config) => check_jmap(jmap_config, folders).await,
+        #[cfg(feature = "pop3")]
+        BackendConfig::Pop3(pop3_config) => check_pop3(pop3_config, folders).await,
+    };
+
+    checks.extend(check_hooks(&hooks).await);
+    checks
+}
+
+/// Check the IMAP connection, the NOTIFY and IDLE extensions and the
+/// existence of the given folders.
+///
+/// Extensions are only required by their own watch mode: in auto
+/// mode, the absence of NOTIFY makes folders being watched with
+/// IDLE, and the absence of IDLE makes folders being polled.
+#[cfg(feature = "imap")]
+async fn check_imap(
+    account_config: Arc<AccountConfig>,
+    imap_config: ImapConfig,
+    watch_config: WatchConfig,
+    folders: &[String],
+) -> Vec<Check> {
+    let imap_config = Arc::new(imap_config);
+    let mut checks = Vec::new();
+    let mut client = None;
+
+    let check = Check::run("IMAP connection", async {
+        let host = &imap_config.host;
+        let port = imap_config.port;
+        let credentials = imap_config.build_credentials().await?;
+        let mut builder = ImapClientBuilder::new(imap_config.clone(), Some(credentials));
+        let mut imap = builder.build().await?;
+        imap.refresh_capabilities().await?;
+        client = Some(imap);
+        Ok(CheckOutcome::Pass(format!("connected to {host}:{port}")))
+    });
+
+    checks.push(check.await);
+
+    // other checks require a working connection
+    let Some(mut client) = client else {
+        return checks;
+    };
+
+    let notify = notify_supported(&client);
+
+    let check = Check::run("IMAP NOTIFY", async {
+        match watch_config.mode() {
+            mode @ (WatchMode::Idle | WatchMode::Poll) => {
+                Ok(CheckOutcome::Pass(format!("not needed, using {mode} mode")))
+            }
+            _ if notify => Ok(CheckOutcome::Pass(format!(
+                "server supports NOTIFY, watching {} folder(s) over one connection",
+                folders.len()
+            ))),
+            WatchMode::Notify => {
+                bail!("server does not advertise the NOTIFY capability, please set watch.mode to auto")
+            }
+            WatchMode::Auto => Ok(CheckOutcome::Pass(String::from(
+                "server does not advertise the NOTIFY capability, watching each folder over its own connection",
+            ))),
+        }
+    });
+
+    checks.push(check.await);
+
+    let check = Check::run("IMAP IDLE", async {
+        let interval = watch_config.poll_interval();
+
+        match watch_config.mode() {
+            WatchMode::Poll => Ok(CheckOutcome::Pass(format!(
+                "not needed, polling every {interval:?}"
+            ))),
+            WatchMode::Notify => Ok(CheckOutcome::Pass(String::from("not needed, using NOTIFY"))),
+            WatchMode::Auto if notify => {
+                Ok(CheckOutcome::Pass(String::from("not needed, using NOTIFY")))
+            }
+            _ if client.state.ext_idle_supported() => {
+                Ok(CheckOutcome::Pass(String::from("server supports IDLE")))
+            }
+            WatchMode::Idle => {
+                bail!("server does not advertise the IDLE capability, please set watch.mode to auto or poll")
+            }
+            WatchMode::Auto => Ok(CheckOutcome::Warn(format!(
+                "server does not advertise the IDLE capability, polling every {interval:?}"
+            ))),
+        }
+    });
+
+    checks.push(check.await);
+
+    for folder in folders {
+        let check = Check::run(format!("folder {folder}"), async {
+            let folder = account_config.get_folder_alias(folder);
+            let data = client
+                .examine(encode_utf7(folder.clone()))
+                .await
+                .wrap_err_with(|| format!("cannot examine IMAP folder {folder}"))?;
+
+            let count = data.exists.unwrap_or_default();
+            Ok(CheckOutcome::Pass(format!(
+                "folder exists ({count} messages)"
+            )))
+        });
+
+        checks.push(check.await);
+    }
+
+    checks
+}
+
+/// Check the Maildir root directory and the existence of the given
+/// folders.
+#[cfg(feature = "maildir")]
+async fn check_maildir(
+    account_config: Arc<AccountConfig>,
+    maildir_config: MaildirConfig,
+    folders: &[String],
+) -> Vec<Check> {
+    let root_dir = maildir_config.root_dir.clone();
+    let ctx_builder = MaildirContextBuilder::new(account_config, Arc::new(maildir_config));
+    let mut checks = Vec::new();
+    let mut ctx = None;
+
+    let check = Check::run("Maildir integrity", async {
+        ctx_builder.clone().check().await?;
+        ctx = Some(ctx_builder.build().await?);
+        Ok(CheckOutcome::Pass(format!(
+            "{} is valid",
+            root_dir.display()
+        )))
+    });
+
+    checks.push(check.await);
+
+    let Some(ctx) = ctx else {
+        return checks;
+    };
+
+    for folder in folders {
+        let check = Check::run(format!("folder {folder}"), async {
+            let session = ctx.lock().await;
+            let mdir = session.get_maildir_from_folder_alias(folder)?;
+            let path = mdir.path();
+
+            for dir in ["cur", "new", "tmp"] {
+                if !path.join(dir).is_dir() {
+                    bail!("cannot find Maildir folder at {}", path.display());
+                }
+            }
+
+            Ok(CheckOutcome::Pass(String::from("folder exists")))
+        });
+
+        checks.push(check.await);
+    }
+
+    checks
+}
+
+/// Check the Notmuch database and the validity of the given queries.
+#[cfg(feature = "notmuch")]
+async fn check_notmuch(notmuch_config: NotmuchConfig, folders: &[String]) -> Vec<Check> {
+    use color_eyre::eyre::eyre;
+
+    use crate::watch::notmuch;
+
+    let mut checks = Vec::new();
+
+    // the database cannot be shared between checks, since it cannot
+    // be sent between threads
+    let check = Check::run("Notmuch database", async {
+        let db = notmuch::open_db(&notmuch_config)?;
+        let path = db.path().display().to_string();
+        Ok(CheckOutcome::Pass(format!("{path} is valid")))
+    });
+
+    checks.push(check.await);
+
+    if checks[0].status == CheckStatus::Fail {
+        return checks;
+    }
+
+    for folder in folders {
+        let check = Check::run(format!("query {folder}"), async {
+            let db = notmuch::open_db(&notmuch_config)?;
+            let query = notmuch::to_query(folder);
+            let count = db
+                .create_query(&query)
+                .and_then(|query| query.count_messages())
+                .map_err(|err| eyre!(err))
+                .wrap_err_with(|| format!("cannot search Notmuch query {query}"))?;
+
+            Ok(CheckOutcome::Pass(format!(
+                "query is valid ({count} messages)"
+            )))
+        });
+
+        checks.push(check.await);
+    }
+
+    checks
+}
+
+/// Check the JMAP session, the EventSource push support and the
+/// existence of the given folders.
+#[cfg(feature = "jmap")]
+async fn check_jmap(jmap_config: JmapConfig, folders: &[String]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let mut client = None;
+
+    let check = Check::run("JMAP session", async {
+        let url = jmap_config.session_url.clone();
+        let jmap = JmapClientBuilder::new(Arc::new(jmap_config))
+            .build()
+            .await?;
+        let username = jmap.session().username.clone();
+        client = Some(jmap);
+        Ok(CheckOutcome::Pass(format!(
+            "connected to {url} as {username}"
+        )))
+    });
+
+    checks.push(check.await);
+
+    // other checks require a working session
+    let Some(client) = client else {
+        return checks;
+    };
+
+    let check = Check::run("JMAP push", async {
+        client.subscribe().await?;
+        Ok(CheckOutcome::Pass(String::from(
+            "server supports EventSource push",
+        )))
+    });
+
+    checks.push(check.await);
+
+    for folder in folders {
+        let check = Check::run(format!("folder {folder}"), async {
+            let mailbox = client.find_mailbox(folder).await?;
+            let count = mailbox.total_emails;
+            Ok(CheckOutcome::Pass(format!(
+                "folder exists ({count} messages)"
+            )))
+        });
+
+        checks.push(check.await);
+    }
+
+    checks
+}
+
+/// Check the POP3 connection, the UIDL command support and the
+/// given folders, since POP3 mailboxes only have an inbox.
+#[cfg(feature = "pop3")]
+async fn check_pop3(pop3_config: Pop3Config, folders: &[String]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let mut client = None;
+
+    let check = Check::run("POP3 connection", async {
+        let host = format!("{}:{}", pop3_config.host, pop3_config.port);
+        let login = pop3_config.login.clone();
+        let pop3 = Pop3ClientBuilder::new(Arc::new(pop3_config))
+            .build()
+            .await?;
+        client = Some(pop3);
+        Ok(CheckOutcome::Pass(format!(
+            "connected to {host} as {login}"
+        )))
+    });
+
+    checks.push(check.await);
+
+    // other checks require a working session
+    let Some(mut client) = client else {
+        return checks;
+    };
+
+    let check = Check::run("POP3 UIDL", async {
+        let count = client.list_uids().await?.len();
+        Ok(CheckOutcome::Pass(format!(
+            "server supports unique identifiers ({count} messages)"
+        )))
+    });
+
+    checks.push(check.await);
+    client.quit().await;
+
+    for folder in folders {
+        let check = Check::run(format!("folder {folder}"), async {
+            if !folder.eq_ignore_ascii_case(INBOX) {
+                bail!("POP3 mailboxes only have an {INBOX} folder");
+            }
+
+            Ok(CheckOutcome::Pass(String::from("folder exists")))
+        });
+
+        checks.push(check.await);
+    }
+
+    checks
+}
+
+/// Check hooks prerequisites: programs of commands must be found in
+/// `PATH`, and a notification daemon must be reachable.
+///
+/// Programs of shell commands not found in `PATH` only trigger a
+/// warning, since they can be shell functions or aliases.
+async fn check_hooks(hooks: &WatchHooks) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    if hooks.iter().next().is_none() {
+        let check = Check::run("hooks", async {
+            let warning = "no hook defined, changes will only be logged";
+            Ok(CheckOutcome::Warn(String::from(warning)))
+        });
+
+        checks.push(check.await);
+        return checks;
+    }
+
+    let quoted: Vec<_> = hooks
+        .iter()
+        .filter_map(|hook| hook.cmd.as_ref())
+        .flat_map(|cmd| cmd.quoted_placeholders())
+        .collect();
+
+    if !quoted.is_empty() {
+        let check = Check::run("shell command quoting", async {
+            bail!(
+                "placeholder(s) {} inside quotes, remove the quotes since placeholder values are already quoted",
+                quoted.join(", ")
+            )
+        });
+
+        checks.push(check.await);
+    }
+
+    let mut programs: Vec<Option<String>> = Vec::new();
+
+    for cmd in hooks.iter().filter_map(|hook| hook.cmd.as_ref()) {
+        let shell = matches!(cmd, WatchCmdConfig::Shell(_));
+        let program = match cmd {
+            WatchCmdConfig::Shell(cmd) => find_program(cmd),
+            WatchCmdConfig::Exec(exec) if exec.program.starts_with('{') => None,
+            WatchCmdConfig::Exec(exec) => Some(exec.program.clone()),
+        };
+
+        if programs.contains(&program) {
+            continue;
+        }
+
+        programs.push(program.clone());
+
+        let name = match &program {
+            Some(program) => format!("command {program}"),
+            None => format!("command {cmd}"),
+        };
+
+        let check = Check::run(name, async move {
+            let Some(program) = program else {
+                let warning = "cannot determine the program, skipping";
+                return Ok(CheckOutcome::Warn(String::from(warning)));
+            };
+
+            if SHELL_BUILTINS.contains(&program.as_str()) {
+                return Ok(CheckOutcome::Pass(String::from("shell builtin")));
+            }
+
+            if SHELL_KEYWORDS.contains(&program.as_str()) {
+                return Ok(CheckOutcome::Pass(String::from("shell keyword")));
+            }
+
+            match find_in_path(&program) {
+                Some(path) => Ok(CheckOutcome::Pass(format!("found at {}", path.display()))),
+                None if shell => {
+                    let warning = format!("cannot find executable program {program} in PATH");
+                    Ok(CheckOutcome::Warn(warning))
+                }
+                None => bail!("cannot find executable program {program} in PATH"),
+            }
+        });
+
+        checks.push(check.await);
+    }
+
+    #[cfg(target_os = "linux")]
+    if hooks.iter().any(|hook| hook.notify.is_some()) {
+        let check = Check::run("notification daemon", async {
+            let info = tokio::task::spawn_blocking(notify_rust::get_server_information)
+                .await?
+                .wrap_err("cannot reach desktop notification daemon")?;
+
+            let message = format!("{} {} is reachable", info.name, info.version);
+            Ok(CheckOutcome::Pass(message))
+        });
+
+        checks.push(check.await);
+    }
+
+    checks
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// Find the program executed by the given shell command.
+///
+/// Leading environment variable assignments are skipped. Returns
+/// `None` if the program cannot be determined statically (variable,
+/// subshell…).
+fn find_program(cmd: &str) -> Option<String> {
+    let is_assignment = |word: &str| match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+
+    let program = shell_words(cmd)
+        .into_iter()
+        .find(|word| !is_assignment(word))?;
+
+    if program.is_empty() || program.starts_with(['$', '(', '{', '`']) {
+        return None;
+    }
+
+    Some(program)
+}
+
+/// Split the given shell command into words, without quotes.
+///
+/// Only quotes and backslashes are interpreted, which is enough to
+/// find the program of simple commands.
+fn shell_words(cmd: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = cmd.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '"' | '\'') => {
+                word.get_or_insert_with(String::new);
+                quote = Some(c);
+            }
+            (Some(q), c) if q == c => quote = None,
+            (None | Some('"'), '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.next());
+            }
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    words
+}
+
+/// Find the given executable program in `PATH`.
+///
+/// Programs containing a path separator are not searched, they are
+/// checked as is, after expanding the tilde and environment
+/// variables.
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = expand::path(program);
+    let path = path.as_path();
+
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_owned());
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+/// Tell whether the given path is an executable file.
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Tell whether the given path is an executable file.
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn find_in_path_requires_executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("hook.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        let program = script.to_str().unwrap();
+
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(find_in_path(program), None);
+
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_in_path(program), Some(script));
+    }
+
+    #[test]
+    fn find_in_path_ignores_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(find_in_path(dir.path().to_str().unwrap()), None);
+    }
+
+    #[test]
+    fn find_in_path_missing_program() {
+        assert_eq!(find_in_path("mirador-missing-program"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_in_path_expands_tilde() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let Some(home) = env::var_os("HOME") else {
+            return;
+        };
+
+        let dir = tempfile::tempdir_in(home).unwrap();
+        let script = dir.path().join("hook.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let name = dir.path().file_name().unwrap().to_str().unwrap();
+        let program = format!("~/{name}/hook.sh");
+        assert_eq!(find_in_path(&program), Some(script));
+    }
+
+    #[test]
+    fn find_program_of_shell_commands() {
+        let program = |cmd| find_program(cmd);
+
+        assert_eq!(program("notify-send {subject}").unwrap(), "notify-send");
+        assert_eq!(program("~/bin/hook.sh {id}").unwrap(), "~/bin/hook.sh");
+        assert_eq!(program("'my hook' {id}").unwrap(), "my hook");
+        assert_eq!(program("if true; then echo; fi").unwrap(), "if");
+
+        // environment variable assignments are skipped, even quoted
+        assert_eq!(program("A=1 notify-send {subject}").unwrap(), "notify-send");
+        assert_eq!(program("VAR=\"a b\" notify-send x").unwrap(), "notify-send");
+        assert_eq!(program("A='x y' B=a\\ b logger x").unwrap(), "logger");
+
+        assert_eq!(program("$HOOK {id}"), None);
+        assert_eq!(program("(cd /tmp && make)"), None);
+        assert_eq!(program("{program} {id}"), None);
+        assert_eq!(program("A=1"), None);
+        assert_eq!(program(""), None);
+    }
+
+    #[test]
+    fn split_shell_words() {
+        assert_eq!(
+            shell_words("a  'b c' \"d \\\" e\" f\\ g ''"),
+            ["a", "b c", "d \" e", "f g", ""]
+        );
+        assert_eq!(shell_words("  "), Vec::<String>::new());
+    }
+}
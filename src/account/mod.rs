@@ -3,12 +3,15 @@
 //! An account is a backend tuple (left and right) identified by a
 //! name. The [`arg`] and [`command`] modules contain CLI
 //! account-related arguments and commands. The [`config`] module
-//! contains its associated user configuration. Finally, the
-//! [`wizard`] module contains code to generate an account
-//! configuration.
+//! contains its associated user configuration. The [`doctor`] module
+//! contains account checks, and the [`summary`] module a redacted
+//! overview of configured accounts. Finally, the [`wizard`] module
+//! contains code to generate an account configuration.
 
 pub mod arg;
 pub mod command;
 pub mod config;
+pub mod doctor;
+pub mod summary;
 #[cfg(feature = "wizard")]
 pub mod wizard;
@@ -1,15 +1,15 @@
 use color_eyre::Result;
-use email::watch::config::{WatchHook, WatchNotifyConfig};
+use email::watch::config::WatchNotifyConfig;
 use pimalaya_tui::terminal::prompt;
 
-use crate::backend;
+use crate::{backend, watch::hook::WatchHookConfig};
 
-use super::config::TomlAccountConfig;
+use super::config::{TomlAccountConfig, DEFAULT_FOLDER};
 
 pub async fn configure() -> Result<(String, TomlAccountConfig)> {
     let name = prompt::text("Account name:", Some("personal"))?;
-    let folder = prompt::text("Folder to watch:", Some("INBOX"))?;
-    let hook = WatchHook {
+    let folder = prompt::text("Folder to watch:", Some(DEFAULT_FOLDER))?;
+    let hook = WatchHookConfig {
         notify: if prompt::bool("Send system notification on new message?", true)? {
             Some(WatchNotifyConfig {
                 summary: prompt::text("Notification title:", Some("📫 New message from {sender}"))?,
@@ -23,14 +23,25 @@ pub async fn configure() -> Result<(String, TomlAccountConfig)> {
         } else {
             None
         },
-        callback: None,
+        webhook: None,
+        timeout: None,
     };
 
     let config = TomlAccountConfig {
         default: Some(true),
         folder: Some(folder),
+        folders: None,
         on_message_added: Some(hook),
+        on_message_removed: None,
+        on_flags_changed: None,
+        on_any_change: None,
+        on_hook_failed: None,
+        rules: None,
         backend: backend::wizard::configure(&name).await?,
+        reconnect: None,
+        state: None,
+        debounce: None,
+        watch: None,
     };
 
     Ok((name, config))
@@ -6,8 +6,15 @@
 use email::imap::config::ImapConfig;
 #[cfg(feature = "maildir")]
 use email::maildir::config::MaildirConfig;
+#[cfg(feature = "notmuch")]
+use email::notmuch::config::NotmuchConfig;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "jmap")]
+use crate::jmap::config::JmapConfig;
+#[cfg(feature = "pop3")]
+use crate::pop3::config::Pop3Config;
+
 /// The backend-specific configuration.
 ///
 /// Represents all valid backends managed by Mirador with their
@@ -22,4 +29,21 @@ pub enum BackendConfig {
     /// The Maildir backend configuration.
     #[cfg(feature = "maildir")]
     Maildir(MaildirConfig),
+
+    /// The Notmuch backend configuration.
+    ///
+    /// Folders of accounts using this backend are Notmuch queries.
+    #[cfg(feature = "notmuch")]
+    Notmuch(NotmuchConfig),
+
+    /// The JMAP backend configuration.
+    #[cfg(feature = "jmap")]
+    Jmap(JmapConfig),
+
+    /// The POP3 backend configuration.
+    ///
+    /// Accounts using this backend can only watch the `INBOX`
+    /// folder, which is polled for new messages.
+    #[cfg(feature = "pop3")]
+    Pop3(Pop3Config),
 }
@@ -11,6 +11,12 @@ pub enum BackendKind {
     Imap,
     #[cfg(feature = "maildir")]
     Maildir,
+    #[cfg(feature = "notmuch")]
+    Notmuch,
+    #[cfg(feature = "jmap")]
+    Jmap,
+    #[cfg(feature = "pop3")]
+    Pop3,
 }
 
 impl fmt::Display for BackendKind {
@@ -21,6 +27,12 @@ impl fmt::Display for BackendKind {
             Self::Imap => write!(f, "IMAP"),
             #[cfg(feature = "maildir")]
             Self::Maildir => write!(f, "Maildir"),
+            #[cfg(feature = "notmuch")]
+            Self::Notmuch => write!(f, "Notmuch"),
+            #[cfg(feature = "jmap")]
+            Self::Jmap => write!(f, "JMAP"),
+            #[cfg(feature = "pop3")]
+            Self::Pop3 => write!(f, "POP3"),
         }
     }
 }
@@ -9,10 +9,16 @@ static BACKENDS: &[BackendKind] = &[
     BackendKind::Imap,
     #[cfg(feature = "maildir")]
     BackendKind::Maildir,
+    #[cfg(feature = "notmuch")]
+    BackendKind::Notmuch,
+    #[cfg(feature = "jmap")]
+    BackendKind::Jmap,
+    #[cfg(feature = "pop3")]
+    BackendKind::Pop3,
 ];
 
 pub async fn configure(account_name: &str) -> Result<BackendConfig> {
-    let backend = prompt::item("Backend to configure:", &*BACKENDS, None)?;
+    let backend = prompt::item("Backend to configure:", BACKENDS, None)?;
 
     let backend = match backend {
         #[cfg(feature = "imap")]
@@ -31,6 +37,21 @@ pub async fn configure(account_name: &str) -> Result<BackendConfig> {
             let config = wizard::maildir::start(account_name)?;
             BackendConfig::Maildir(config)
         }
+        #[cfg(feature = "notmuch")]
+        BackendKind::Notmuch => {
+            let config = wizard::notmuch::start()?;
+            BackendConfig::Notmuch(config)
+        }
+        #[cfg(feature = "jmap")]
+        BackendKind::Jmap => {
+            let config = crate::jmap::wizard::start(account_name).await?;
+            BackendConfig::Jmap(config)
+        }
+        #[cfg(feature = "pop3")]
+        BackendKind::Pop3 => {
+            let config = crate::pop3::wizard::start(account_name).await?;
+            BackendConfig::Pop3(config)
+        }
         _ => unreachable!(),
     };
 
@@ -9,11 +9,13 @@ use pimalaya_tui::{
 
 use crate::{
     account::command::{
-        configure::ConfigureAccountCommand, doctor::DoctorAccountCommand, watch::WatchCommand,
+        configure::ConfigureAccountCommand, doctor::DoctorAccountCommand,
+        test_hook::TestHookCommand, watch::WatchCommand,
     },
     completion::command::GenerateCompletionCommand,
-    config::TomlConfig,
+    config::{command::ConfigSubcommand, TomlConfig},
     manual::command::GenerateManualCommand,
+    service::command::ServiceSubcommand,
 };
 
 #[derive(Parser, Debug)]
@@ -37,6 +39,12 @@ pub struct Cli {
     #[arg(value_name = "PATH", value_parser = path_parser)]
     pub config_paths: Vec<PathBuf>,
 
+    /// Print the accounts summary as JSON.
+    ///
+    /// Only applies when no subcommand is given.
+    #[arg(long)]
+    pub json: bool,
+
     /// Enable logs with spantrace.
     ///
     /// This is the same as running the command with `RUST_LOG=debug`
@@ -63,6 +71,16 @@ pub enum MiradorCommand {
     #[command()]
     Watch(WatchCommand),
 
+    #[command(alias = "test-hooks")]
+    TestHook(TestHookCommand),
+
+    #[command(subcommand)]
+    Config(ConfigSubcommand),
+
+    #[command(subcommand)]
+    #[command(alias = "services")]
+    Service(ServiceSubcommand),
+
     #[command(arg_required_else_help = true)]
     #[command(alias = "manuals", alias = "mans")]
     Manual(GenerateManualCommand),
@@ -84,9 +102,18 @@ impl MiradorCommand {
                 cmd.execute(&config).await
             }
             Self::Watch(cmd) => {
+                let config = TomlConfig::from_paths_or_default(config_paths).await?;
+                cmd.execute(&config, config_paths).await
+            }
+            Self::TestHook(cmd) => {
                 let config = TomlConfig::from_paths_or_default(config_paths).await?;
                 cmd.execute(&config).await
             }
+            Self::Config(cmd) => cmd.execute(config_paths).await,
+            Self::Service(cmd) => {
+                let config = TomlConfig::from_paths_or_default(config_paths).await?;
+                cmd.execute(&config, config_paths).await
+            }
             Self::Manual(cmd) => cmd.execute().await,
             Self::Completion(cmd) => cmd.execute().await,
         }
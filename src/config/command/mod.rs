@@ -0,0 +1,31 @@
+//! # Config commands
+//!
+//! This module gathers CLI commands dedicated to the configuration:
+//! [`validate`] to check the configuration files.
+
+pub mod validate;
+
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use color_eyre::Result;
+
+use self::validate::ValidateConfigCommand;
+
+/// Manage the configuration.
+///
+/// This subcommand allows you to check the configuration, without
+/// watching anything.
+#[derive(Debug, Subcommand)]
+pub enum ConfigSubcommand {
+    #[command(alias = "check")]
+    Validate(ValidateConfigCommand),
+}
+
+impl ConfigSubcommand {
+    pub async fn execute(self, config_paths: &[PathBuf]) -> Result<()> {
+        match self {
+            Self::Validate(cmd) => cmd.execute(config_paths).await,
+        }
+    }
+}
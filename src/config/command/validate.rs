@@ -0,0 +1,458 @@
+//! # Validate config command
+//!
+//! This module contains the [`clap`] command for validating the
+//! configuration.
+
+use std::{
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use color_eyre::eyre::{bail, Report, Result};
+use pimalaya_tui::terminal::config::TomlConfig as _;
+use tracing::instrument;
+
+#[cfg(feature = "imap")]
+use crate::backend::config::BackendConfig;
+use crate::config::TomlConfig;
+
+/// Validate the configuration.
+///
+/// This command checks all the configuration files, as given by
+/// `--config` or found at the default location, without executing
+/// anything. Errors are reported with their file, line and column,
+/// followed by warnings about suspicious settings. The command exits
+/// with a non-zero code when at least one error is found.
+///
+/// Each file is checked on its own, which means only the first error
+/// of a file can be reported: fix it, then validate again.
+#[derive(Debug, Parser)]
+pub struct ValidateConfigCommand;
+
+impl ValidateConfigCommand {
+    #[instrument(skip_all)]
+    pub async fn execute(self, config_paths: &[PathBuf]) -> Result<()> {
+        let paths = match config_paths {
+            [] => match TomlConfig::first_valid_default_path() {
+                Some(path) => vec![path],
+                None => bail!("cannot find any configuration file"),
+            },
+            paths => paths.to_vec(),
+        };
+
+        let mut problems = Vec::new();
+
+        for path in &paths {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(err) => {
+                    problems.push(Problem::error(
+                        path,
+                        None,
+                        format!("cannot read file: {err}"),
+                    ));
+                    continue;
+                }
+            };
+
+            problems.extend(validate_file(path, &content, paths.len() > 1));
+        }
+
+        // missing fields are only reported once files are merged,
+        // since they can be defined in another file
+        if problems.is_empty() {
+            match TomlConfig::from_paths(&paths) {
+                Ok(config) => problems.extend(validate_config(&paths[0], &config)),
+                Err(err) => {
+                    let err = Report::from(err);
+                    let chain: Vec<_> = err.chain().map(ToString::to_string).collect();
+                    let message = chain.join(": ");
+                    let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+                    problems.push(Problem::error(&paths[0], None, message));
+                }
+            }
+        }
+
+        for problem in &problems {
+            println!("{problem}");
+        }
+
+        let errors = problems.iter().filter(|p| p.is_error()).count();
+        let warnings = problems.len() - errors;
+
+        if errors > 0 {
+            bail!("invalid configuration: {errors} error(s), {warnings} warning(s)");
+        }
+
+        let files = paths.len();
+        println!("Configuration is valid ({files} file(s), {warnings} warning(s)).");
+
+        Ok(())
+    }
+}
+
+/// A configuration problem.
+struct Problem {
+    severity: Severity,
+    path: PathBuf,
+    /// The line and column, starting from 1.
+    location: Option<(usize, usize)>,
+    message: String,
+}
+
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl Problem {
+    fn error(path: &Path, location: Option<(usize, usize)>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            path: path.to_path_buf(),
+            location,
+            message,
+        }
+    }
+
+    fn warning(path: &Path, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            path: path.to_path_buf(),
+            location: None,
+            message,
+        }
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(self.severity, Severity::Error)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+
+        if let Some((line, col)) = self.location {
+            write!(f, ":{line}:{col}")?;
+        }
+
+        match self.severity {
+            Severity::Error => write!(f, ": error: {}", self.message),
+            Severity::Warning => write!(f, ": warning: {}", self.message),
+        }
+    }
+}
+
+/// Check the syntax and the structure of the given file.
+///
+/// When the configuration is split across multiple files, missing
+/// fields are ignored.
+fn validate_file(path: &Path, content: &str, partial: bool) -> Option<Problem> {
+    if let Err(err) = content.parse::<toml::Table>() {
+        return Some(to_problem(path, content, &err));
+    }
+
+    let err = toml::from_str::<TomlConfig>(content).err()?;
+
+    if partial && err.message().starts_with("missing field") {
+        return None;
+    }
+
+    Some(to_problem(path, content, &err))
+}
+
+fn to_problem(path: &Path, content: &str, err: &toml::de::Error) -> Problem {
+    let location = err.span().map(|span| location(content, span));
+    let mut message = err.message().trim().to_owned();
+
+    if let Some(suggestion) = suggest(&message).map(ToOwned::to_owned) {
+        // the list of expected names is replaced by the suggestion
+        if let Some(i) = message.find(", expected") {
+            message.truncate(i);
+        }
+
+        message = format!("{message}, did you mean `{suggestion}`?");
+    }
+
+    Problem::error(path, location, message)
+}
+
+/// Check the merged configuration for suspicious settings.
+fn validate_config(path: &Path, config: &TomlConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if config.accounts.is_empty() {
+        problems.push(Problem::warning(
+            path,
+            String::from("no account configured"),
+        ));
+    }
+
+    let mut defaults: Vec<_> = config
+        .accounts
+        .iter()
+        .filter(|(_, config)| config.default == Some(true))
+        .map(|(name, _)| name.as_str())
+        .collect();
+
+    defaults.sort();
+
+    match defaults.len() {
+        0 if !config.accounts.is_empty() => {
+            let message = "no account marked as default, commands need an explicit account name";
+            problems.push(Problem::warning(path, message.to_owned()));
+        }
+        0 | 1 => (),
+        _ => {
+            let message = format!(
+                "multiple accounts marked as default ({}), only one of them will be used",
+                defaults.join(", ")
+            );
+            problems.push(Problem::warning(path, message));
+        }
+    }
+
+    if config.max_concurrent_hooks == Some(0) {
+        let message = "max-concurrent-hooks is 0, at least 1 hook is executed at a time";
+        problems.push(Problem::warning(path, message.to_owned()));
+    }
+
+    let mut accounts: Vec<_> = config.accounts.iter().collect();
+    accounts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (name, config) in accounts {
+        let hooks = config.get_watch_hooks();
+
+        if hooks.iter().next().is_none() {
+            let message = format!("account {name} has no hook, changes will only be logged");
+            problems.push(Problem::warning(path, message));
+        }
+
+        if hooks.iter().any(|hook| hook.timeout == Some(0)) {
+            let message = format!("account {name} has a hook with a timeout of 0 second");
+            problems.push(Problem::warning(path, message));
+        }
+
+        let quoted = hooks
+            .iter()
+            .filter_map(|hook| hook.cmd.as_ref())
+            .flat_map(|cmd| cmd.quoted_placeholders());
+
+        for placeholder in quoted {
+            let message = format!(
+                "account {name} has a shell command with placeholder {placeholder} inside quotes, \
+                 remove the quotes since placeholder values are already quoted"
+            );
+            problems.push(Problem::error(path, None, message));
+        }
+
+        #[cfg(feature = "imap")]
+        let is_imap = matches!(config.backend, BackendConfig::Imap(_));
+        #[cfg(not(feature = "imap"))]
+        let is_imap = false;
+
+        if config.watch.is_some() && !is_imap {
+            let message = format!("account {name} has watch options, which only apply to IMAP");
+            problems.push(Problem::warning(path, message));
+        }
+    }
+
+    problems
+}
+
+/// Get the line and the column of the given span, starting from 1.
+fn location(content: &str, span: Range<usize>) -> (usize, usize) {
+    let before = &content[..span.start.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = match before.rfind('\n') {
+        Some(i) => before[i + 1..].chars().count() + 1,
+        None => before.chars().count() + 1,
+    };
+
+    (line, col)
+}
+
+/// Suggest the closest valid name for unknown fields and variants.
+///
+/// Relies on serde messages, like ``unknown field `on-mesage-added`,
+/// expected one of `folder`, `folders`…``.
+fn suggest(message: &str) -> Option<&str> {
+    if !message.starts_with("unknown field") && !message.starts_with("unknown variant") {
+        return None;
+    }
+
+    let mut names = message.split('`').skip(1).step_by(2);
+    let unknown = names.next()?;
+
+    names
+        .map(|name| (levenshtein(unknown, name), name))
+        .filter(|(distance, name)| *distance <= (name.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut next = vec![i + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            next.push((prev[j] + cost).min(prev[j + 1] + 1).min(next[j] + 1));
+        }
+
+        prev = next;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "maildir")]
+    fn problems(content: &str) -> Vec<String> {
+        let path = Path::new("config.toml");
+        let config: TomlConfig = toml::from_str(content).unwrap();
+
+        validate_config(path, &config)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn validate(content: &str, partial: bool) -> Option<String> {
+        let problem = validate_file(Path::new("config.toml"), content, partial)?;
+        Some(problem.to_string())
+    }
+
+    #[cfg(feature = "maildir")]
+    #[test]
+    fn unknown_field_with_suggestion() {
+        let content = "[accounts.example]\n\
+                       backend.type = \"maildir\"\n\
+                       backend.root-dir = \"/tmp/mail\"\n\
+                       on-mesage-added.cmd = \"true\"\n";
+
+        assert_eq!(
+            validate(content, false).unwrap(),
+            "config.toml:4:1: error: unknown field `on-mesage-added`, did you mean `on-message-added`?"
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        let content = "[accounts.example]\ndefault = \n";
+        let problem = validate(content, false).unwrap();
+        assert!(
+            problem.starts_with("config.toml:2:11: error: "),
+            "{problem}"
+        );
+    }
+
+    #[test]
+    fn missing_field_of_partial_file() {
+        let content = "[accounts.example]\ndefault = true\n";
+
+        // the backend can be defined by another file
+        assert_eq!(validate(content, true), None);
+
+        let problem = validate(content, false).unwrap();
+        assert!(
+            problem.contains(": error: missing field `backend`"),
+            "{problem}"
+        );
+    }
+
+    #[cfg(feature = "maildir")]
+    #[test]
+    fn default_accounts() {
+        let account = |name: &str, default: bool| {
+            format!(
+                "[accounts.{name}]\n\
+                 default = {default}\n\
+                 backend.type = \"maildir\"\n\
+                 backend.root-dir = \"/tmp/mail\"\n\
+                 on-message-added.cmd = \"true\"\n"
+            )
+        };
+
+        let none = account("a", false) + &account("b", false);
+        assert_eq!(
+            problems(&none),
+            ["config.toml: warning: no account marked as default, commands need an explicit account name"]
+        );
+
+        let one = account("a", true) + &account("b", false);
+        assert!(problems(&one).is_empty());
+
+        let two = account("b", true) + &account("a", true);
+        assert_eq!(
+            problems(&two),
+            ["config.toml: warning: multiple accounts marked as default (a, b), only one of them will be used"]
+        );
+    }
+
+    #[test]
+    fn location_of_span() {
+        let content = "a = 1\nbé = 2\n";
+        assert_eq!(location(content, 0..1), (1, 1));
+        assert_eq!(location(content, 6..7), (2, 1));
+        // columns count characters, not bytes
+        assert_eq!(location(content, 10..11), (2, 4));
+        // spans past the end are clamped
+        assert_eq!(location(content, 100..101), (3, 1));
+    }
+
+    #[test]
+    fn suggest_closest_name() {
+        let message = "unknown field `on-mesage-added`, expected one of `on-message-added`, `on-message-removed`";
+        assert_eq!(suggest(message), Some("on-message-added"));
+
+        let message = "unknown variant `mail-dir`, expected one of `imap`, `maildir`";
+        assert_eq!(suggest(message), Some("maildir"));
+
+        // names too far away are not suggested
+        let message = "unknown field `xyz`, expected one of `on-message-added`";
+        assert_eq!(suggest(message), None);
+
+        let message = "missing field `backend`";
+        assert_eq!(suggest(message), None);
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("maildir", "maildir"), 0);
+        assert_eq!(levenshtein("mail-dir", "maildir"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[cfg(all(feature = "maildir", unix))]
+    #[test]
+    fn quoted_placeholders_are_errors() {
+        let problems = problems(
+            r#"
+            [accounts.example]
+            default = true
+            backend.type = "maildir"
+            backend.root-dir = "/tmp/mail"
+            on-message-added.cmd = "notify-send \"New: {subject}\" {sender}"
+            "#,
+        );
+
+        assert_eq!(
+            problems,
+            ["config.toml: error: account example has a shell command with placeholder {subject} \
+              inside quotes, remove the quotes since placeholder values are already quoted"]
+        );
+    }
+}
@@ -1,3 +1,10 @@
+//! # Config
+//!
+//! Module dedicated to the main configuration. The [`command`]
+//! module contains CLI commands dedicated to the configuration.
+
+pub mod command;
+
 use std::collections::HashMap;
 
 use async_trait::async_trait;
@@ -11,6 +18,22 @@ use crate::account::config::TomlAccountConfig;
 pub struct TomlConfig {
     /// The configuration of all the accounts.
     pub accounts: HashMap<String, TomlAccountConfig>,
+
+    /// The maximum number of hooks executed at the same time, all
+    /// accounts and folders included.
+    ///
+    /// Other hooks wait for a running one to finish. Defaults to 8.
+    pub max_concurrent_hooks: Option<usize>,
+}
+
+impl TomlConfig {
+    pub const DEFAULT_MAX_CONCURRENT_HOOKS: usize = 8;
+
+    pub fn max_concurrent_hooks(&self) -> usize {
+        self.max_concurrent_hooks
+            .unwrap_or(Self::DEFAULT_MAX_CONCURRENT_HOOKS)
+            .max(1)
+    }
 }
 
 #[async_trait]
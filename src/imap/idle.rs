@@ -0,0 +1,279 @@
+//! # IMAP IDLE
+//!
+//! Module dedicated to the IMAP IDLE extension, which lets the server
+//! report changes of the selected mailbox as they happen. email-lib
+//! implements it, but does not tell when the server accepted the
+//! command: the IDLE command is written and read directly on the
+//! underlying stream instead, so that watchers are only reported as
+//! ready once changes cannot be missed anymore.
+//!
+//! See <https://www.rfc-editor.org/rfc/rfc2177>.
+
+use std::time::Duration;
+
+use color_eyre::{
+    eyre::{bail, OptionExt},
+    Result,
+};
+use imap_client::client::tokio::Client;
+use tokio::{
+    sync::oneshot,
+    time::{timeout_at, Instant},
+};
+use tracing::debug;
+
+use super::session::{quote, Completion, ImapSession};
+
+/// The IMAP IDLE client.
+///
+/// Wraps an authenticated session dedicated to IDLE. The IDLE command
+/// is restarted after the watch timeout of the IMAP configuration, so
+/// that servers do not close the connection.
+pub struct ImapIdleClient {
+    session: ImapSession,
+    timeout: Duration,
+    tag: Option<String>,
+    changed: bool,
+}
+
+impl ImapIdleClient {
+    /// Take over the stream of the given authenticated session, see
+    /// [`ImapSession::new`].
+    pub async fn new(client: Client) -> Result<Self> {
+        let timeout = *client.state.get_idle_timeout();
+
+        Ok(Self {
+            session: ImapSession::new(client).await?,
+            timeout,
+            tag: None,
+            changed: false,
+        })
+    }
+
+    /// Examine the given mailbox, which changes are then reported
+    /// while idling.
+    ///
+    /// The mailbox name must be UTF-7 encoded.
+    pub async fn examine(&mut self, mailbox: &str) -> Result<()> {
+        let cmd = format!("EXAMINE {}", quote(mailbox));
+
+        match self.session.command(&cmd).await? {
+            Completion::Ok => Ok(()),
+            Completion::Rejected(text) => bail!("cannot examine IMAP mailbox {mailbox}: {text}"),
+        }
+    }
+
+    /// Start idling, returning once the server accepted the command.
+    ///
+    /// Changes reported before the acceptance are kept, so that the
+    /// next [`ImapIdleClient::wait`] returns straight away.
+    pub async fn idle(&mut self) -> Result<()> {
+        let tag = self.session.send("IDLE").await?;
+        let prefix = format!("{tag} ");
+
+        loop {
+            let response = self.session.read_response().await?;
+
+            if response.starts_with(b"+") {
+                debug!("IMAP server accepted IDLE");
+                self.tag = Some(tag);
+                break Ok(());
+            }
+
+            if let Some(rest) = response.strip_prefix(prefix.as_bytes()) {
+                let text = String::from_utf8_lossy(rest);
+                bail!("IMAP server rejected IDLE: {}", text.trim());
+            }
+
+            self.changed |= is_change(&response)?;
+        }
+    }
+
+    /// Wait for a change of the examined mailbox, then stop idling.
+    ///
+    /// Also stops idling when the watch timeout is reached, which is
+    /// reported as a change. Returns `false` if the wait was
+    /// interrupted by the done request.
+    pub async fn wait(
+        &mut self,
+        wait_for_done_request: &mut oneshot::Receiver<()>,
+    ) -> Result<bool> {
+        let tag = self
+            .tag
+            .take()
+            .ok_or_eyre("IMAP IDLE command not started")?;
+        let deadline = Instant::now() + self.timeout;
+        let mut changed = std::mem::take(&mut self.changed);
+
+        while !changed {
+            tokio::select! {
+                res = timeout_at(deadline, self.session.readable()) => match res {
+                    Ok(res) => res?,
+                    Err(_) => {
+                        debug!("IMAP IDLE timed out, restarting it");
+                        break;
+                    }
+                },
+                _ = &mut *wait_for_done_request => {
+                    self.done(&tag).await?;
+                    return Ok(false);
+                }
+            }
+
+            let response = self.session.read_response().await?;
+            changed = is_change(&response)?;
+        }
+
+        self.done(&tag).await?;
+        Ok(true)
+    }
+
+    /// Stop idling.
+    async fn done(&mut self, tag: &str) -> Result<()> {
+        self.session.send_line("DONE").await?;
+
+        match self.session.complete(tag).await? {
+            Completion::Ok => Ok(()),
+            Completion::Rejected(text) => bail!("IMAP server rejected IDLE: {text}"),
+        }
+    }
+}
+
+/// Return `true` if the given response reports a change of the
+/// selected mailbox.
+///
+/// Fails if the server is closing the connection.
+fn is_change(response: &[u8]) -> Result<bool> {
+    let Some(rest) = response.strip_prefix(b"* ") else {
+        return Ok(false);
+    };
+
+    let upper = rest.to_ascii_uppercase();
+    let mut words = upper.split(|b| matches!(b, b' ' | b'\r' | b'\n'));
+
+    match (words.next(), words.next()) {
+        (Some(b"BYE"), _) => {
+            let text = String::from_utf8_lossy(&rest[3..]);
+            bail!("IMAP connection closed by server: {}", text.trim());
+        }
+        (Some(b"VANISHED"), _) => Ok(true),
+        (_, Some(b"EXISTS" | b"EXPUNGE" | b"FETCH")) => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::imap::session::tests::serve;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_for_change() {
+        let greeting = "* OK [CAPABILITY IMAP4rev1 IDLE] ready\r\n";
+
+        let port = serve(
+            greeting,
+            vec![
+                "{tag} OK NOOP completed\r\n",
+                "* 1 EXISTS\r\n{tag} OK [READ-ONLY] EXAMINE completed\r\n",
+                "+ idling\r\n* OK still here\r\n* 2 EXISTS\r\n",
+                "N2 OK IDLE terminated\r\n",
+            ],
+        )
+        .await;
+
+        let client = Client::insecure("127.0.0.1", port).await.unwrap();
+        let mut client = ImapIdleClient::new(client).await.unwrap();
+        client.examine("INBOX").await.unwrap();
+        client.idle().await.unwrap();
+
+        let (_done, mut wait_for_done_request) = oneshot::channel();
+        assert!(client.wait(&mut wait_for_done_request).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn keep_changes_before_acceptance() {
+        let greeting = "* OK [CAPABILITY IMAP4rev1 IDLE] ready\r\n";
+
+        let port = serve(
+            greeting,
+            vec![
+                "{tag} OK NOOP completed\r\n",
+                "{tag} OK [READ-ONLY] EXAMINE completed\r\n",
+                "* 1 EXPUNGE\r\n+ idling\r\n",
+                "N2 OK IDLE terminated\r\n",
+            ],
+        )
+        .await;
+
+        let client = Client::insecure("127.0.0.1", port).await.unwrap();
+        let mut client = ImapIdleClient::new(client).await.unwrap();
+        client.examine("INBOX").await.unwrap();
+        client.idle().await.unwrap();
+
+        let (_done, mut wait_for_done_request) = oneshot::channel();
+        assert!(client.wait(&mut wait_for_done_request).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stop_on_done_request() {
+        let greeting = "* OK [CAPABILITY IMAP4rev1 IDLE] ready\r\n";
+
+        let port = serve(
+            greeting,
+            vec![
+                "{tag} OK NOOP completed\r\n",
+                "{tag} OK [READ-ONLY] EXAMINE completed\r\n",
+                "+ idling\r\n",
+                "N2 OK IDLE terminated\r\n",
+            ],
+        )
+        .await;
+
+        let client = Client::insecure("127.0.0.1", port).await.unwrap();
+        let mut client = ImapIdleClient::new(client).await.unwrap();
+        client.examine("INBOX").await.unwrap();
+        client.idle().await.unwrap();
+
+        let (done, mut wait_for_done_request) = oneshot::channel();
+        done.send(()).unwrap();
+        assert!(!client.wait(&mut wait_for_done_request).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn idle_rejected() {
+        let greeting = "* OK [CAPABILITY IMAP4rev1] ready\r\n";
+
+        let port = serve(
+            greeting,
+            vec![
+                "{tag} OK NOOP completed\r\n",
+                "{tag} OK [READ-ONLY] EXAMINE completed\r\n",
+                "{tag} BAD unknown command\r\n",
+            ],
+        )
+        .await;
+
+        let client = Client::insecure("127.0.0.1", port).await.unwrap();
+        let mut client = ImapIdleClient::new(client).await.unwrap();
+        client.examine("INBOX").await.unwrap();
+
+        let err = client.idle().await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "IMAP server rejected IDLE: BAD unknown command"
+        );
+    }
+
+    #[test]
+    fn changes() {
+        assert!(is_change(b"* 2 EXISTS\r\n").unwrap());
+        assert!(is_change(b"* 1 expunge\r\n").unwrap());
+        assert!(is_change(b"* 1 FETCH (FLAGS (\\Seen))\r\n").unwrap());
+        assert!(is_change(b"* VANISHED 1:2\r\n").unwrap());
+        assert!(!is_change(b"* OK still here\r\n").unwrap());
+        assert!(!is_change(b"N1 OK done\r\n").unwrap());
+        assert!(is_change(b"* BYE shutting down\r\n").is_err());
+    }
+}
@@ -0,0 +1,12 @@
+//! # IMAP
+//!
+//! Module dedicated to the parts of the IMAP protocol not covered by
+//! email-lib. The [`notify`] module implements the NOTIFY extension,
+//! see RFC 5465, in order to watch many mailboxes over a single
+//! connection. The [`idle`] module implements the IDLE extension, see
+//! RFC 2177, reporting when the server accepted the command. Both run
+//! over a raw [`session`].
+
+pub mod idle;
+pub mod notify;
+pub mod session;
@@ -0,0 +1,272 @@
+//! # IMAP NOTIFY
+//!
+//! Module dedicated to the IMAP NOTIFY extension, which makes the
+//! server report changes of many mailboxes over a single connection,
+//! using untagged `STATUS` responses. Neither email-lib nor
+//! imap-client support it, the NOTIFY command and its responses are
+//! written and read over a raw session, see [`ImapSession`].
+//!
+//! See <https://www.rfc-editor.org/rfc/rfc5465>.
+
+use std::time::Duration;
+
+use color_eyre::{eyre::bail, Result};
+use imap_client::client::tokio::Client;
+use tokio::time::timeout;
+use tracing::{debug, info};
+
+use super::session::{quote, Completion, ImapSession, IO_TIMEOUT};
+
+/// The events requested for watched mailboxes.
+const EVENTS: &str = "(MessageNew MessageExpunge FlagChange)";
+
+/// The events requested when the server rejects [`EVENTS`].
+const EVENTS_WITHOUT_FLAGS: &str = "(MessageNew MessageExpunge)";
+
+/// Return `true` if the given session advertises the NOTIFY
+/// capability.
+pub fn notify_supported(client: &Client) -> bool {
+    client
+        .state
+        .capabilities_iter()
+        .any(|capability| capability.to_string().eq_ignore_ascii_case("NOTIFY"))
+}
+
+/// The IMAP notification.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ImapNotification {
+    /// Messages of the given mailbox changed.
+    ///
+    /// The name is the one sent by the server, which is UTF-7
+    /// encoded.
+    MailboxChanged(String),
+
+    /// The server could not keep track of changes, which means that
+    /// any mailbox may have changed.
+    Overflow,
+}
+
+/// The IMAP NOTIFY client.
+///
+/// Once notifications are set, the client only waits for them: a
+/// NOOP command is sent after [`ImapNotifyClient::KEEPALIVE_INTERVAL`]
+/// of silence, so that dead connections are detected.
+pub struct ImapNotifyClient {
+    session: ImapSession,
+    noop_tag: Option<String>,
+}
+
+impl ImapNotifyClient {
+    /// The maximum duration without receiving anything from the
+    /// server.
+    pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+    /// Take over the stream of the given authenticated session, see
+    /// [`ImapSession::new`].
+    pub async fn new(client: Client) -> Result<Self> {
+        Ok(Self {
+            session: ImapSession::new(client).await?,
+            noop_tag: None,
+        })
+    }
+
+    /// Ask the server to notify changes of the given mailboxes.
+    ///
+    /// Mailbox names must be UTF-7 encoded. Servers rejecting the
+    /// list of mailboxes are asked to notify changes of all the
+    /// personal mailboxes instead, and servers rejecting the
+    /// FlagChange event are asked to only notify new and expunged
+    /// messages.
+    ///
+    /// Returns `false` if the server rejected all of them.
+    pub async fn set(&mut self, mailboxes: &[String]) -> Result<bool> {
+        let mailboxes: Vec<_> = mailboxes.iter().map(|mbox| quote(mbox)).collect();
+        let mut filter = format!("mailboxes ({})", mailboxes.join(" "));
+        let mut events = EVENTS;
+
+        loop {
+            let cmd = format!("NOTIFY SET ({filter} {events})");
+
+            let text = match self.session.command(&cmd).await? {
+                Completion::Ok => {
+                    debug!(filter, events, "IMAP notifications set");
+                    break Ok(true);
+                }
+                Completion::Rejected(text) => text,
+            };
+
+            if events == EVENTS && text.to_ascii_uppercase().contains("[BADEVENT") {
+                info!("IMAP server cannot notify flag changes, only new and expunged messages will be notified: {text}");
+                events = EVENTS_WITHOUT_FLAGS;
+            } else if filter != "personal" {
+                debug!("IMAP server rejected the list of mailboxes, trying personal ones: {text}");
+                filter = String::from("personal");
+            } else {
+                debug!("IMAP server rejected notifications: {text}");
+                break Ok(false);
+            }
+        }
+    }
+
+    /// Wait for the next notification.
+    pub async fn next(&mut self) -> Result<ImapNotification> {
+        loop {
+            let wait = match self.noop_tag {
+                Some(_) => IO_TIMEOUT,
+                None => Self::KEEPALIVE_INTERVAL,
+            };
+
+            match timeout(wait, self.session.readable()).await {
+                Ok(res) => res?,
+                Err(_) if self.noop_tag.is_some() => {
+                    bail!("cannot receive IMAP NOOP response: timed out")
+                }
+                Err(_) => {
+                    let tag = self.session.send("NOOP").await?;
+                    self.noop_tag = Some(tag);
+                    continue;
+                }
+            }
+
+            let response = self.session.read_response().await?;
+
+            if let Some(rest) = response.strip_prefix(b"* ") {
+                let upper = rest.to_ascii_uppercase();
+
+                if let Some(mailbox) = upper.strip_prefix(b"STATUS ") {
+                    // the original case is kept, mailbox names being
+                    // case-sensitive except INBOX
+                    let mailbox = &rest[rest.len() - mailbox.len()..];
+
+                    match parse_mailbox(mailbox) {
+                        Some(mailbox) => break Ok(ImapNotification::MailboxChanged(mailbox)),
+                        None => debug!("invalid IMAP STATUS response, skipping"),
+                    }
+                } else if upper.starts_with(b"OK [NOTIFICATIONOVERFLOW") {
+                    break Ok(ImapNotification::Overflow);
+                } else if upper.starts_with(b"BYE") {
+                    let text = String::from_utf8_lossy(&rest[3..]);
+                    bail!("IMAP connection closed by server: {}", text.trim());
+                }
+
+                continue;
+            }
+
+            let Some(tag) = self.noop_tag.as_deref() else {
+                continue;
+            };
+
+            if let Some(rest) = response.strip_prefix(format!("{tag} ").as_bytes()) {
+                if !rest.to_ascii_uppercase().starts_with(b"OK") {
+                    let text = String::from_utf8_lossy(rest);
+                    bail!("IMAP server rejected NOOP: {}", text.trim());
+                }
+
+                self.noop_tag = None;
+            }
+        }
+    }
+}
+
+/// Parse the mailbox name at the beginning of the given input, which
+/// can be an atom, a quoted string or a literal.
+fn parse_mailbox(input: &[u8]) -> Option<String> {
+    let name = match input.first()? {
+        b'"' => {
+            let mut name = Vec::new();
+            let mut bytes = input[1..].iter();
+
+            loop {
+                match bytes.next()? {
+                    b'\\' => name.push(*bytes.next()?),
+                    b'"' => break name,
+                    b => name.push(*b),
+                }
+            }
+        }
+        b'{' => {
+            let end = input.iter().position(|b| *b == b'}')?;
+            let len: usize = std::str::from_utf8(&input[1..end])
+                .ok()?
+                .trim_end_matches('+')
+                .parse()
+                .ok()?;
+            let data = &input[end + 1..];
+            let data = data.strip_prefix(b"\r\n").or(data.strip_prefix(b"\n"))?;
+            data.get(..len)?.to_vec()
+        }
+        _ => input
+            .iter()
+            .take_while(|b| !matches!(b, b' ' | b'(' | b'\r' | b'\n'))
+            .copied()
+            .collect(),
+    };
+
+    Some(String::from_utf8_lossy(&name).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::imap::session::tests::serve;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn take_over_after_buffered_responses() {
+        // the greeting and an unsolicited response are read at once
+        // by the session, before the stream is taken over
+        let greeting = "* OK [CAPABILITY IMAP4rev1 NOTIFY] ready\r\n* OK [ALERT] unsolicited\r\n";
+
+        let port = serve(
+            greeting,
+            vec![
+                "{tag} OK NOOP completed\r\n",
+                "{tag} OK NOTIFY completed\r\n* STATUS \"Work\" (MESSAGES 2)\r\n",
+            ],
+        )
+        .await;
+
+        let client = Client::insecure("127.0.0.1", port).await.unwrap();
+        assert!(notify_supported(&client));
+
+        let mut client = ImapNotifyClient::new(client).await.unwrap();
+        assert!(client.set(&[String::from("Work")]).await.unwrap());
+
+        let notification = client.next().await.unwrap();
+        assert_eq!(
+            notification,
+            ImapNotification::MailboxChanged("Work".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn set_falls_back_to_personal_mailboxes() {
+        let greeting = "* OK [CAPABILITY IMAP4rev1 NOTIFY] ready\r\n";
+
+        let port = serve(
+            greeting,
+            vec![
+                "{tag} OK NOOP completed\r\n",
+                "{tag} NO [BADEVENT] flag changes not supported\r\n",
+                "{tag} NO [NOTIFICATIONOVERFLOW] too many mailboxes\r\n",
+                "{tag} OK NOTIFY completed\r\n* OK [NOTIFICATIONOVERFLOW] lost\r\n",
+            ],
+        )
+        .await;
+
+        let client = Client::insecure("127.0.0.1", port).await.unwrap();
+        let mut client = ImapNotifyClient::new(client).await.unwrap();
+        assert!(client.set(&[String::from("Work")]).await.unwrap());
+        assert_eq!(client.next().await.unwrap(), ImapNotification::Overflow);
+    }
+
+    #[test]
+    fn parse_mailboxes() {
+        assert_eq!(parse_mailbox(b"INBOX (MESSAGES 1)").unwrap(), "INBOX");
+        assert_eq!(
+            parse_mailbox(b"\"a \\\"b\\\"\" (UIDNEXT 2)").unwrap(),
+            "a \"b\""
+        );
+        assert_eq!(parse_mailbox(b"{3}\r\nabc (MESSAGES 1)").unwrap(), "abc");
+    }
+}
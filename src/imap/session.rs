@@ -0,0 +1,241 @@
+//! # IMAP session
+//!
+//! Module dedicated to raw IMAP sessions. Sessions are opened and
+//! authenticated by email-lib, then commands and responses are
+//! written and read directly on the underlying stream, for the
+//! extensions that neither email-lib nor imap-client expose.
+
+use std::time::Duration;
+
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+use imap_client::client::tokio::{Client, MaybeTlsStream};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    time::timeout,
+};
+use tracing::trace;
+
+/// The maximum duration of a network operation.
+pub const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum size of a response line.
+const LINE_LIMIT: usize = 64 * 1024;
+
+/// The maximum size of a response, literals included.
+const RESPONSE_LIMIT: usize = 1024 * 1024;
+
+/// The completion result of a command.
+pub enum Completion {
+    Ok,
+    Rejected(String),
+}
+
+/// The raw IMAP session.
+pub struct ImapSession {
+    stream: BufReader<MaybeTlsStream>,
+    next_tag: u32,
+}
+
+impl ImapSession {
+    /// Take over the stream of the given authenticated session.
+    ///
+    /// The session should not have any command in progress. Input
+    /// already read by the session is kept in its state, which
+    /// imap-client does not expose: a NOOP is completed through the
+    /// session first, so that all the responses sent until then are
+    /// consumed by the session and the stream is taken over at a
+    /// response boundary. Servers have nothing to send after that, no
+    /// mailbox being selected.
+    pub async fn new(mut client: Client) -> Result<Self> {
+        timeout(IO_TIMEOUT, client.noop())
+            .await
+            .map_err(|_| eyre!("cannot send IMAP NOOP command: timed out"))?
+            .wrap_err("cannot send IMAP NOOP command")?;
+
+        Ok(Self {
+            stream: BufReader::new(client.stream.into_inner()),
+            next_tag: 1,
+        })
+    }
+
+    /// Wait for input from the server.
+    ///
+    /// Waiting does not consume anything, so the wait can be
+    /// interrupted safely.
+    pub async fn readable(&mut self) -> Result<()> {
+        match self.stream.fill_buf().await {
+            Ok([]) => bail!("IMAP connection closed by server"),
+            Ok(_) => Ok(()),
+            Err(err) => Err(eyre!(err).wrap_err("cannot read IMAP response")),
+        }
+    }
+
+    /// Send the given command, then wait for its completion.
+    ///
+    /// Untagged responses received in the meantime are ignored.
+    pub async fn command(&mut self, cmd: &str) -> Result<Completion> {
+        let tag = self.send(cmd).await?;
+        self.complete(&tag).await
+    }
+
+    /// Wait for the completion of the command of the given tag.
+    ///
+    /// Untagged responses received in the meantime are ignored.
+    pub async fn complete(&mut self, tag: &str) -> Result<Completion> {
+        let prefix = format!("{tag} ");
+
+        loop {
+            let response = self.read_response().await?;
+
+            let Some(rest) = response.strip_prefix(prefix.as_bytes()) else {
+                continue;
+            };
+
+            let text = String::from_utf8_lossy(rest).trim().to_owned();
+
+            if text.to_ascii_uppercase().starts_with("OK") {
+                break Ok(Completion::Ok);
+            } else {
+                break Ok(Completion::Rejected(text));
+            }
+        }
+    }
+
+    /// Send the given command, returning its tag.
+    pub async fn send(&mut self, cmd: &str) -> Result<String> {
+        let tag = format!("N{}", self.next_tag);
+        self.next_tag += 1;
+        self.send_line(&format!("{tag} {cmd}")).await?;
+        Ok(tag)
+    }
+
+    /// Send the given line, without tag.
+    pub async fn send_line(&mut self, line: &str) -> Result<()> {
+        trace!("IMAP >> {line}");
+
+        let line = format!("{line}\r\n");
+        let stream = self.stream.get_mut();
+
+        timeout(IO_TIMEOUT, async {
+            stream.write_all(line.as_bytes()).await?;
+            stream.flush().await
+        })
+        .await
+        .map_err(|_| eyre!("cannot send IMAP command: timed out"))?
+        .wrap_err("cannot send IMAP command")
+    }
+
+    /// Read a whole response, literals included.
+    pub async fn read_response(&mut self) -> Result<Vec<u8>> {
+        let mut response = Vec::new();
+
+        loop {
+            let line = self.read_line().await?;
+            response.extend_from_slice(&line);
+
+            let Some(len) = literal_len(&line) else {
+                trace!("IMAP << {}", String::from_utf8_lossy(&response).trim_end());
+                break Ok(response);
+            };
+
+            if response.len() + len > RESPONSE_LIMIT {
+                bail!("IMAP response exceeds {RESPONSE_LIMIT} bytes");
+            }
+
+            let mut literal = vec![0; len];
+
+            timeout(IO_TIMEOUT, self.stream.read_exact(&mut literal))
+                .await
+                .map_err(|_| eyre!("cannot read IMAP response: timed out"))?
+                .wrap_err("cannot read IMAP response")?;
+
+            response.extend(literal);
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut reader = (&mut self.stream).take(LINE_LIMIT as u64);
+
+        let n = timeout(IO_TIMEOUT, reader.read_until(b'\n', &mut line))
+            .await
+            .map_err(|_| eyre!("cannot read IMAP response: timed out"))?
+            .wrap_err("cannot read IMAP response")?;
+
+        if n == 0 {
+            bail!("IMAP connection closed by server");
+        }
+
+        if !line.ends_with(b"\n") {
+            bail!("IMAP response line exceeds {LINE_LIMIT} bytes");
+        }
+
+        Ok(line)
+    }
+}
+
+/// Quote the given mailbox name.
+pub fn quote(mailbox: &str) -> String {
+    let mailbox = mailbox.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{mailbox}\"")
+}
+
+/// Get the length of the literal announced at the end of the given
+/// line, if any.
+fn literal_len(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\n")?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_suffix(b"}")?;
+    let start = line.iter().rposition(|b| *b == b'{')?;
+    let len = std::str::from_utf8(&line[start + 1..]).ok()?;
+    len.trim_end_matches('+').parse().ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Start a server answering the given responses, each one to the
+    /// next line received. Responses are sent with the tag of the
+    /// line in place of `{tag}`.
+    pub async fn serve(greeting: &'static str, responses: Vec<&'static str>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.write_all(greeting.as_bytes()).await.unwrap();
+
+            for response in responses {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let tag = line.split(' ').next().unwrap();
+                let response = response.replace("{tag}", tag);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+
+            // keep the connection open until the client closes it
+            let mut line = String::new();
+            let _ = stream.read_line(&mut line).await;
+        });
+
+        port
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(literal_len(b"* STATUS {12}\r\n"), Some(12));
+        assert_eq!(literal_len(b"* STATUS {12+}\r\n"), Some(12));
+        assert_eq!(literal_len(b"* STATUS INBOX ()\r\n"), None);
+        assert_eq!(quote("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+    }
+}
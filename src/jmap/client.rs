@@ -0,0 +1,994 @@
+//! # JMAP client
+//!
+//! Module dedicated to the JMAP client. Clients are built by a
+//! [`JmapClientBuilder`], which can resolve credentials once so that
+//! new sessions can be opened on reconnection without executing
+//! password commands again.
+
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use color_eyre::{
+    eyre::{bail, eyre, OptionExt, WrapErr},
+    Result,
+};
+use email::envelope::{Address, Envelope, Flag, Flags};
+use http::{
+    ureq::{
+        http::{Method, Request, Response},
+        Body,
+    },
+    Client,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::task::spawn_blocking;
+use tracing::debug;
+
+use crate::watch::event::EnvelopesMap;
+
+use super::{config::JmapConfig, push::JmapEventSource};
+
+/// The JMAP core capability.
+pub const CORE_CAPABILITY: &str = "urn:ietf:params:jmap:core";
+
+/// The JMAP mail capability.
+pub const MAIL_CAPABILITY: &str = "urn:ietf:params:jmap:mail";
+
+/// The maximum duration of a request, push requests excepted.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum size of a response body.
+const BODY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// The number of emails fetched per API request, which is also the
+/// maximum number of changes requested at once.
+const PAGE_SIZE: usize = 256;
+
+/// The mailbox properties needed to find mailboxes.
+const MAILBOX_PROPERTIES: &[&str] = &["id", "name", "parentId", "role", "totalEmails"];
+
+/// The email properties needed to build envelopes.
+const ENVELOPE_PROPERTIES: &[&str] = &[
+    "id",
+    "messageId",
+    "inReplyTo",
+    "from",
+    "to",
+    "subject",
+    "sentAt",
+    "receivedAt",
+    "keywords",
+    "hasAttachment",
+];
+
+/// The JMAP client builder.
+#[derive(Clone, Debug)]
+pub struct JmapClientBuilder {
+    config: Arc<JmapConfig>,
+    authorization: Option<String>,
+}
+
+impl JmapClientBuilder {
+    pub fn new(config: Arc<JmapConfig>) -> Self {
+        Self {
+            config,
+            authorization: None,
+        }
+    }
+
+    /// Resolve credentials now, instead of at every build.
+    pub async fn with_prebuilt_credentials(mut self) -> Result<Self> {
+        self.authorization = Some(self.config.build_authorization().await?);
+        Ok(self)
+    }
+
+    /// Fetch the JMAP session, then build the client.
+    pub async fn build(self) -> Result<JmapClient> {
+        let authorization = match self.authorization {
+            Some(authorization) => authorization,
+            None => self.config.build_authorization().await?,
+        };
+
+        let http = Client::new();
+        let url = self.config.session_url.clone();
+
+        let res = send(&http, &authorization, Method::GET, &url, None).await?;
+        let session: JmapSession = read_json(res)
+            .await
+            .wrap_err_with(|| format!("cannot get JMAP session at {url}"))?;
+
+        let Some(account_id) = session.primary_accounts.get(MAIL_CAPABILITY).cloned() else {
+            bail!("JMAP server at {url} does not support mails");
+        };
+
+        debug!(
+            url,
+            username = session.username,
+            account_id,
+            "JMAP session opened"
+        );
+
+        Ok(JmapClient {
+            http,
+            authorization,
+            session,
+            account_id,
+        })
+    }
+}
+
+/// The JMAP session resource.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8620#section-2>.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapSession {
+    pub username: String,
+    pub api_url: String,
+    pub download_url: String,
+    #[serde(default)]
+    pub event_source_url: String,
+    pub primary_accounts: HashMap<String, String>,
+}
+
+/// The JMAP mailbox.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapMailbox {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub role: Option<String>,
+    #[serde(default)]
+    pub total_emails: usize,
+}
+
+/// The changes of a JMAP data type since a given state.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8620#section-5.2>.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct JmapChanges {
+    /// The state the changes lead to.
+    pub state: String,
+
+    /// The identifiers of created or updated objects.
+    pub updated: Vec<String>,
+
+    /// The identifiers of destroyed objects.
+    pub destroyed: Vec<String>,
+
+    /// The properties of updated objects that changed, when the
+    /// server tells that only some of them did (`Mailbox/changes`).
+    pub updated_properties: Option<Vec<String>>,
+}
+
+impl JmapChanges {
+    /// Tell whether the given object changed.
+    pub fn contains(&self, id: &str) -> bool {
+        self.updated.iter().chain(&self.destroyed).any(|i| i == id)
+    }
+}
+
+/// The JMAP method error.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8620#section-3.6.2>.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JmapMethodError {
+    pub kind: String,
+    pub description: Option<String>,
+}
+
+impl JmapMethodError {
+    /// The error returned by `Foo/changes` methods when the server
+    /// cannot calculate changes since the given state.
+    pub const CANNOT_CALCULATE_CHANGES: &'static str = "cannotCalculateChanges";
+}
+
+impl fmt::Display for JmapMethodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = &self.kind;
+
+        match &self.description {
+            Some(desc) => write!(f, "JMAP method failed with {kind} error: {desc}"),
+            None => write!(f, "JMAP method failed with {kind} error"),
+        }
+    }
+}
+
+impl std::error::Error for JmapMethodError {}
+
+/// The JMAP client.
+#[derive(Clone, Debug)]
+pub struct JmapClient {
+    http: Client,
+    authorization: String,
+    session: JmapSession,
+    account_id: String,
+}
+
+impl JmapClient {
+    pub fn session(&self) -> &JmapSession {
+        &self.session
+    }
+
+    /// The identifier of the primary mail account.
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    /// Execute the given method calls within a single API request.
+    ///
+    /// Returns the arguments of the method responses, in order.
+    pub async fn call(&self, calls: Vec<(&str, Value)>) -> Result<Vec<Value>> {
+        let method_calls: Vec<_> = calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, args))| json!([name, args, i.to_string()]))
+            .collect();
+
+        let body = json!({
+            "using": [CORE_CAPABILITY, MAIL_CAPABILITY],
+            "methodCalls": method_calls,
+        });
+
+        let url = &self.session.api_url;
+        let body = serde_json::to_vec(&body)?;
+        let res = send(
+            &self.http,
+            &self.authorization,
+            Method::POST,
+            url,
+            Some(body),
+        )
+        .await?;
+        let res: ApiResponse = read_json(res).await?;
+
+        res.method_responses
+            .into_iter()
+            .map(|(name, args, _)| {
+                if name != "error" {
+                    return Ok(args);
+                }
+
+                let err = JmapMethodError {
+                    kind: args["type"].as_str().unwrap_or("unknown").to_owned(),
+                    description: args["description"].as_str().map(ToOwned::to_owned),
+                };
+
+                Err(err.into())
+            })
+            .collect()
+    }
+
+    /// Find the mailbox matching the given folder.
+    ///
+    /// Folders match mailboxes by path, where parent names are
+    /// separated by slashes, then by role, so that `INBOX` matches
+    /// the mailbox having the `inbox` role.
+    pub async fn find_mailbox(&self, folder: &str) -> Result<JmapMailbox> {
+        let (_, mailbox) = self.find_mailbox_with_state(folder).await?;
+        Ok(mailbox)
+    }
+
+    /// Find the mailbox matching the given folder, together with the
+    /// current mailbox state.
+    ///
+    /// See [`JmapClient::find_mailbox`].
+    pub async fn find_mailbox_with_state(&self, folder: &str) -> Result<(String, JmapMailbox)> {
+        let args = json!({
+            "accountId": self.account_id,
+            "ids": null,
+            "properties": MAILBOX_PROPERTIES,
+        });
+
+        let [mut res] = self.call_n([("Mailbox/get", args)]).await?;
+        let state: String = from_value(&mut res, "state")?;
+        let mailboxes: Vec<JmapMailbox> = from_value(&mut res, "list")?;
+        let by_id: HashMap<_, _> = mailboxes.iter().map(|m| (m.id.as_str(), m)).collect();
+
+        let path = |mailbox: &JmapMailbox| {
+            let mut names = vec![mailbox.name.as_str()];
+            let mut parent_id = mailbox.parent_id.as_deref();
+
+            // the length guard prevents infinite loops on buggy servers
+            while let Some(parent) = parent_id.and_then(|id| by_id.get(id)) {
+                if names.len() > by_id.len() {
+                    break;
+                }
+
+                names.push(&parent.name);
+                parent_id = parent.parent_id.as_deref();
+            }
+
+            names.reverse();
+            names.join("/")
+        };
+
+        let by_path = mailboxes.iter().find(|m| path(m) == folder);
+
+        let by_role = || {
+            mailboxes.iter().find(|m| {
+                m.role
+                    .as_ref()
+                    .is_some_and(|role| role.eq_ignore_ascii_case(folder))
+            })
+        };
+
+        match by_path.or_else(by_role) {
+            Some(mailbox) => Ok((state, mailbox.clone())),
+            None => bail!("cannot find JMAP mailbox matching folder {folder}"),
+        }
+    }
+
+    /// Get the changes of the given data type (`Email`, `Mailbox`…)
+    /// since the given state.
+    ///
+    /// Returns `None` if the server cannot calculate the changes,
+    /// usually because the state is too old, in which case the data
+    /// needs to be fetched again.
+    pub async fn changes(&self, data_type: &str, since_state: &str) -> Result<Option<JmapChanges>> {
+        let method = format!("{data_type}/changes");
+        let mut changes = JmapChanges {
+            state: since_state.to_owned(),
+            ..Default::default()
+        };
+        let mut all_properties = false;
+
+        loop {
+            let args = json!({
+                "accountId": self.account_id,
+                "sinceState": changes.state,
+                "maxChanges": PAGE_SIZE,
+            });
+
+            let mut res = match self.call_n([(method.as_str(), args)]).await {
+                Ok([res]) => res,
+                Err(err) if is_cannot_calculate_changes(&err) => {
+                    debug!(since_state, "cannot calculate JMAP {data_type} changes");
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            };
+
+            changes.state = from_value(&mut res, "newState")?;
+
+            for property in ["created", "updated"] {
+                let ids: Vec<String> = from_value(&mut res, property)?;
+                changes.updated.extend(ids);
+            }
+
+            let ids: Vec<String> = from_value(&mut res, "destroyed")?;
+            changes.destroyed.extend(ids);
+
+            // properties are only relevant if all the pages have them
+            match from_value::<Option<Vec<String>>>(&mut res, "updatedProperties")? {
+                Some(properties) if !all_properties => changes
+                    .updated_properties
+                    .get_or_insert_with(Vec::new)
+                    .extend(properties),
+                _ => {
+                    all_properties = true;
+                    changes.updated_properties = None;
+                }
+            }
+
+            if !from_value::<bool>(&mut res, "hasMoreChanges")? {
+                break;
+            }
+        }
+
+        Ok(Some(changes))
+    }
+
+    /// Update the given envelopes of the given mailbox, using the
+    /// email changes since the given state.
+    ///
+    /// Only emails that changed are fetched. Returns the next email
+    /// state together with the updated envelopes, or `None` if the
+    /// server cannot calculate the changes, in which case the
+    /// mailbox needs to be listed again (see
+    /// [`JmapClient::list_envelopes`]).
+    pub async fn update_envelopes(
+        &self,
+        mailbox_id: &str,
+        since_state: &str,
+        envelopes: &EnvelopesMap,
+    ) -> Result<Option<(String, EnvelopesMap)>> {
+        let Some(changes) = self.changes("Email", since_state).await? else {
+            return Ok(None);
+        };
+
+        let mut envelopes = envelopes.clone();
+
+        for id in &changes.destroyed {
+            envelopes.remove(id);
+        }
+
+        let mut properties = ENVELOPE_PROPERTIES.to_vec();
+        properties.push("mailboxIds");
+
+        for ids in changes.updated.chunks(PAGE_SIZE) {
+            let args = json!({
+                "accountId": self.account_id,
+                "ids": ids,
+                "properties": properties,
+            });
+
+            let [mut res] = self.call_n([("Email/get", args)]).await?;
+            let emails: Vec<JmapEmail> = from_value(&mut res, "list")?;
+
+            // emails destroyed in the meantime are not found
+            for id in ids {
+                envelopes.remove(id);
+            }
+
+            for email in emails {
+                if email.mailbox_ids.get(mailbox_id) == Some(&true) {
+                    envelopes.insert(email.id.clone(), Envelope::from(email));
+                }
+            }
+        }
+
+        Ok(Some((changes.state, envelopes)))
+    }
+
+    /// List the envelopes of the given mailbox, together with the
+    /// current email state.
+    pub async fn list_envelopes(&self, mailbox_id: &str) -> Result<(String, EnvelopesMap)> {
+        let mut envelopes = EnvelopesMap::new();
+        let mut state = None;
+        let mut position = 0;
+
+        loop {
+            let page = self
+                .query_envelopes(mailbox_id, position, PAGE_SIZE)
+                .await?;
+            let count = page.envelopes.len();
+
+            // only the state of the first page is kept, so that
+            // changes happening while paginating are fetched again
+            state.get_or_insert(page.state);
+            position += count;
+
+            for envelope in page.envelopes {
+                envelopes.insert(envelope.id.clone(), envelope);
+            }
+
+            let done = match page.total {
+                Some(total) => position >= total,
+                None => count < PAGE_SIZE,
+            };
+
+            if count == 0 || done {
+                break;
+            }
+        }
+
+        Ok((state.unwrap_or_default(), envelopes))
+    }
+
+    /// Find the most recent envelope of the given mailbox.
+    pub async fn find_newest(&self, mailbox_id: &str) -> Result<Option<Envelope>> {
+        let page = self.query_envelopes(mailbox_id, 0, 1).await?;
+        Ok(page.envelopes.into_iter().next())
+    }
+
+    /// Download the raw message of the given email.
+    pub async fn read_msg(&self, email_id: &str) -> Result<Vec<u8>> {
+        let args = json!({
+            "accountId": self.account_id,
+            "ids": [email_id],
+            "properties": ["blobId"],
+        });
+
+        let [mut res] = self.call_n([("Email/get", args)]).await?;
+        let emails: Vec<BlobRef> = from_value(&mut res, "list")?;
+        let blob_id = emails
+            .into_iter()
+            .next()
+            .ok_or_eyre(format!("cannot find JMAP email {email_id}"))?
+            .blob_id;
+
+        let url = expand(
+            &self.session.download_url,
+            &[
+                ("accountId", &self.account_id),
+                ("blobId", &blob_id),
+                ("type", "message/rfc822"),
+                ("name", "message.eml"),
+            ],
+        );
+
+        let res = send(&self.http, &self.authorization, Method::GET, &url, None).await?;
+        read_body(res)
+            .await
+            .wrap_err_with(|| format!("cannot download JMAP email {email_id}"))
+    }
+
+    /// Subscribe to email and mailbox changes, using the EventSource
+    /// push channel.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8620#section-7.3>.
+    pub async fn subscribe(&self) -> Result<JmapEventSource> {
+        let template = &self.session.event_source_url;
+
+        if template.is_empty() {
+            bail!("JMAP server does not support EventSource push");
+        }
+
+        let ping = JmapEventSource::PING_INTERVAL.as_secs().to_string();
+        let url = expand(
+            template,
+            &[
+                ("types", "Email,Mailbox"),
+                ("closeafter", "no"),
+                ("ping", &ping),
+            ],
+        );
+
+        let authorization = self.authorization.clone();
+        let uri = url.clone();
+
+        let res = self
+            .http
+            .send(move |agent| {
+                let req = Request::builder()
+                    .method(Method::GET)
+                    .uri(uri)
+                    .header("authorization", authorization)
+                    .header("accept", "text/event-stream")
+                    .header("cache-control", "no-cache")
+                    .body(())?;
+
+                // the body is an endless stream, only the response
+                // head is expected in time
+                let req = agent
+                    .configure_request(req)
+                    .http_status_as_error(false)
+                    .timeout_connect(Some(REQUEST_TIMEOUT))
+                    .timeout_recv_response(Some(REQUEST_TIMEOUT))
+                    .build();
+
+                agent.run(req)
+            })
+            .await
+            .map_err(|err| eyre!(err).wrap_err(format!("cannot connect to {url}")))?;
+
+        let res = check_status(res, &url).await?;
+        debug!(url, "JMAP EventSource opened");
+        Ok(JmapEventSource::new(res.into_body()))
+    }
+
+    /// Execute exactly N method calls.
+    async fn call_n<const N: usize>(&self, calls: [(&str, Value); N]) -> Result<[Value; N]> {
+        let res = self.call(calls.into()).await?;
+        let count = res.len();
+
+        res.try_into()
+            .map_err(|_| eyre!("expected {N} JMAP method responses, got {count}"))
+    }
+
+    /// Query a page of envelopes of the given mailbox, from the
+    /// most recent to the oldest.
+    async fn query_envelopes(
+        &self,
+        mailbox_id: &str,
+        position: usize,
+        limit: usize,
+    ) -> Result<EnvelopesPage> {
+        let query = json!({
+            "accountId": self.account_id,
+            "filter": { "inMailbox": mailbox_id },
+            "sort": [{ "property": "receivedAt", "isAscending": false }],
+            "position": position,
+            "limit": limit,
+            "calculateTotal": true,
+        });
+
+        let get = json!({
+            "accountId": self.account_id,
+            "#ids": { "resultOf": "0", "name": "Email/query", "path": "/ids" },
+            "properties": ENVELOPE_PROPERTIES,
+        });
+
+        let [query, mut get] = self
+            .call_n([("Email/query", query), ("Email/get", get)])
+            .await?;
+        let total = query["total"].as_u64().map(|total| total as usize);
+        let state = from_value(&mut get, "state")?;
+        let emails: Vec<JmapEmail> = from_value(&mut get, "list")?;
+
+        Ok(EnvelopesPage {
+            total,
+            state,
+            envelopes: emails.into_iter().map(Envelope::from).collect(),
+        })
+    }
+}
+
+/// A page of envelopes.
+struct EnvelopesPage {
+    total: Option<usize>,
+    state: String,
+    envelopes: Vec<Envelope>,
+}
+
+/// The JMAP API response.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiResponse {
+    method_responses: Vec<(String, Value, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlobRef {
+    blob_id: String,
+}
+
+/// The JMAP email, restricted to [`ENVELOPE_PROPERTIES`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JmapEmail {
+    id: String,
+    message_id: Option<Vec<String>>,
+    in_reply_to: Option<Vec<String>>,
+    from: Option<Vec<JmapAddress>>,
+    to: Option<Vec<JmapAddress>>,
+    subject: Option<String>,
+    sent_at: Option<String>,
+    received_at: Option<String>,
+    #[serde(default)]
+    keywords: HashMap<String, bool>,
+    #[serde(default)]
+    has_attachment: bool,
+    /// Only fetched to filter changed emails.
+    #[serde(default)]
+    mailbox_ids: HashMap<String, bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JmapAddress {
+    name: Option<String>,
+    email: String,
+}
+
+impl From<JmapAddress> for Address {
+    fn from(addr: JmapAddress) -> Self {
+        Address::new(addr.name.filter(|name| !name.is_empty()), addr.email)
+    }
+}
+
+impl From<JmapEmail> for Envelope {
+    fn from(email: JmapEmail) -> Self {
+        let first_id = |ids: Option<Vec<String>>| {
+            let id = ids?.into_iter().next()?;
+            Some(format!("<{id}>"))
+        };
+
+        let first_addr = |addrs: Option<Vec<JmapAddress>>| {
+            addrs
+                .and_then(|addrs| addrs.into_iter().next())
+                .map(Address::from)
+                .unwrap_or_default()
+        };
+
+        // keywords are IMAP flags, with $ instead of \
+        let flags: Flags = email
+            .keywords
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(keyword, _)| Flag::from(keyword.strip_prefix('$').unwrap_or(&keyword)))
+            .collect();
+
+        let date = email
+            .sent_at
+            .or(email.received_at)
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(&date).ok())
+            .unwrap_or_default();
+
+        Envelope {
+            message_id: first_id(email.message_id).unwrap_or_default(),
+            in_reply_to: first_id(email.in_reply_to),
+            id: email.id,
+            flags,
+            from: first_addr(email.from),
+            to: first_addr(email.to),
+            subject: email.subject.unwrap_or_default(),
+            date,
+            has_attachment: email.has_attachment,
+        }
+    }
+}
+
+/// Send the given request, with a timeout.
+///
+/// Responses with an unsuccessful status are turned into errors.
+async fn send(
+    http: &Client,
+    authorization: &str,
+    method: Method,
+    url: &str,
+    body: Option<Vec<u8>>,
+) -> Result<Response<Body>> {
+    let authorization = authorization.to_owned();
+    let uri = url.to_owned();
+
+    let res = http
+        .send(move |agent| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", authorization)
+                .header("accept", "application/json");
+
+            match body {
+                Some(body) => {
+                    let req = req.header("content-type", "application/json").body(body)?;
+                    let req = agent
+                        .configure_request(req)
+                        .http_status_as_error(false)
+                        .timeout_global(Some(REQUEST_TIMEOUT))
+                        .build();
+                    agent.run(req)
+                }
+                None => {
+                    let req = agent
+                        .configure_request(req.body(())?)
+                        .http_status_as_error(false)
+                        .timeout_global(Some(REQUEST_TIMEOUT))
+                        .build();
+                    agent.run(req)
+                }
+            }
+        })
+        .await
+        .map_err(|err| eyre!(err).wrap_err(format!("cannot send JMAP request to {url}")))?;
+
+    check_status(res, url).await
+}
+
+/// Turn responses with an unsuccessful status into errors.
+///
+/// The error contains the problem details sent by the server, if
+/// any.
+async fn check_status(res: Response<Body>, url: &str) -> Result<Response<Body>> {
+    let status = res.status();
+
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let details = read_body(res).await.unwrap_or_default();
+    let details = String::from_utf8_lossy(&details);
+    let details = details.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if details.is_empty() {
+        bail!("JMAP server at {url} answered with status {status}");
+    }
+
+    bail!("JMAP server at {url} answered with status {status}: {details}")
+}
+
+/// Read the whole response body, in the background.
+async fn read_body(res: Response<Body>) -> Result<Vec<u8>> {
+    let body = spawn_blocking(move || {
+        res.into_body()
+            .into_with_config()
+            .limit(BODY_LIMIT)
+            .read_to_vec()
+    })
+    .await??;
+
+    Ok(body)
+}
+
+/// Read the whole response body as JSON.
+async fn read_json<T: DeserializeOwned>(res: Response<Body>) -> Result<T> {
+    let body = read_body(res).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Tell whether the given error is a `cannotCalculateChanges` method
+/// error.
+fn is_cannot_calculate_changes(err: &color_eyre::Report) -> bool {
+    matches!(
+        err.downcast_ref::<JmapMethodError>(),
+        Some(err) if err.kind == JmapMethodError::CANNOT_CALCULATE_CHANGES
+    )
+}
+
+/// Deserialize the given property of the given JMAP object.
+fn from_value<T: DeserializeOwned>(value: &mut Value, property: &str) -> Result<T> {
+    let value = value.get_mut(property).map(Value::take).unwrap_or_default();
+    serde_json::from_value(value).wrap_err_with(|| format!("cannot parse JMAP property {property}"))
+}
+
+/// Expand the given URL template, see RFC 6570 (level 1 only).
+fn expand(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut url = template.to_owned();
+
+    for (name, value) in vars {
+        url = url.replace(&format!("{{{name}}}"), &percent_encode(value));
+    }
+
+    url
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use email::flag::Flag;
+
+    use crate::jmap::mock::JmapServer;
+
+    use super::*;
+
+    fn ids(envelopes: &EnvelopesMap) -> Vec<&str> {
+        let mut ids: Vec<_> = envelopes.keys().map(String::as_str).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn find_mailbox() {
+        let server = JmapServer::start().await;
+        server.create_mailbox("archive", "Archive", None);
+        let client = server.client().await;
+
+        let (state, mailbox) = client.find_mailbox_with_state("INBOX").await.unwrap();
+        assert_eq!(state, "2");
+        assert_eq!(mailbox.id, "inbox");
+
+        let mailbox = client.find_mailbox("Archive").await.unwrap();
+        assert_eq!(mailbox.id, "archive");
+
+        let err = client.find_mailbox("Trash").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot find JMAP mailbox matching folder Trash"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_envelopes() {
+        let server = JmapServer::start().await;
+        server.create_mailbox("archive", "Archive", None);
+        server.add_email("e1", "inbox", "first");
+        server.add_email("e2", "archive", "second");
+        server.add_email("e3", "inbox", "third");
+        let client = server.client().await;
+
+        let (state, envelopes) = client.list_envelopes("inbox").await.unwrap();
+        assert_eq!(state, "3");
+        assert_eq!(ids(&envelopes), ["e1", "e3"]);
+        assert_eq!(envelopes["e3"].subject, "third");
+        assert_eq!(envelopes["e3"].from.addr, "alice@localhost");
+    }
+
+    #[tokio::test]
+    async fn update_envelopes() {
+        let server = JmapServer::start().await;
+        server.create_mailbox("archive", "Archive", None);
+        server.add_email("e1", "inbox", "first");
+        server.add_email("e2", "inbox", "second");
+        server.add_email("e3", "inbox", "third");
+        let client = server.client().await;
+        let (state, envelopes) = client.list_envelopes("inbox").await.unwrap();
+
+        server.add_email("e4", "inbox", "fourth");
+        server.add_email("e5", "archive", "fifth");
+        server.move_email("e1", "archive");
+        server.mark_seen("e2");
+        server.destroy_email("e3");
+        server.take_calls();
+
+        let (state, envelopes) = client
+            .update_envelopes("inbox", &state, &envelopes)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(state, "8");
+        assert_eq!(ids(&envelopes), ["e2", "e4"]);
+        assert!(envelopes["e2"].flags.contains(&Flag::Seen));
+        assert!(!envelopes["e4"].flags.contains(&Flag::Seen));
+
+        // changes are paginated, and the mailbox is not listed again
+        assert_eq!(
+            server.take_calls(),
+            [
+                "Email/changes",
+                "Email/changes",
+                "Email/changes",
+                "Email/get"
+            ]
+        );
+
+        let (_, unchanged) = client
+            .update_envelopes("inbox", &state, &envelopes)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(unchanged, envelopes);
+    }
+
+    #[tokio::test]
+    async fn update_envelopes_cannot_calculate_changes() {
+        let server = JmapServer::start().await;
+        server.add_email("e1", "inbox", "first");
+        let client = server.client().await;
+        let (state, envelopes) = client.list_envelopes("inbox").await.unwrap();
+
+        server.add_email("e2", "inbox", "second");
+        server.forget_changes();
+
+        let updated = client
+            .update_envelopes("inbox", &state, &envelopes)
+            .await
+            .unwrap();
+
+        assert_eq!(updated, None);
+    }
+
+    #[tokio::test]
+    async fn mailbox_changes() {
+        let server = JmapServer::start().await;
+        let client = server.client().await;
+        let (state, _) = client.find_mailbox_with_state("INBOX").await.unwrap();
+
+        server.add_email("e1", "inbox", "first");
+        let changes = client.changes("Mailbox", &state).await.unwrap().unwrap();
+        assert_eq!(changes.updated, ["inbox"]);
+        assert!(changes.contains("inbox"));
+        assert_eq!(
+            changes.updated_properties.unwrap(),
+            ["totalEmails", "unreadEmails"]
+        );
+
+        server.rename_mailbox("inbox", "Renamed");
+        let changes = client.changes("Mailbox", &state).await.unwrap().unwrap();
+        assert_eq!(changes.state, "3");
+        assert_eq!(changes.updated, ["inbox", "inbox"]);
+        assert_eq!(changes.updated_properties, None);
+    }
+
+    #[tokio::test]
+    async fn method_error() {
+        let server = JmapServer::start().await;
+        let client = server.client().await;
+
+        let err = client.call(vec![("Foo/get", json!({}))]).await.unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<JmapMethodError>(),
+            Some(&JmapMethodError {
+                kind: String::from("unknownMethod"),
+                description: None,
+            })
+        );
+        assert!(!is_cannot_calculate_changes(&err));
+    }
+
+    #[tokio::test]
+    async fn read_msg() {
+        let server = JmapServer::start().await;
+        server.add_email("e1", "inbox", "first");
+        let client = server.client().await;
+
+        let msg = client.read_msg("e1").await.unwrap();
+        assert!(String::from_utf8(msg).unwrap().contains("Subject: first"));
+    }
+}
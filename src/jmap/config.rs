@@ -0,0 +1,98 @@
+//! # JMAP configuration
+//!
+//! Module dedicated to the JMAP backend configuration.
+
+use std::io;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use color_eyre::{
+    eyre::{OptionExt, WrapErr},
+    Result,
+};
+use email::account::config::passwd::PasswordConfig;
+use serde::{Deserialize, Serialize};
+
+/// The JMAP backend configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JmapConfig {
+    /// The URL of the JMAP session resource, for example
+    /// `https://api.fastmail.com/jmap/session`.
+    ///
+    /// Servers supporting autodiscovery expose it at
+    /// `https://<domain>/.well-known/jmap`.
+    pub session_url: String,
+
+    /// The JMAP login, required by the password authentication.
+    pub login: Option<String>,
+
+    /// The JMAP authentication configuration.
+    pub auth: JmapAuthConfig,
+}
+
+impl JmapConfig {
+    /// Build the value of the `Authorization` header.
+    ///
+    /// Secrets are resolved by this function, which means that
+    /// password commands are executed.
+    pub async fn build_authorization(&self) -> Result<String> {
+        match &self.auth {
+            JmapAuthConfig::Password(passwd) => {
+                let login = self
+                    .login
+                    .as_ref()
+                    .ok_or_eyre("missing JMAP login, please define it using login")?;
+                let passwd = passwd.get().await.wrap_err("cannot get JMAP password")?;
+                let passwd = passwd.lines().next().unwrap_or_default();
+                let credentials = BASE64.encode(format!("{login}:{passwd}"));
+                Ok(format!("Basic {credentials}"))
+            }
+            JmapAuthConfig::Bearer(token) => {
+                let token = token.get().await.wrap_err("cannot get JMAP token")?;
+                let token = token.lines().next().unwrap_or_default();
+                Ok(format!("Bearer {token}"))
+            }
+        }
+    }
+}
+
+/// The JMAP authentication configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+pub enum JmapAuthConfig {
+    /// The basic authentication, based on the login and the given
+    /// password.
+    Password(PasswordConfig),
+
+    /// The bearer authentication, based on the given token (also
+    /// known as API token or app token).
+    Bearer(PasswordConfig),
+}
+
+impl JmapAuthConfig {
+    /// The secret, whatever the authentication.
+    fn secret(&self) -> &PasswordConfig {
+        match self {
+            Self::Password(passwd) => passwd,
+            Self::Bearer(token) => token,
+        }
+    }
+
+    /// The human-readable name of the secret.
+    pub fn secret_name(&self) -> &'static str {
+        match self {
+            Self::Password(_) => "JMAP password",
+            Self::Bearer(_) => "JMAP token",
+        }
+    }
+
+    /// Delete the secret, if it is stored in the keyring.
+    pub async fn reset(&self) -> Result<()> {
+        Ok(self.secret().reset().await?)
+    }
+
+    /// Define the secret, only if it does not exist in the keyring.
+    pub async fn configure(&self, get_secret: impl Fn() -> io::Result<String>) -> Result<()> {
+        Ok(self.secret().configure(get_secret).await?)
+    }
+}
@@ -0,0 +1,507 @@
+//! # JMAP stand-in
+//!
+//! Module dedicated to testing JMAP features against a minimal local
+//! JMAP server. Only the methods used by the [`client`] are served,
+//! data is kept in memory and states are simple counters, so that
+//! tests can change emails and mailboxes then push state changes.
+//!
+//! [`client`]: super::client
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use super::{
+    client::{JmapClient, JmapClientBuilder},
+    config::JmapConfig,
+};
+
+/// The maximum number of changes returned at once, kept low so that
+/// paginated changes are tested.
+const MAX_CHANGES: usize = 2;
+
+/// The local JMAP server.
+#[derive(Clone)]
+pub struct JmapServer {
+    url: String,
+    data: Arc<Mutex<Data>>,
+}
+
+#[derive(Default)]
+struct Data {
+    mailboxes: BTreeMap<String, Mailbox>,
+    mailbox_state: usize,
+    mailbox_changes: Vec<Change>,
+    emails: BTreeMap<String, Email>,
+    email_state: usize,
+    email_changes: Vec<Change>,
+    calls: Vec<String>,
+    event_sources: Vec<mpsc::UnboundedSender<String>>,
+}
+
+#[derive(Clone)]
+struct Mailbox {
+    name: String,
+    role: Option<String>,
+}
+
+#[derive(Clone)]
+struct Email {
+    mailbox_id: String,
+    subject: String,
+    seen: bool,
+}
+
+/// A change, leading to the state it is indexed at.
+#[derive(Clone)]
+enum Change {
+    Created(String),
+    Updated(String, Option<Vec<&'static str>>),
+    Destroyed(String),
+}
+
+impl JmapServer {
+    /// Start the server, with an inbox mailbox of identifier `inbox`.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = Self {
+            url,
+            data: Default::default(),
+        };
+
+        server.create_mailbox("inbox", "Inbox", Some("inbox"));
+
+        let server_ref = server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server_ref.clone().serve(stream));
+            }
+        });
+
+        server
+    }
+
+    /// The configuration of a client connecting to this server.
+    pub fn config(&self) -> JmapConfig {
+        let config = format!(
+            "session-url = \"{}/session\"\nauth.type = \"bearer\"\nauth.raw = \"token\"",
+            self.url,
+        );
+
+        toml::from_str(&config).unwrap()
+    }
+
+    /// Build a client connected to this server.
+    pub async fn client(&self) -> JmapClient {
+        JmapClientBuilder::new(Arc::new(self.config()))
+            .build()
+            .await
+            .unwrap()
+    }
+
+    /// The names of the methods called so far, then forget them.
+    pub fn take_calls(&self) -> Vec<String> {
+        std::mem::take(&mut self.data.lock().unwrap().calls)
+    }
+
+    /// Forget past changes, so that the server cannot calculate
+    /// changes since a previous state anymore.
+    pub fn forget_changes(&self) {
+        let mut data = self.data.lock().unwrap();
+        data.mailbox_changes = vec![];
+        data.email_changes = vec![];
+    }
+
+    pub fn create_mailbox(&self, id: &str, name: &str, role: Option<&str>) {
+        let mut data = self.data.lock().unwrap();
+
+        let mailbox = Mailbox {
+            name: name.to_owned(),
+            role: role.map(ToOwned::to_owned),
+        };
+
+        data.mailboxes.insert(id.to_owned(), mailbox);
+        data.change_mailbox(Change::Created(id.to_owned()));
+    }
+
+    pub fn rename_mailbox(&self, id: &str, name: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.mailboxes.get_mut(id).unwrap().name = name.to_owned();
+        data.change_mailbox(Change::Updated(id.to_owned(), None));
+    }
+
+    pub fn add_email(&self, id: &str, mailbox_id: &str, subject: &str) {
+        let mut data = self.data.lock().unwrap();
+
+        let email = Email {
+            mailbox_id: mailbox_id.to_owned(),
+            subject: subject.to_owned(),
+            seen: false,
+        };
+
+        data.emails.insert(id.to_owned(), email);
+        data.change_email(Change::Created(id.to_owned()));
+        data.change_mailbox_counts(mailbox_id);
+    }
+
+    pub fn move_email(&self, id: &str, mailbox_id: &str) {
+        let mut data = self.data.lock().unwrap();
+        let email = data.emails.get_mut(id).unwrap();
+        let prev_mailbox_id = std::mem::replace(&mut email.mailbox_id, mailbox_id.to_owned());
+        data.change_email(Change::Updated(id.to_owned(), None));
+        data.change_mailbox_counts(&prev_mailbox_id);
+        data.change_mailbox_counts(mailbox_id);
+    }
+
+    pub fn mark_seen(&self, id: &str) {
+        let mut data = self.data.lock().unwrap();
+        let email = data.emails.get_mut(id).unwrap();
+        email.seen = true;
+        let mailbox_id = email.mailbox_id.clone();
+        data.change_email(Change::Updated(id.to_owned(), None));
+        data.change_mailbox_counts(&mailbox_id);
+    }
+
+    pub fn destroy_email(&self, id: &str) {
+        let mut data = self.data.lock().unwrap();
+        let email = data.emails.remove(id).unwrap();
+        data.change_email(Change::Destroyed(id.to_owned()));
+        data.change_mailbox_counts(&email.mailbox_id);
+    }
+
+    /// Push the current states to all the opened EventSource
+    /// connections.
+    pub fn push(&self) {
+        let mut data = self.data.lock().unwrap();
+
+        let event = json!({
+            "@type": "StateChange",
+            "changed": {
+                "account": {
+                    "Email": data.email_state.to_string(),
+                    "Mailbox": data.mailbox_state.to_string(),
+                },
+            },
+        });
+
+        let event = format!("event: state\ndata: {event}\n\n");
+        data.event_sources
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Serve a single request, then close the connection.
+    async fn serve(self, stream: TcpStream) {
+        let mut reader = BufReader::new(stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_owned();
+        let path = parts.next().unwrap_or_default().to_owned();
+
+        let mut authorization = String::new();
+        let mut len = 0;
+
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            let line = line.trim_end();
+
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line.split_once(':').unwrap();
+
+            match name.to_lowercase().as_str() {
+                "authorization" => authorization = value.trim().to_owned(),
+                "content-length" => len = value.trim().parse().unwrap(),
+                _ => (),
+            }
+        }
+
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).await.unwrap();
+        let mut stream = reader.into_inner();
+
+        if authorization != "Bearer token" {
+            respond(&mut stream, "401 Unauthorized", "text/plain", b"").await;
+            return;
+        }
+
+        match (method.as_str(), path.as_str()) {
+            ("GET", "/session") => {
+                let session = json!({
+                    "username": "test",
+                    "apiUrl": format!("{}/api", self.url),
+                    "downloadUrl": format!("{}/download/{{accountId}}/{{blobId}}/{{name}}?type={{type}}", self.url),
+                    "eventSourceUrl": format!("{}/events?types={{types}}&closeafter={{closeafter}}&ping={{ping}}", self.url),
+                    "primaryAccounts": { "urn:ietf:params:jmap:mail": "account" },
+                });
+
+                let body = session.to_string();
+                respond(&mut stream, "200 OK", "application/json", body.as_bytes()).await;
+            }
+            ("POST", "/api") => {
+                let req: Value = serde_json::from_slice(&body).unwrap();
+                let res = self.data.lock().unwrap().api(req);
+                let body = res.to_string();
+                respond(&mut stream, "200 OK", "application/json", body.as_bytes()).await;
+            }
+            ("GET", path) if path.starts_with("/events?") => {
+                assert!(path.contains("types=Email%2CMailbox"), "{path}");
+
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                self.data.lock().unwrap().event_sources.push(tx);
+
+                // the body is delimited by the end of the connection
+                let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n";
+                stream.write_all(head.as_bytes()).await.unwrap();
+
+                while let Some(event) = rx.recv().await {
+                    if stream.write_all(event.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            ("GET", path) if path.starts_with("/download/account/") => {
+                let blob_id = path.split('/').nth(3).unwrap();
+                let subject = self
+                    .data
+                    .lock()
+                    .unwrap()
+                    .emails
+                    .get(blob_id)
+                    .map(|email| email.subject.clone());
+
+                match subject {
+                    Some(subject) => {
+                        let msg = format!(
+                            "From: alice@localhost\r\nSubject: {subject}\r\n\r\nHello!\r\n"
+                        );
+                        respond(&mut stream, "200 OK", "message/rfc822", msg.as_bytes()).await;
+                    }
+                    None => respond(&mut stream, "404 Not Found", "text/plain", b"").await,
+                }
+            }
+            _ => respond(&mut stream, "404 Not Found", "text/plain", b"").await,
+        }
+    }
+}
+
+impl Data {
+    fn change_mailbox(&mut self, change: Change) {
+        self.mailbox_changes.push(change);
+        self.mailbox_state += 1;
+    }
+
+    fn change_mailbox_counts(&mut self, id: &str) {
+        let properties = vec!["totalEmails", "unreadEmails"];
+        self.change_mailbox(Change::Updated(id.to_owned(), Some(properties)));
+    }
+
+    fn change_email(&mut self, change: Change) {
+        self.email_changes.push(change);
+        self.email_state += 1;
+    }
+
+    /// Execute the method calls of the given API request.
+    fn api(&mut self, req: Value) -> Value {
+        let mut responses: Vec<Value> = vec![];
+
+        for call in req["methodCalls"].as_array().unwrap() {
+            let name = call[0].as_str().unwrap();
+            let mut args = call[1].clone();
+            let call_id = call[2].clone();
+
+            self.calls.push(name.to_owned());
+
+            // resolve back-references to the ids of previous results
+            if let Some(reference) = args.get("#ids").cloned() {
+                let prev = responses
+                    .iter()
+                    .find(|res| res[2] == reference["resultOf"])
+                    .unwrap();
+                args["ids"] = prev[1]["ids"].clone();
+            }
+
+            let res = match name {
+                "Mailbox/get" => self.get_mailboxes(),
+                "Mailbox/changes" => changes(&self.mailbox_changes, self.mailbox_state, &args),
+                "Email/query" => self.query_emails(&args),
+                "Email/get" => self.get_emails(&args),
+                "Email/changes" => changes(&self.email_changes, self.email_state, &args),
+                _ => Err("unknownMethod"),
+            };
+
+            responses.push(match res {
+                Ok(res) => json!([name, res, call_id]),
+                Err(kind) => json!(["error", { "type": kind }, call_id]),
+            });
+        }
+
+        json!({ "methodResponses": responses, "sessionState": "0" })
+    }
+
+    fn get_mailboxes(&self) -> Result<Value, &'static str> {
+        let list: Vec<_> = self
+            .mailboxes
+            .iter()
+            .map(|(id, mailbox)| {
+                let total = self.emails.values().filter(|e| e.mailbox_id == *id).count();
+
+                json!({
+                    "id": id,
+                    "name": mailbox.name,
+                    "parentId": null,
+                    "role": mailbox.role,
+                    "totalEmails": total,
+                })
+            })
+            .collect();
+
+        Ok(json!({
+            "accountId": "account",
+            "state": self.mailbox_state.to_string(),
+            "list": list,
+            "notFound": [],
+        }))
+    }
+
+    /// Query emails of a mailbox, the last added first.
+    fn query_emails(&self, args: &Value) -> Result<Value, &'static str> {
+        let mailbox_id = args["filter"]["inMailbox"].as_str().unwrap();
+        let position = args["position"].as_u64().unwrap_or(0) as usize;
+        let limit = args["limit"].as_u64().unwrap() as usize;
+
+        let ids: Vec<_> = self
+            .emails
+            .iter()
+            .rev()
+            .filter(|(_, email)| email.mailbox_id == mailbox_id)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let total = ids.len();
+        let ids: Vec<_> = ids.into_iter().skip(position).take(limit).collect();
+
+        Ok(json!({
+            "accountId": "account",
+            "queryState": self.email_state.to_string(),
+            "ids": ids,
+            "position": position,
+            "total": total,
+        }))
+    }
+
+    fn get_emails(&self, args: &Value) -> Result<Value, &'static str> {
+        let mut list = vec![];
+        let mut not_found = vec![];
+
+        for id in args["ids"].as_array().unwrap() {
+            let id = id.as_str().unwrap();
+
+            let Some(email) = self.emails.get(id) else {
+                not_found.push(id);
+                continue;
+            };
+
+            let keywords = if email.seen {
+                json!({ "$seen": true })
+            } else {
+                json!({})
+            };
+
+            list.push(json!({
+                "id": id,
+                "blobId": id,
+                "mailboxIds": { email.mailbox_id.as_str(): true },
+                "from": [{ "name": "Alice", "email": "alice@localhost" }],
+                "subject": email.subject,
+                "receivedAt": "2024-01-01T00:00:00Z",
+                "keywords": keywords,
+            }));
+        }
+
+        Ok(json!({
+            "accountId": "account",
+            "state": self.email_state.to_string(),
+            "list": list,
+            "notFound": not_found,
+        }))
+    }
+}
+
+/// Get the changes since the state given in arguments, where the
+/// change at index `i` leads to the state `state - len + i + 1`.
+fn changes(log: &[Change], state: usize, args: &Value) -> Result<Value, &'static str> {
+    let since_state: usize = args["sinceState"].as_str().unwrap().parse().unwrap();
+    let max_changes = args["maxChanges"].as_u64().unwrap() as usize;
+    let max_changes = max_changes.min(MAX_CHANGES);
+
+    // changes older than the log were forgotten
+    let Some(skip) = since_state.checked_sub(state - log.len()) else {
+        return Err("cannotCalculateChanges");
+    };
+
+    let log: Vec<_> = log.iter().skip(skip).take(max_changes).collect();
+    let new_state = since_state + log.len();
+
+    let mut created = vec![];
+    let mut updated = vec![];
+    let mut destroyed = vec![];
+    let mut properties: Option<Vec<&str>> = Some(vec![]);
+
+    for change in log {
+        match change {
+            Change::Created(id) => created.push(id),
+            Change::Updated(id, Some(props)) => {
+                updated.push(id);
+                if let Some(properties) = &mut properties {
+                    properties.extend(props);
+                }
+            }
+            Change::Updated(id, None) => {
+                updated.push(id);
+                properties = None;
+            }
+            Change::Destroyed(id) => destroyed.push(id),
+        }
+    }
+
+    if !created.is_empty() || !destroyed.is_empty() {
+        properties = None;
+    }
+
+    Ok(json!({
+        "accountId": "account",
+        "oldState": since_state.to_string(),
+        "newState": new_state.to_string(),
+        "hasMoreChanges": new_state < state,
+        "created": created,
+        "updated": updated,
+        "destroyed": destroyed,
+        "updatedProperties": properties,
+    }))
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        body.len(),
+    );
+
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(body).await;
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,16 @@
+//! # JMAP
+//!
+//! Module dedicated to the JMAP protocol, see RFC 8620 and RFC 8621.
+//! Only what is needed to watch mailboxes is implemented: the
+//! [`client`] module opens sessions, lists mailboxes and emails and
+//! downloads messages, and the [`push`] module reads the EventSource
+//! push channel. The [`config`] module contains the backend
+//! configuration.
+
+pub mod client;
+pub mod config;
+#[cfg(test)]
+pub mod mock;
+pub mod push;
+#[cfg(feature = "wizard")]
+pub mod wizard;
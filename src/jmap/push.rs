@@ -0,0 +1,193 @@
+//! # JMAP push
+//!
+//! Module dedicated to the JMAP EventSource push channel, which is a
+//! never-ending `text/event-stream` response sending `state` events
+//! whenever data changes on the server, and `ping` events to keep
+//! the connection alive.
+//!
+//! See <https://www.rfc-editor.org/rfc/rfc8620#section-7.3>.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use http::ureq::Body;
+use serde::Deserialize;
+use tokio::{sync::mpsc, time::timeout};
+use tracing::{debug, trace};
+
+/// The push event.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PushEvent {
+    /// Some data changed on the server.
+    ///
+    /// Contains the new states, indexed by account identifier then
+    /// by data type (`Email`, `Mailbox`…).
+    StateChange(HashMap<String, HashMap<String, String>>),
+
+    /// The connection is still alive.
+    Ping,
+}
+
+/// The JMAP EventSource push channel.
+///
+/// The stream is read by a dedicated thread, which stops at the
+/// first event received after the channel is dropped. Thanks to
+/// pings, this happens at most [`JmapEventSource::PING_INTERVAL`]
+/// later. The thread is detached rather than spawned as a blocking
+/// task, so that it never delays the shutdown of the runtime.
+pub struct JmapEventSource {
+    events: mpsc::Receiver<Result<ServerSentEvent>>,
+    ping_interval: Duration,
+}
+
+impl JmapEventSource {
+    /// The interval between two pings, as requested to the server.
+    pub const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(body: Body) -> Self {
+        let (tx, events) = mpsc::channel(16);
+
+        thread::spawn(move || {
+            let reader = BufReader::new(body.into_reader());
+            let mut event = ServerSentEvent::default();
+
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        let err = eyre!(err).wrap_err("cannot read JMAP push event");
+                        let _ = tx.blocking_send(Err(err));
+                        return;
+                    }
+                };
+
+                let Some(next) = event.feed(&line) else {
+                    continue;
+                };
+
+                if tx.blocking_send(Ok(next)).is_err() {
+                    debug!("JMAP EventSource dropped, stopping");
+                    return;
+                }
+            }
+
+            debug!("JMAP EventSource closed by server");
+        });
+
+        Self {
+            events,
+            ping_interval: Self::PING_INTERVAL,
+        }
+    }
+
+    /// Wait for the next push event.
+    ///
+    /// The connection is considered dead when nothing is received
+    /// for three ping intervals.
+    pub async fn next(&mut self) -> Result<PushEvent> {
+        loop {
+            let deadline = self.ping_interval * 3;
+
+            let event = match timeout(deadline, self.events.recv()).await {
+                Ok(Some(event)) => event?,
+                Ok(None) => bail!("JMAP push connection closed by server"),
+                Err(_) => bail!("no JMAP push event received for {deadline:?}"),
+            };
+
+            trace!(?event, "received JMAP push event");
+
+            match event.name.as_str() {
+                "ping" => {
+                    // servers may not respect the requested interval
+                    if let Ok(ping) = serde_json::from_str::<Ping>(&event.data) {
+                        self.ping_interval = Duration::from_secs(ping.interval.max(1));
+                    }
+
+                    return Ok(PushEvent::Ping);
+                }
+                // some servers do not name state events
+                "state" | "message" => match serde_json::from_str::<StateChange>(&event.data) {
+                    Ok(change) => return Ok(PushEvent::StateChange(change.changed)),
+                    Err(err) => debug!("cannot parse JMAP state change, skipping: {err}"),
+                },
+                name => debug!(name, "unknown JMAP push event, skipping"),
+            }
+        }
+    }
+}
+
+/// The server-sent event, as defined by the HTML standard.
+///
+/// See <https://html.spec.whatwg.org/multipage/server-sent-events.html>.
+#[derive(Clone, Debug, Default)]
+struct ServerSentEvent {
+    name: String,
+    data: String,
+}
+
+impl ServerSentEvent {
+    /// Feed the given line to the event being parsed.
+    ///
+    /// Returns the event once complete, which means on empty line.
+    fn feed(&mut self, line: &str) -> Option<Self> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if line.is_empty() {
+            let event = std::mem::take(self);
+
+            if event.data.is_empty() {
+                return None;
+            }
+
+            return Some(Self {
+                name: if event.name.is_empty() {
+                    String::from("message")
+                } else {
+                    event.name
+                },
+                data: event.data,
+            });
+        }
+
+        // lines starting with a colon are comments
+        let (field, value) = match line.split_once(':') {
+            Some(("", _)) => return None,
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "event" => self.name = value.to_owned(),
+            "data" => {
+                if !self.data.is_empty() {
+                    self.data.push('\n');
+                }
+
+                self.data.push_str(value);
+            }
+            _ => (),
+        }
+
+        None
+    }
+}
+
+/// The JMAP state change.
+#[derive(Debug, Deserialize)]
+struct StateChange {
+    changed: HashMap<String, HashMap<String, String>>,
+}
+
+/// The JMAP ping.
+#[derive(Debug, Deserialize)]
+struct Ping {
+    interval: u64,
+}
@@ -0,0 +1,75 @@
+//! # JMAP wizard
+//!
+//! Module dedicated to the interactive configuration of the JMAP
+//! backend.
+
+use color_eyre::Result;
+use email::account::config::passwd::PasswordConfig;
+use pimalaya_tui::terminal::prompt;
+use secret::Secret;
+
+use super::config::{JmapAuthConfig, JmapConfig};
+
+static AUTHS: &[&str] = &[PASSWORD, BEARER];
+
+const PASSWORD: &str = "Password";
+const BEARER: &str = "Token (also known as API token or app token)";
+
+static SECRETS: &[&str] = &[
+    RAW,
+    #[cfg(feature = "keyring")]
+    KEYRING,
+    CMD,
+];
+
+const RAW: &str = "Ask my secret, then save it in the configuration file (not safe)";
+#[cfg(feature = "keyring")]
+const KEYRING: &str = "Ask my secret, then save it in my system's global keyring";
+const CMD: &str = "Ask me a shell command that exposes my secret";
+
+pub async fn start(account_name: &str) -> Result<JmapConfig> {
+    let email = prompt::email("Email address:", None)?;
+
+    let default_session_url = format!("https://{}/.well-known/jmap", email.domain());
+    let session_url = prompt::text("JMAP session URL:", Some(&default_session_url))?;
+
+    let (login, auth) = match *prompt::item("JMAP authentication:", AUTHS, None)? {
+        PASSWORD => {
+            let login = prompt::text("JMAP login:", Some(email.as_str()))?;
+            let passwd = configure_secret(account_name, "password").await?;
+            (Some(login), JmapAuthConfig::Password(passwd))
+        }
+        BEARER => {
+            let token = configure_secret(account_name, "token").await?;
+            (None, JmapAuthConfig::Bearer(token))
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(JmapConfig {
+        session_url,
+        login,
+        auth,
+    })
+}
+
+async fn configure_secret(account_name: &str, kind: &str) -> Result<PasswordConfig> {
+    let secret = match prompt::item(format!("JMAP {kind} strategy:"), SECRETS, None)? {
+        #[cfg(feature = "keyring")]
+        &KEYRING => {
+            let secret = Secret::try_new_keyring_entry(format!("{account_name}-jmap-{kind}"))?;
+            secret
+                .set_if_keyring(prompt::password(format!("JMAP {kind}:"))?)
+                .await?;
+            secret
+        }
+        &RAW => Secret::new_raw(prompt::password(format!("JMAP {kind}:"))?),
+        &CMD => Secret::new_command(prompt::text(
+            "Shell command:",
+            Some(&format!("pass show {account_name}")),
+        )?),
+        _ => unreachable!(),
+    };
+
+    Ok(PasswordConfig(secret))
+}
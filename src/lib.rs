@@ -3,4 +3,12 @@ pub mod backend;
 pub mod cli;
 pub mod completion;
 pub mod config;
+#[cfg(feature = "imap")]
+pub mod imap;
+#[cfg(feature = "jmap")]
+pub mod jmap;
 pub mod manual;
+#[cfg(feature = "pop3")]
+pub mod pop3;
+pub mod service;
+pub mod watch;
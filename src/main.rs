@@ -1,7 +1,13 @@
 use clap::Parser;
 use color_eyre::Result;
-use mirador::{cli::Cli, config::TomlConfig};
-use pimalaya_tui::terminal::{cli::tracing, config::TomlConfig as _};
+use mirador::{account::summary::ConfigSummary, cli::Cli, config::TomlConfig};
+use pimalaya_tui::terminal::{
+    cli::{
+        printer::{OutputFmt, Printer, StdoutPrinter},
+        tracing,
+    },
+    config::TomlConfig as _,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -14,8 +20,14 @@ async fn main() -> Result<()> {
 
     let Some(cmd) = cli.command else {
         let config = TomlConfig::from_paths_or_default(&cli.config_paths).await?;
-        println!("{config:#?}");
-        return Ok(());
+        let output = if cli.json {
+            OutputFmt::Json
+        } else {
+            OutputFmt::Plain
+        };
+
+        let mut printer = StdoutPrinter::new(output);
+        return printer.out(ConfigSummary::new(&config));
     };
 
     let res = cmd.execute(&cli.config_paths).await;
@@ -0,0 +1,394 @@
+//! # POP3 client
+//!
+//! Module dedicated to the POP3 client. Clients are built by a
+//! [`Pop3ClientBuilder`], which can resolve credentials once so that
+//! new sessions can be opened at every poll without executing
+//! password commands again.
+//!
+//! See <https://www.rfc-editor.org/rfc/rfc1939> and
+//! <https://www.rfc-editor.org/rfc/rfc2595#section-4> for STLS.
+
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::{
+    eyre::{bail, eyre, OptionExt, WrapErr},
+    Result,
+};
+use rustls_platform_verifier::ConfigVerifierExt;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::timeout,
+};
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsConnector,
+};
+use tracing::{debug, trace};
+
+use super::config::{Pop3Config, Pop3Encryption};
+
+/// The maximum duration of a network operation.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum size of a response line.
+const LINE_LIMIT: usize = 64 * 1024;
+
+/// The maximum size of a multi-line response.
+const BODY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The POP3 client builder.
+#[derive(Clone, Debug)]
+pub struct Pop3ClientBuilder {
+    config: Arc<Pop3Config>,
+    password: Option<String>,
+}
+
+impl Pop3ClientBuilder {
+    pub fn new(config: Arc<Pop3Config>) -> Self {
+        Self {
+            config,
+            password: None,
+        }
+    }
+
+    /// Resolve credentials now, instead of at every build.
+    pub async fn with_prebuilt_credentials(mut self) -> Result<Self> {
+        self.password = Some(self.config.auth.build_credentials().await?);
+        Ok(self)
+    }
+
+    /// Connect to the POP3 server, then authenticate.
+    pub async fn build(self) -> Result<Pop3Client> {
+        let password = match self.password {
+            Some(password) => password,
+            None => self.config.auth.build_credentials().await?,
+        };
+
+        let host = self.config.host.as_str();
+        let port = self.config.port;
+
+        let tcp = timeout(IO_TIMEOUT, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| eyre!("cannot connect to POP3 server {host}:{port}: timed out"))?
+            .wrap_err_with(|| format!("cannot connect to POP3 server {host}:{port}"))?;
+
+        let mut client = match self.config.encryption() {
+            Pop3Encryption::None => {
+                let mut client = Pop3Client::new(tcp);
+                client.read_greeting().await?;
+                client
+            }
+            Pop3Encryption::StartTls => {
+                let mut client = Pop3Client::new(tcp);
+                client.read_greeting().await?;
+                client
+                    .command("STLS")
+                    .await
+                    .wrap_err("cannot start POP3 TLS negotiation")?;
+
+                // no data can be buffered at this point, since the
+                // server waits for the TLS handshake
+                let greeting = client.greeting;
+                let tcp = client
+                    .stream
+                    .into_inner()
+                    .into_tcp()
+                    .ok_or_eyre("POP3 session is already encrypted")?;
+
+                let mut client = Pop3Client::new(connect_tls(host, tcp).await?);
+                client.greeting = greeting;
+                client
+            }
+            Pop3Encryption::Tls => {
+                let mut client = Pop3Client::new(connect_tls(host, tcp).await?);
+                client.read_greeting().await?;
+                client
+            }
+        };
+
+        client
+            .command(&format!("USER {}", self.config.login))
+            .await
+            .wrap_err("cannot authenticate to POP3 server")?;
+        client
+            .command_sensitive(&format!("PASS {password}"), "PASS <redacted>")
+            .await
+            .wrap_err("cannot authenticate to POP3 server")?;
+
+        debug!(host, port, login = self.config.login, "POP3 session opened");
+
+        Ok(client)
+    }
+}
+
+/// The stream of a POP3 session, plain or encrypted.
+trait Pop3Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    /// Get back the TCP stream, if not encrypted yet.
+    fn into_tcp(self: Box<Self>) -> Option<TcpStream>;
+}
+
+impl Pop3Stream for TcpStream {
+    fn into_tcp(self: Box<Self>) -> Option<TcpStream> {
+        Some(*self)
+    }
+}
+
+impl Pop3Stream for tokio_rustls::client::TlsStream<TcpStream> {
+    fn into_tcp(self: Box<Self>) -> Option<TcpStream> {
+        None
+    }
+}
+
+/// Upgrade the given TCP stream to TLS.
+async fn connect_tls(
+    host: &str,
+    tcp: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let mut config = ClientConfig::with_platform_verifier();
+
+    // See <https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids>
+    config.alpn_protocols = vec![b"pop3".to_vec()];
+
+    let connector = TlsConnector::from(Arc::new(config));
+    let name = ServerName::try_from(host.to_owned())
+        .wrap_err_with(|| format!("invalid POP3 server name {host}"))?;
+
+    timeout(IO_TIMEOUT, connector.connect(name, tcp))
+        .await
+        .map_err(|_| eyre!("cannot negotiate TLS with POP3 server {host}: timed out"))?
+        .wrap_err_with(|| format!("cannot negotiate TLS with POP3 server {host}"))
+}
+
+/// The POP3 client.
+pub struct Pop3Client {
+    stream: BufReader<Box<dyn Pop3Stream>>,
+    greeting: String,
+}
+
+impl Pop3Client {
+    fn new(stream: impl Pop3Stream) -> Self {
+        let stream: Box<dyn Pop3Stream> = Box::new(stream);
+
+        Self {
+            stream: BufReader::new(stream),
+            greeting: String::new(),
+        }
+    }
+
+    /// The greeting sent by the server when connecting.
+    pub fn greeting(&self) -> &str {
+        &self.greeting
+    }
+
+    /// List messages, by message number and unique identifier.
+    ///
+    /// Message numbers are only valid during the current session,
+    /// whereas unique identifiers are stable across sessions.
+    pub async fn list_uids(&mut self) -> Result<Vec<(u32, String)>> {
+        self.command("UIDL")
+            .await
+            .wrap_err("cannot list POP3 messages")?;
+
+        let body = self.read_multi_line().await?;
+        let mut uids = Vec::new();
+
+        for line in String::from_utf8_lossy(&body).lines() {
+            let Some((num, uid)) = line.trim().split_once(' ') else {
+                debug!(line, "invalid POP3 UIDL line, skipping");
+                continue;
+            };
+
+            let Ok(num) = num.parse() else {
+                debug!(line, "invalid POP3 message number, skipping");
+                continue;
+            };
+
+            uids.push((num, uid.trim().to_owned()));
+        }
+
+        Ok(uids)
+    }
+
+    /// Fetch the headers of the given message.
+    pub async fn read_headers(&mut self, num: u32) -> Result<Vec<u8>> {
+        self.command(&format!("TOP {num} 0"))
+            .await
+            .wrap_err_with(|| format!("cannot fetch headers of POP3 message {num}"))?;
+        self.read_multi_line().await
+    }
+
+    /// Fetch the whole given message.
+    pub async fn read_msg(&mut self, num: u32) -> Result<Vec<u8>> {
+        self.command(&format!("RETR {num}"))
+            .await
+            .wrap_err_with(|| format!("cannot fetch POP3 message {num}"))?;
+        self.read_multi_line().await
+    }
+
+    /// Close the session.
+    ///
+    /// Messages are never deleted, so errors can safely be ignored.
+    pub async fn quit(mut self) {
+        if let Err(err) = self.command("QUIT").await {
+            debug!("cannot close POP3 session properly: {err}");
+        }
+    }
+
+    async fn read_greeting(&mut self) -> Result<()> {
+        self.greeting = self
+            .read_status()
+            .await
+            .wrap_err("cannot read POP3 server greeting")?;
+        Ok(())
+    }
+
+    /// Send the given command, then read the status line.
+    async fn command(&mut self, cmd: &str) -> Result<String> {
+        self.command_sensitive(cmd, cmd).await
+    }
+
+    /// Same as [`Pop3Client::command`], with a different command to
+    /// display in logs.
+    async fn command_sensitive(&mut self, cmd: &str, trace: &str) -> Result<String> {
+        trace!("POP3 >> {trace}");
+
+        let line = format!("{cmd}\r\n");
+        let stream = self.stream.get_mut();
+
+        timeout(IO_TIMEOUT, async {
+            stream.write_all(line.as_bytes()).await?;
+            stream.flush().await
+        })
+        .await
+        .map_err(|_| eyre!("cannot send POP3 command: timed out"))?
+        .wrap_err("cannot send POP3 command")?;
+
+        self.read_status().await
+    }
+
+    /// Read a status line, returning its text on `+OK`.
+    async fn read_status(&mut self) -> Result<String> {
+        let line = self.read_line().await?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        trace!("POP3 << {line}");
+
+        if let Some(text) = line.strip_prefix("+OK") {
+            return Ok(text.trim().to_owned());
+        }
+
+        match line.strip_prefix("-ERR") {
+            Some(text) => bail!("POP3 server error: {}", text.trim()),
+            None => bail!("invalid POP3 response: {line}"),
+        }
+    }
+
+    /// Read the lines of a multi-line response, until the
+    /// terminating dot.
+    ///
+    /// Lines starting with a dot are unstuffed, line endings are
+    /// kept as CRLF.
+    async fn read_multi_line(&mut self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+
+        loop {
+            let line = self.read_line().await?;
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+            if line == b"." {
+                break Ok(body);
+            }
+
+            let line = line.strip_prefix(b".").unwrap_or(line);
+
+            if body.len() + line.len() > BODY_LIMIT {
+                bail!("POP3 response exceeds {BODY_LIMIT} bytes");
+            }
+
+            body.extend_from_slice(line);
+            body.extend_from_slice(b"\r\n");
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut reader = (&mut self.stream).take(LINE_LIMIT as u64);
+
+        let n = timeout(IO_TIMEOUT, reader.read_until(b'\n', &mut line))
+            .await
+            .map_err(|_| eyre!("cannot read POP3 response: timed out"))?
+            .wrap_err("cannot read POP3 response")?;
+
+        if n == 0 {
+            bail!("POP3 connection closed by server");
+        }
+
+        if !line.ends_with(b"\n") {
+            bail!("POP3 response line exceeds {LINE_LIMIT} bytes");
+        }
+
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pop3::mock::Pop3Server;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn build_and_list_uids() {
+        let server = Pop3Server::start().await;
+        server.add_message("uid-1", "first", "hello");
+        server.add_message("uid-2", "second", "world");
+
+        let mut client = server.client().await;
+        assert_eq!(client.greeting(), "POP3 stand-in ready");
+
+        let uids = client.list_uids().await.unwrap();
+        assert_eq!(uids, [(1, "uid-1".into()), (2, "uid-2".into())]);
+
+        client.quit().await;
+        assert_eq!(
+            server.take_commands(),
+            ["USER alice", "PASS secret", "UIDL", "QUIT"],
+        );
+    }
+
+    #[tokio::test]
+    async fn read_headers_and_msg() {
+        let server = Pop3Server::start().await;
+        server.add_message("uid-1", "first", "hello\r\n.hidden\r\n..double");
+
+        let mut client = server.client().await;
+
+        let headers = client.read_headers(1).await.unwrap();
+        let headers = String::from_utf8(headers).unwrap();
+        assert!(headers.contains("Subject: first\r\n"));
+        assert!(!headers.contains("hello"));
+
+        // dot-stuffed lines are restored
+        let msg = client.read_msg(1).await.unwrap();
+        let msg = String::from_utf8(msg).unwrap();
+        assert!(msg.ends_with("\r\n\r\nhello\r\n.hidden\r\n..double\r\n"));
+    }
+
+    #[tokio::test]
+    async fn server_errors() {
+        let server = Pop3Server::start().await;
+
+        let builder = Pop3ClientBuilder::new(Arc::new(server.config("wrong")));
+        let err = builder.build().await.err().unwrap();
+        let err = format!("{err:?}");
+        assert!(err.contains("cannot authenticate to POP3 server"), "{err}");
+        assert!(err.contains("POP3 server error: invalid password"), "{err}");
+
+        let mut client = server.client().await;
+        let err = client.read_headers(1).await.unwrap_err();
+        let err = format!("{err:?}");
+        assert!(err.contains("POP3 server error: no such message"), "{err}");
+    }
+}
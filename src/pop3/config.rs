@@ -0,0 +1,115 @@
+//! # POP3 configuration
+//!
+//! Module dedicated to the POP3 backend configuration.
+
+use std::{fmt, io, time::Duration};
+
+use color_eyre::{eyre::WrapErr, Result};
+use email::account::config::passwd::PasswordConfig;
+use serde::{Deserialize, Serialize};
+
+/// The POP3 backend configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Pop3Config {
+    /// The POP3 server host name.
+    pub host: String,
+
+    /// The POP3 server host port.
+    pub port: u16,
+
+    /// The POP3 encryption protocol to use.
+    pub encryption: Option<Pop3Encryption>,
+
+    /// The POP3 server login.
+    pub login: String,
+
+    /// The POP3 server authentication configuration.
+    pub auth: Pop3AuthConfig,
+
+    /// The interval between two polls, in seconds.
+    ///
+    /// Defaults to 60 seconds.
+    pub poll_interval: Option<u64>,
+}
+
+impl Pop3Config {
+    pub const DEFAULT_POLL_INTERVAL: u64 = 60;
+
+    pub fn poll_interval(&self) -> Duration {
+        let secs = self.poll_interval.unwrap_or(Self::DEFAULT_POLL_INTERVAL);
+        Duration::from_secs(secs.max(1))
+    }
+
+    pub fn encryption(&self) -> Pop3Encryption {
+        self.encryption.clone().unwrap_or_default()
+    }
+}
+
+/// The POP3 encryption protocol.
+///
+/// Shares the shape of the IMAP encryption configuration, so that
+/// `encryption.type` accepts the same values.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Pop3Encryption {
+    /// The SSL/TLS encryption, usually on port 995.
+    #[default]
+    Tls,
+
+    /// The STARTTLS encryption, using the STLS command, usually on
+    /// port 110.
+    StartTls,
+
+    /// No encryption.
+    None,
+}
+
+impl fmt::Display for Pop3Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tls => write!(f, "SSL/TLS"),
+            Self::StartTls => write!(f, "StartTLS"),
+            Self::None => write!(f, "None"),
+        }
+    }
+}
+
+/// The POP3 authentication configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+pub enum Pop3AuthConfig {
+    /// The password authentication, using the USER and PASS
+    /// commands.
+    Password(PasswordConfig),
+}
+
+impl Pop3AuthConfig {
+    /// Get the password.
+    ///
+    /// Secrets are resolved by this function, which means that
+    /// password commands are executed.
+    pub async fn build_credentials(&self) -> Result<String> {
+        match self {
+            Self::Password(passwd) => {
+                let passwd = passwd.get().await.wrap_err("cannot get POP3 password")?;
+                let passwd = passwd.lines().next().unwrap_or_default();
+                Ok(passwd.to_owned())
+            }
+        }
+    }
+
+    /// Delete the password, if it is stored in the keyring.
+    pub async fn reset(&self) -> Result<()> {
+        match self {
+            Self::Password(passwd) => Ok(passwd.reset().await?),
+        }
+    }
+
+    /// Define the password, only if it does not exist in the keyring.
+    pub async fn configure(&self, get_passwd: impl Fn() -> io::Result<String>) -> Result<()> {
+        match self {
+            Self::Password(passwd) => Ok(passwd.configure(get_passwd).await?),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! # POP3 stand-in
+//!
+//! Module dedicated to testing POP3 features against a minimal local
+//! POP3 server. Only the commands used by the [`client`] are served,
+//! without encryption, and messages are kept in memory so that tests
+//! can add messages between two polls.
+//!
+//! [`client`]: super::client
+
+use std::sync::{Arc, Mutex};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{
+    client::{Pop3Client, Pop3ClientBuilder},
+    config::Pop3Config,
+};
+
+/// The password expected by the server.
+pub const PASSWORD: &str = "secret";
+
+/// The local POP3 server.
+#[derive(Clone)]
+pub struct Pop3Server {
+    port: u16,
+    data: Arc<Mutex<Data>>,
+}
+
+#[derive(Default)]
+struct Data {
+    /// The messages, by unique identifier.
+    messages: Vec<(String, String)>,
+    commands: Vec<String>,
+}
+
+impl Pop3Server {
+    /// Start the server, with an empty mailbox.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let server = Self {
+            port: listener.local_addr().unwrap().port(),
+            data: Default::default(),
+        };
+
+        let server_ref = server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server_ref.clone().serve(stream));
+            }
+        });
+
+        server
+    }
+
+    /// The configuration of a client connecting to this server with
+    /// the given password.
+    pub fn config(&self, password: &str) -> Pop3Config {
+        let config = format!(
+            "host = \"127.0.0.1\"\nport = {}\nencryption.type = \"none\"\nlogin = \"alice\"\nauth.type = \"password\"\nauth.raw = \"{password}\"",
+            self.port,
+        );
+
+        toml::from_str(&config).unwrap()
+    }
+
+    /// Build a client builder connecting to this server.
+    pub fn client_builder(&self) -> Pop3ClientBuilder {
+        Pop3ClientBuilder::new(Arc::new(self.config(PASSWORD)))
+    }
+
+    /// Build a client connected to this server.
+    pub async fn client(&self) -> Pop3Client {
+        self.client_builder().build().await.unwrap()
+    }
+
+    /// The commands received so far, then forget them.
+    pub fn take_commands(&self) -> Vec<String> {
+        std::mem::take(&mut self.data.lock().unwrap().commands)
+    }
+
+    /// Add a message with the given unique identifier, subject and
+    /// body.
+    pub fn add_message(&self, uid: &str, subject: &str, body: &str) {
+        let msg = format!(
+            "From: bob@localhost\r\nTo: alice@localhost\r\nSubject: {subject}\r\nDate: Thu, 1 Oct 2026 10:00:00 +0000\r\n\r\n{body}"
+        );
+
+        let mut data = self.data.lock().unwrap();
+        data.messages.push((uid.to_owned(), msg));
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let mut stream = BufReader::new(stream);
+        let _ = stream.write_all(b"+OK POP3 stand-in ready\r\n").await;
+
+        loop {
+            let mut line = String::new();
+            match stream.read_line(&mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+
+            let line = line.trim_end().to_owned();
+            let res = self.reply(&line);
+            self.data.lock().unwrap().commands.push(line.clone());
+
+            if stream.write_all(res.as_bytes()).await.is_err() || line == "QUIT" {
+                break;
+            }
+        }
+    }
+
+    fn reply(&self, line: &str) -> String {
+        let data = self.data.lock().unwrap();
+        let mut args = line.split(' ');
+
+        match (args.next().unwrap_or_default(), args.next(), args.next()) {
+            ("USER", Some(_), None) | ("QUIT", None, None) => String::from("+OK\r\n"),
+            ("PASS", Some(PASSWORD), None) => String::from("+OK logged in\r\n"),
+            ("PASS", _, _) => String::from("-ERR invalid password\r\n"),
+            ("UIDL", None, None) => {
+                let mut res = String::from("+OK\r\n");
+                for (i, (uid, _)) in data.messages.iter().enumerate() {
+                    res.push_str(&format!("{} {uid}\r\n", i + 1));
+                }
+                res + ".\r\n"
+            }
+            ("TOP", Some(num), Some("0")) => match message(&data, num) {
+                Some(msg) => {
+                    let headers = msg.split("\r\n\r\n").next().unwrap_or_default();
+                    format!("+OK\r\n{}\r\n\r\n.\r\n", stuff(headers))
+                }
+                None => String::from("-ERR no such message\r\n"),
+            },
+            ("RETR", Some(num), None) => match message(&data, num) {
+                Some(msg) => format!("+OK\r\n{}\r\n.\r\n", stuff(msg)),
+                None => String::from("-ERR no such message\r\n"),
+            },
+            _ => String::from("-ERR unknown command\r\n"),
+        }
+    }
+}
+
+/// Find the message of the given message number.
+fn message<'a>(data: &'a Data, num: &str) -> Option<&'a str> {
+    let num: usize = num.parse().ok()?;
+    let (_, msg) = data.messages.get(num.checked_sub(1)?)?;
+    Some(msg)
+}
+
+/// Escape lines starting with a dot, as multi-line responses
+/// require.
+fn stuff(text: &str) -> String {
+    text.split("\r\n")
+        .map(|line| match line.starts_with('.') {
+            true => format!(".{line}"),
+            false => line.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
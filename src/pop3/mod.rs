@@ -0,0 +1,14 @@
+//! # POP3
+//!
+//! Module dedicated to the POP3 protocol, see RFC 1939. Only what is
+//! needed to poll mailboxes is implemented: the [`client`] module
+//! lists messages by unique identifier (UIDL) and fetches their
+//! headers (TOP) or content (RETR). The [`config`] module contains
+//! the backend configuration.
+
+pub mod client;
+pub mod config;
+#[cfg(test)]
+pub mod mock;
+#[cfg(feature = "wizard")]
+pub mod wizard;
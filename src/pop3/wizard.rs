@@ -0,0 +1,72 @@
+//! # POP3 wizard
+//!
+//! Module dedicated to the interactive configuration of the POP3
+//! backend.
+
+use color_eyre::Result;
+use email::account::config::passwd::PasswordConfig;
+use pimalaya_tui::terminal::prompt;
+use secret::Secret;
+
+use super::config::{Pop3AuthConfig, Pop3Config, Pop3Encryption};
+
+static SECRETS: &[&str] = &[
+    RAW,
+    #[cfg(feature = "keyring")]
+    KEYRING,
+    CMD,
+];
+
+const RAW: &str = "Ask my password, then save it in the configuration file (not safe)";
+#[cfg(feature = "keyring")]
+const KEYRING: &str = "Ask my password, then save it in my system's global keyring";
+const CMD: &str = "Ask me a shell command that exposes my password";
+
+pub async fn start(account_name: &str) -> Result<Pop3Config> {
+    let email = prompt::email("Email address:", None)?;
+
+    let default_host = format!("pop.{}", email.domain());
+    let host = prompt::text("POP3 hostname:", Some(&default_host))?;
+
+    let encryptions = [
+        Pop3Encryption::Tls,
+        Pop3Encryption::StartTls,
+        Pop3Encryption::None,
+    ];
+
+    let encryption = prompt::item("POP3 encryption:", encryptions, None)?;
+
+    let default_port = match encryption {
+        Pop3Encryption::Tls => 995,
+        Pop3Encryption::StartTls | Pop3Encryption::None => 110,
+    };
+
+    let port = prompt::u16("POP3 port:", Some(default_port))?;
+    let login = prompt::text("POP3 login:", Some(email.as_str()))?;
+
+    let secret = match *prompt::item("POP3 password strategy:", SECRETS, None)? {
+        #[cfg(feature = "keyring")]
+        KEYRING => {
+            let secret = Secret::try_new_keyring_entry(format!("{account_name}-pop3-passwd"))?;
+            secret
+                .set_if_keyring(prompt::password("POP3 password:")?)
+                .await?;
+            secret
+        }
+        RAW => Secret::new_raw(prompt::password("POP3 password:")?),
+        CMD => Secret::new_command(prompt::text(
+            "Shell command:",
+            Some(&format!("pass show {account_name}")),
+        )?),
+        _ => unreachable!(),
+    };
+
+    Ok(Pop3Config {
+        host,
+        port,
+        encryption: Some(encryption),
+        login,
+        auth: Pop3AuthConfig::Password(PasswordConfig(secret)),
+        poll_interval: None,
+    })
+}
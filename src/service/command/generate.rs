@@ -0,0 +1,239 @@
+//! # Generate service command
+//!
+//! This module contains the [`clap`] command for generating systemd
+//! user service units.
+
+use std::{env, fs, path::PathBuf};
+
+use clap::Parser;
+use color_eyre::eyre::{eyre, Result};
+use pimalaya_tui::terminal::config::TomlConfig as _;
+use shellexpand_utils::{canonicalize, expand};
+use tracing::instrument;
+
+use crate::{account::arg::name::OptionalAccountNameArg, config::TomlConfig};
+
+/// Generate a systemd user service unit watching an account.
+///
+/// The unit runs `mirador watch` for the given account as a
+/// `Type=notify` service: systemd considers the service started once
+/// all the folders are being watched, and restarts it when the
+/// watchdog is not pinged anymore. Reloading the service reloads the
+/// configuration.
+#[derive(Debug, Parser)]
+pub struct GenerateServiceCommand {
+    #[command(flatten)]
+    pub account: OptionalAccountNameArg,
+
+    /// Directory where the unit file should be generated in.
+    ///
+    /// Defaults to the systemd user unit directory, usually
+    /// `~/.config/systemd/user`. If the directory does not exist, it
+    /// will be created. An existing unit file will be overriden.
+    #[arg(long, short, value_name = "DIR", value_parser = dir_parser)]
+    pub dir: Option<PathBuf>,
+
+    /// Print the unit to the standard output instead of writing it.
+    #[arg(long, conflicts_with = "dir")]
+    pub stdout: bool,
+
+    /// The watchdog timeout, in seconds.
+    ///
+    /// Watchdog pings are only sent while all the folders are being
+    /// watched, which means that a session failing for longer than
+    /// this timeout (reconnection backoff included) makes systemd
+    /// restart the service. Use 0 to disable the watchdog.
+    #[arg(long, value_name = "SECS", default_value_t = 600)]
+    pub watchdog_sec: u64,
+}
+
+impl GenerateServiceCommand {
+    #[instrument(skip_all)]
+    pub async fn execute(self, config: &TomlConfig, config_paths: &[PathBuf]) -> Result<()> {
+        let (name, _) = config.to_toml_account_config(self.account.name.as_deref())?;
+        let unit = self.render_unit(&name, config_paths)?;
+
+        if self.stdout {
+            print!("{unit}");
+            return Ok(());
+        }
+
+        let dir = match self.dir {
+            Some(dir) => dir,
+            None => dirs::config_dir()
+                .ok_or_else(|| eyre!("cannot find user configuration directory"))?
+                .join("systemd")
+                .join("user"),
+        };
+
+        let unit_name = format!("mirador-{name}.service");
+        let path = dir.join(&unit_name);
+
+        fs::create_dir_all(&dir)?;
+        fs::write(&path, unit)?;
+
+        println!("Service unit successfully generated at {path:?}!");
+        println!("Enable it with:");
+        println!();
+        println!("  systemctl --user daemon-reload");
+        println!("  systemctl --user enable --now {unit_name}");
+
+        Ok(())
+    }
+
+    fn render_unit(&self, name: &str, config_paths: &[PathBuf]) -> Result<String> {
+        let exe = env::current_exe()?;
+
+        let mut args = vec![exe.to_string_lossy().to_string()];
+
+        for path in config_paths {
+            args.push(String::from("--config"));
+            args.push(path.to_string_lossy().to_string());
+        }
+
+        args.push(String::from("watch"));
+        args.push(name.to_owned());
+
+        let exec_start = args.iter().map(|arg| quote(arg)).collect::<Vec<_>>();
+        let exec_start = exec_start.join(" ");
+
+        let mut unit = format!(
+            "\
+# Generated by mirador service generate
+
+[Unit]
+Description=Mirador watcher for account {name}
+Documentation=https://github.com/pimalaya/mirador
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+RestartSec=10
+"
+        );
+
+        if self.watchdog_sec > 0 {
+            unit.push_str(&format!("WatchdogSec={}\n", self.watchdog_sec));
+        }
+
+        unit.push_str(
+            "
+[Install]
+WantedBy=default.target
+",
+        );
+
+        Ok(unit)
+    }
+}
+
+/// Quote the given argument for a systemd command line.
+///
+/// Percent signs are escaped, since systemd expands them as
+/// specifiers.
+fn quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+
+    let needs_quotes = arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '$' | ';'));
+
+    if !needs_quotes {
+        return arg;
+    }
+
+    let arg = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "$$");
+
+    format!("\"{arg}\"")
+}
+
+/// Parse the given [`str`] as [`PathBuf`].
+///
+/// The path is first shell expanded, then canonicalized (if
+/// applicable).
+fn dir_parser(path: &str) -> Result<PathBuf, String> {
+    expand::try_path(path)
+        .map(canonicalize::path)
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> GenerateServiceCommand {
+        let args = ["generate"].iter().chain(args);
+        GenerateServiceCommand::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn unit() {
+        let exe = quote(&env::current_exe().unwrap().to_string_lossy());
+        let paths = [PathBuf::from("/home/me/my config.toml")];
+        let unit = command(&[]).render_unit("perso", &paths).unwrap();
+
+        assert_eq!(
+            unit,
+            format!(
+                "\
+# Generated by mirador service generate
+
+[Unit]
+Description=Mirador watcher for account perso
+Documentation=https://github.com/pimalaya/mirador
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exe} --config \"/home/me/my config.toml\" watch perso
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+RestartSec=10
+WatchdogSec=600
+
+[Install]
+WantedBy=default.target
+"
+            )
+        );
+    }
+
+    #[test]
+    fn unit_without_watchdog() {
+        let unit = command(&["--watchdog-sec", "0"])
+            .render_unit("perso", &[])
+            .unwrap();
+
+        assert!(unit.contains(" watch perso\n"));
+        assert!(!unit.contains("WatchdogSec"));
+    }
+
+    #[test]
+    fn unit_custom_watchdog() {
+        let unit = command(&["--watchdog-sec", "30"])
+            .render_unit("perso", &[])
+            .unwrap();
+
+        assert!(unit.contains("\nWatchdogSec=30\n"));
+    }
+
+    #[test]
+    fn quote_args() {
+        assert_eq!(quote("/usr/bin/mirador"), "/usr/bin/mirador");
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote("my config"), r#""my config""#);
+        assert_eq!(quote("100%"), "100%%");
+        assert_eq!(quote(r#"a "b" $c\d"#), r#""a \"b\" $$c\\d""#);
+    }
+}
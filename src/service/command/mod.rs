@@ -0,0 +1,32 @@
+//! # Service commands
+//!
+//! This module gathers CLI commands dedicated to running Mirador as
+//! a service: [`generate`] to generate a systemd user service unit.
+
+pub mod generate;
+
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use color_eyre::Result;
+
+use crate::config::TomlConfig;
+
+use self::generate::GenerateServiceCommand;
+
+/// Manage Mirador services.
+///
+/// This subcommand allows you to run Mirador as a service, managed
+/// by systemd.
+#[derive(Debug, Subcommand)]
+pub enum ServiceSubcommand {
+    Generate(GenerateServiceCommand),
+}
+
+impl ServiceSubcommand {
+    pub async fn execute(self, config: &TomlConfig, config_paths: &[PathBuf]) -> Result<()> {
+        match self {
+            Self::Generate(cmd) => cmd.execute(config, config_paths).await,
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! # Service
+//!
+//! Module dedicated to running Mirador as a service. The [`command`]
+//! module generates service units, and the [`notify`] module
+//! notifies the service manager about the watch status.
+
+pub mod command;
+pub mod notify;
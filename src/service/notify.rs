@@ -0,0 +1,279 @@
+//! # Service notify
+//!
+//! Module dedicated to the systemd notification protocol. When
+//! Mirador runs as a `Type=notify` service, the service manager is
+//! told when all the folders are being watched (`READY=1`), what
+//! is being watched (`STATUS=`) and that the process is still
+//! healthy (`WATCHDOG=1`).
+//!
+//! See `sd_notify(3)`.
+
+use std::{env, ffi::OsStr, sync::Arc, time::Duration};
+
+use color_eyre::Result;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, warn};
+
+use crate::watch::status::{WatchStatus, WatchStatusSummary};
+
+/// The service manager notifier.
+///
+/// Built from the `NOTIFY_SOCKET` environment variable, set by
+/// systemd when the service type is `notify`.
+#[derive(Debug)]
+pub struct ServiceNotifier {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    #[cfg(unix)]
+    addr: std::os::unix::net::SocketAddr,
+}
+
+impl ServiceNotifier {
+    /// Build a notifier from the environment.
+    ///
+    /// Returns `None` when the process is not supervised by a
+    /// service manager.
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var_os("NOTIFY_SOCKET") {
+            Some(path) => Self::from_path(&path),
+            None => Ok(None),
+        }
+    }
+
+    /// Build a notifier sending states to the given socket path.
+    ///
+    /// Paths starting with `@` are sockets of the abstract
+    /// namespace. Returns `None` when such sockets are not supported.
+    pub fn from_path(path: &OsStr) -> Result<Option<Self>> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+            let path = path.to_string_lossy();
+
+            // sockets starting with @ live in the abstract namespace
+            let addr = match path.strip_prefix('@') {
+                #[cfg(target_os = "linux")]
+                Some(name) => {
+                    use std::os::linux::net::SocketAddrExt;
+                    SocketAddr::from_abstract_name(name)?
+                }
+                #[cfg(not(target_os = "linux"))]
+                Some(_) => {
+                    warn!("abstract notify socket {path} not supported, skipping");
+                    return Ok(None);
+                }
+                None => SocketAddr::from_pathname(&*path)?,
+            };
+
+            let socket = UnixDatagram::unbound()?;
+            debug!("notifying service manager at {path}");
+            Ok(Some(Self { socket, addr }))
+        }
+
+        #[cfg(not(unix))]
+        {
+            debug!(?path, "notify socket not supported, skipping");
+            Ok(None)
+        }
+    }
+
+    /// Send the given newline-separated state assignments, for
+    /// example `READY=1\nSTATUS=Watching`.
+    pub fn notify(&self, state: &str) -> Result<()> {
+        #[cfg(unix)]
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)?;
+        #[cfg(not(unix))]
+        let _ = state;
+        Ok(())
+    }
+
+    /// Notify the service manager in the background, until the
+    /// given status is dropped.
+    ///
+    /// `READY=1` is sent once all the folders are being watched for
+    /// the first time, and `STATUS=` is sent on every status change.
+    /// When the watchdog is enabled, `WATCHDOG=1` is sent at half
+    /// the watchdog timeout, as long as all the folders are being
+    /// watched.
+    pub fn spawn(self: Arc<Self>, status: &WatchStatus) {
+        self.spawn_with_watchdog(status, watchdog_timeout())
+    }
+
+    /// Notify the service manager in the background, using the given
+    /// watchdog timeout.
+    ///
+    /// See [`ServiceNotifier::spawn`].
+    fn spawn_with_watchdog(self: Arc<Self>, status: &WatchStatus, watchdog: Option<Duration>) {
+        let mut status = status.subscribe();
+
+        tokio::spawn(async move {
+            let mut ready = false;
+
+            // the interval is only polled when the watchdog is enabled
+            let period = watchdog.unwrap_or(Duration::from_secs(60));
+            let mut ping = interval(period / 2);
+            ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    res = status.changed() => {
+                        if res.is_err() {
+                            break;
+                        }
+
+                        let status = status.borrow_and_update();
+                        let summary = WatchStatusSummary(&status);
+                        let mut state = format!("STATUS={summary}");
+
+                        if !ready && summary.is_ready() {
+                            state.insert_str(0, "READY=1\n");
+                            ready = true;
+                        }
+
+                        self.notify_or_warn(&state);
+                    }
+                    _ = ping.tick(), if watchdog.is_some() => {
+                        if WatchStatusSummary(&status.borrow()).is_ready() {
+                            self.notify_or_warn("WATCHDOG=1");
+                        } else {
+                            debug!("some folders are not watched, skipping watchdog ping");
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Send the given state, logging errors instead of returning
+    /// them.
+    pub fn notify_or_warn(&self, state: &str) {
+        if let Err(err) = self.notify(state) {
+            warn!("cannot notify service manager: {err}");
+            debug!("{err:?}");
+        }
+    }
+}
+
+/// Get the watchdog timeout, if enabled for the current process.
+///
+/// See `sd_watchdog_enabled(3)`.
+fn watchdog_timeout() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            debug!(pid, "watchdog enabled for another process, skipping");
+            return None;
+        }
+    }
+
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use tokio::{net::UnixDatagram, time::timeout};
+
+    use super::*;
+
+    /// Bind a fake notify socket, and build a notifier sending states
+    /// to it.
+    fn notifier(dir: &tempfile::TempDir) -> (Arc<ServiceNotifier>, UnixDatagram) {
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let notifier = ServiceNotifier::from_path(path.as_os_str())
+            .unwrap()
+            .unwrap();
+        (Arc::new(notifier), socket)
+    }
+
+    async fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 1024];
+        let n = timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .expect("no state received")
+            .unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let (notifier, socket) = notifier(&dir);
+
+        notifier.notify("STOPPING=1").unwrap();
+        assert_eq!(recv(&socket).await, "STOPPING=1");
+    }
+
+    #[tokio::test]
+    async fn ready_and_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let (notifier, socket) = notifier(&dir);
+        let status = WatchStatus::default();
+        let _inbox = status.register("example", "INBOX");
+        let _sent = status.register("example", "Sent");
+
+        notifier.spawn_with_watchdog(&status, None);
+
+        status.set_ready("example", "INBOX", true);
+        assert_eq!(
+            recv(&socket).await,
+            "STATUS=Watching INBOX of account example, connecting to Sent of account example"
+        );
+
+        // ready is only sent once all the folders are being watched
+        status.set_ready("example", "Sent", true);
+        assert_eq!(
+            recv(&socket).await,
+            "READY=1\nSTATUS=Watching INBOX, Sent of account example"
+        );
+
+        // ready is sent only once
+        status.set_ready("example", "Sent", false);
+        assert_eq!(
+            recv(&socket).await,
+            "STATUS=Watching INBOX of account example, connecting to Sent of account example"
+        );
+
+        status.set_ready("example", "Sent", true);
+        assert_eq!(
+            recv(&socket).await,
+            "STATUS=Watching INBOX, Sent of account example"
+        );
+    }
+
+    #[tokio::test]
+    async fn watchdog() {
+        let dir = tempfile::tempdir().unwrap();
+        let (notifier, socket) = notifier(&dir);
+        let status = WatchStatus::default();
+        let _inbox = status.register("example", "INBOX");
+
+        notifier.spawn_with_watchdog(&status, Some(Duration::from_millis(200)));
+
+        status.set_ready("example", "INBOX", true);
+        assert!(recv(&socket).await.starts_with("READY=1\n"));
+        assert_eq!(recv(&socket).await, "WATCHDOG=1");
+        assert_eq!(recv(&socket).await, "WATCHDOG=1");
+
+        // pings stop while some folders are not being watched
+        status.set_ready("example", "INBOX", false);
+        let state = loop {
+            let state = recv(&socket).await;
+
+            if state != "WATCHDOG=1" {
+                break state;
+            }
+        };
+        assert_eq!(state, "STATUS=Connecting to INBOX of account example");
+
+        let mut buf = [0; 1024];
+        let res = timeout(Duration::from_millis(500), socket.recv(&mut buf)).await;
+        assert!(res.is_err(), "unexpected watchdog ping");
+    }
+}
@@ -0,0 +1,702 @@
+//! # Watch configuration
+//!
+//! Module dedicated to watch-related configuration.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+    time::Duration,
+};
+
+use email::envelope::{Address, Envelope};
+use serde::{Deserialize, Serialize};
+use shellexpand_utils::expand;
+use tracing::debug;
+
+use super::{
+    event::{WatchEvent, WatchEventKind},
+    hook::WatchHookConfig,
+    pattern::{self, Pattern},
+};
+
+/// The watch hooks, one per kind of envelope change.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WatchHooks {
+    /// Hook executed when a message is added.
+    pub on_message_added: Option<WatchHookConfig>,
+
+    /// Hook executed when a message is removed.
+    pub on_message_removed: Option<WatchHookConfig>,
+
+    /// Hook executed when flags of a message change.
+    pub on_flags_changed: Option<WatchHookConfig>,
+
+    /// Hook executed on any change, in addition to the dedicated
+    /// hook.
+    pub on_any_change: Option<WatchHookConfig>,
+
+    /// Rules evaluated before executing the hooks above.
+    pub rules: Vec<WatchRuleConfig>,
+
+    /// Hook executed when another hook fails or times out.
+    pub on_hook_failed: Option<WatchHookConfig>,
+}
+
+/// The watch rule configuration.
+///
+/// Rules are evaluated in order, for every change. Each rule whose
+/// matcher matches the envelope executes its own hook. A matching
+/// rule can stop the evaluation, in which case neither the following
+/// rules nor the account hooks are executed.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WatchRuleConfig {
+    /// The envelope matcher.
+    ///
+    /// A rule without matcher matches all envelopes.
+    #[serde(default, rename = "match")]
+    pub matcher: WatchRuleMatchConfig,
+
+    /// Stop the evaluation when the rule matches.
+    ///
+    /// Only applies to the event kinds the rule defines a hook for,
+    /// or to all of them when the rule defines no hook at all, which
+    /// ignores matching envelopes. Defaults to `false`.
+    pub stop: Option<bool>,
+
+    /// Hook executed when a matching message is added.
+    pub on_message_added: Option<WatchHookConfig>,
+
+    /// Hook executed when a matching message is removed.
+    pub on_message_removed: Option<WatchHookConfig>,
+
+    /// Hook executed when flags of a matching message change.
+    pub on_flags_changed: Option<WatchHookConfig>,
+}
+
+impl WatchRuleConfig {
+    pub fn is_stop(&self) -> bool {
+        self.stop.unwrap_or_default()
+    }
+
+    /// Return `true` if the rule stops the evaluation of events of
+    /// the given kind, see [`WatchRuleConfig::stop`].
+    pub fn stops(&self, kind: WatchEventKind) -> bool {
+        let no_hook = self.on_message_added.is_none()
+            && self.on_message_removed.is_none()
+            && self.on_flags_changed.is_none();
+
+        self.is_stop() && (no_hook || self.get(kind).is_some())
+    }
+
+    /// Get the hook of the given event kind.
+    pub fn get(&self, kind: WatchEventKind) -> Option<&WatchHookConfig> {
+        match kind {
+            WatchEventKind::MessageAdded => self.on_message_added.as_ref(),
+            WatchEventKind::MessageRemoved => self.on_message_removed.as_ref(),
+            WatchEventKind::FlagsChanged => self.on_flags_changed.as_ref(),
+        }
+    }
+}
+
+/// The watch rule matcher configuration.
+///
+/// An envelope matches when all the defined patterns match. Sender
+/// and recipient patterns are globs, unless surrounded by slashes,
+/// and are tested against both the name and the address. The subject
+/// pattern is always a regular expression. All patterns are
+/// case-insensitive.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WatchRuleMatchConfig {
+    /// The glob pattern of the sender (`*@pagerduty.com`).
+    ///
+    /// Patterns surrounded by slashes are regular expressions.
+    pub sender: Option<Pattern>,
+
+    /// The glob pattern of the recipient (`ops@example.com`).
+    ///
+    /// Patterns surrounded by slashes are regular expressions.
+    pub recipient: Option<Pattern>,
+
+    /// The regular expression of the subject (`^\[alert\]`).
+    ///
+    /// Unlike sender and recipient patterns, the subject pattern is
+    /// always a regular expression, which is not anchored: `invoice`
+    /// matches any subject containing "invoice". Surrounding slashes
+    /// are optional.
+    #[serde(default, deserialize_with = "pattern::deserialize_regex")]
+    pub subject: Option<Pattern>,
+}
+
+impl WatchRuleMatchConfig {
+    pub fn matches(&self, envelope: &Envelope) -> bool {
+        let matches_addr = |pattern: &Pattern, addr: &Address| {
+            pattern.is_match(&addr.addr)
+                || matches!(&addr.name, Some(name) if pattern.is_match(name))
+        };
+
+        let sender = match &self.sender {
+            Some(pattern) => matches_addr(pattern, &envelope.from),
+            None => true,
+        };
+
+        let recipient = match &self.recipient {
+            Some(pattern) => matches_addr(pattern, &envelope.to),
+            None => true,
+        };
+
+        let subject = match &self.subject {
+            Some(pattern) => pattern.is_match(&envelope.subject),
+            None => true,
+        };
+
+        sender && recipient && subject
+    }
+}
+
+impl WatchHooks {
+    /// Iterate over all the hooks, including the ones of rules.
+    pub fn iter(&self) -> impl Iterator<Item = &WatchHookConfig> {
+        let hooks = [
+            &self.on_message_added,
+            &self.on_message_removed,
+            &self.on_flags_changed,
+            &self.on_any_change,
+            &self.on_hook_failed,
+        ];
+
+        let rules_hooks = self.rules.iter().flat_map(|rule| {
+            [
+                &rule.on_message_added,
+                &rule.on_message_removed,
+                &rule.on_flags_changed,
+            ]
+        });
+
+        hooks.into_iter().chain(rules_hooks).flatten()
+    }
+
+    /// Get the dedicated hook of the given event kind.
+    pub fn get(&self, kind: WatchEventKind) -> Option<&WatchHookConfig> {
+        match kind {
+            WatchEventKind::MessageAdded => self.on_message_added.as_ref(),
+            WatchEventKind::MessageRemoved => self.on_message_removed.as_ref(),
+            WatchEventKind::FlagsChanged => self.on_flags_changed.as_ref(),
+        }
+    }
+
+    /// Get the hooks matching the given event, in order of execution.
+    pub fn matching(&self, folder: &str, event: &WatchEvent) -> Vec<&WatchHookConfig> {
+        let id = &event.envelope.id;
+        let mut hooks = Vec::new();
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if !rule.matcher.matches(&event.envelope) {
+                continue;
+            }
+
+            debug!(folder, id, "rule {i} matches");
+
+            if let Some(hook) = rule.get(event.kind) {
+                hooks.push(hook);
+            }
+
+            if rule.stops(event.kind) {
+                debug!(folder, id, "rule {i} stops the evaluation");
+                return hooks;
+            }
+        }
+
+        if let Some(hook) = self.get(event.kind) {
+            hooks.push(hook);
+        }
+
+        if let Some(hook) = self.on_any_change.as_ref() {
+            hooks.push(hook);
+        }
+
+        hooks
+    }
+}
+
+/// The reconnection configuration.
+///
+/// When defined, a watch session that fails (server restart, network
+/// timeout, laptop suspend…) is restarted after a delay that grows
+/// exponentially with the number of consecutive attempts.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// The delay before the first reconnection attempt, in seconds.
+    ///
+    /// Defaults to 1 second. The delay is doubled at every
+    /// consecutive attempt.
+    pub initial_delay: Option<u64>,
+
+    /// The maximum delay between two reconnection attempts, in
+    /// seconds.
+    ///
+    /// Defaults to 300 seconds. A session that lasted longer than
+    /// this delay is considered healthy, which resets the attempts
+    /// counter.
+    pub max_delay: Option<u64>,
+
+    /// The amount of randomness applied to delays, in percent.
+    ///
+    /// Defaults to 10%, which means that a delay of 10 seconds is
+    /// randomly turned into a delay between 9 and 11 seconds. This
+    /// prevents multiple watchers from reconnecting at the same time.
+    pub jitter: Option<u8>,
+
+    /// The maximum number of consecutive reconnection attempts.
+    ///
+    /// Defaults to unlimited attempts.
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectConfig {
+    pub const DEFAULT_INITIAL_DELAY: u64 = 1;
+    pub const DEFAULT_MAX_DELAY: u64 = 300;
+    pub const DEFAULT_JITTER: u8 = 10;
+
+    pub fn initial_delay(&self) -> Duration {
+        Duration::from_secs(self.initial_delay.unwrap_or(Self::DEFAULT_INITIAL_DELAY))
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_secs(self.max_delay.unwrap_or(Self::DEFAULT_MAX_DELAY))
+    }
+
+    pub fn jitter(&self) -> u8 {
+        self.jitter.unwrap_or(Self::DEFAULT_JITTER).min(100)
+    }
+
+    /// Get the attempt following the given one, after a session that
+    /// lasted the given duration.
+    ///
+    /// A session that lasted longer than the maximum delay is
+    /// considered healthy, which resets the attempts counter.
+    pub fn next_attempt(&self, attempt: u32, session: Duration) -> u32 {
+        if session >= self.max_delay() {
+            1
+        } else {
+            attempt + 1
+        }
+    }
+
+    /// Return `true` if the given attempt exceeds the maximum number
+    /// of attempts.
+    pub fn is_exhausted(&self, attempt: u32) -> bool {
+        matches!(self.max_attempts, Some(max) if attempt > max)
+    }
+
+    /// Compute the delay to wait before the given attempt.
+    ///
+    /// Attempts start at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .initial_delay()
+            .saturating_mul(1 << exp)
+            .min(self.max_delay());
+
+        let jitter = self.jitter() as u64;
+
+        if jitter == 0 {
+            return delay;
+        }
+
+        // no need for a cryptographically secure randomness here,
+        // the random state of the std hasher is enough
+        let random = RandomState::new().build_hasher().finish();
+        let factor = 100 - jitter + random % (2 * jitter + 1);
+
+        delay.mul_f64(factor as f64 / 100.0)
+    }
+}
+
+/// The watch state configuration.
+///
+/// When defined, the state of every watched folder is persisted, so
+/// that messages that arrived while Mirador was not running trigger
+/// the message added hook on startup.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StateConfig {
+    /// The directory where state files are saved.
+    ///
+    /// Defaults to `$XDG_STATE_HOME/mirador`, or to the data
+    /// directory on systems that do not have a state directory.
+    pub dir: Option<PathBuf>,
+
+    /// The maximum number of missed messages to report on startup.
+    ///
+    /// Only the most recent ones are reported. Defaults to 10.
+    pub max_missed: Option<usize>,
+}
+
+impl StateConfig {
+    pub const DEFAULT_MAX_MISSED: usize = 10;
+
+    pub fn dir(&self) -> Option<PathBuf> {
+        if let Some(dir) = self.dir.as_ref() {
+            return Some(expand::path(dir));
+        }
+
+        dirs::state_dir()
+            .or_else(dirs::data_dir)
+            .map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+    }
+
+    pub fn max_missed(&self) -> usize {
+        self.max_missed.unwrap_or(Self::DEFAULT_MAX_MISSED)
+    }
+}
+
+/// The debounce configuration.
+///
+/// When defined, hooks are not executed right away: changes are
+/// collected during a short window, so that a burst of changes (a
+/// mailing-list digest, a synchronization…) does not trigger dozens
+/// of hooks.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DebounceConfig {
+    /// The duration of the window, in seconds.
+    ///
+    /// The window starts with the first change. Defaults to 2
+    /// seconds.
+    pub delay: Option<u64>,
+
+    /// Group the envelopes collected in the window into one hook
+    /// call.
+    ///
+    /// When enabled, `{count}` and `{senders}` placeholders describe
+    /// all the envelopes, while other placeholders describe the most
+    /// recent one. When disabled, hooks are only executed once for
+    /// the most recent envelope, and the other envelopes of the window
+    /// are dropped. Defaults to `true`.
+    pub batch: Option<bool>,
+}
+
+impl DebounceConfig {
+    pub const DEFAULT_DELAY: u64 = 2;
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_secs(self.delay.unwrap_or(Self::DEFAULT_DELAY))
+    }
+
+    pub fn is_batch(&self) -> bool {
+        self.batch.unwrap_or(true)
+    }
+}
+
+/// The watch configuration.
+///
+/// Only applies to IMAP accounts, in order to choose how changes
+/// are detected.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WatchConfig {
+    /// The way changes are detected.
+    ///
+    /// Defaults to [`WatchMode::Auto`].
+    pub mode: Option<WatchMode>,
+
+    /// The interval between two polls, in seconds.
+    ///
+    /// Only used when polling. Defaults to 60 seconds.
+    pub poll_interval: Option<u64>,
+}
+
+impl WatchConfig {
+    pub const DEFAULT_POLL_INTERVAL: u64 = 60;
+
+    pub fn mode(&self) -> WatchMode {
+        self.mode.clone().unwrap_or_default()
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        let secs = self.poll_interval.unwrap_or(Self::DEFAULT_POLL_INTERVAL);
+        Duration::from_secs(secs.max(1))
+    }
+}
+
+/// The way changes are detected.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchMode {
+    /// Wait for changes of all the watched folders using the NOTIFY
+    /// extension, over a single connection.
+    ///
+    /// Fails if the server does not support it.
+    Notify,
+
+    /// Wait for changes using the IDLE extension, over one
+    /// connection per watched folder.
+    ///
+    /// Fails if the server does not support it.
+    Idle,
+
+    /// Fetch envelopes at a regular interval.
+    ///
+    /// Works with any server, and with proxies dropping long-lived
+    /// connections, at the cost of latency.
+    Poll,
+
+    /// Use NOTIFY if the server advertises it in its capabilities,
+    /// otherwise IDLE, otherwise poll.
+    #[default]
+    Auto,
+}
+
+impl fmt::Display for WatchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Notify => write!(f, "notify"),
+            Self::Idle => write!(f, "idle"),
+            Self::Poll => write!(f, "poll"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(from: &str, to: &str, subject: &str) -> Envelope {
+        Envelope {
+            id: "1".into(),
+            from: Address::new(Some("Sender"), from),
+            to: Address::new_nameless(to),
+            subject: subject.into(),
+            ..Default::default()
+        }
+    }
+
+    fn added(envelope: Envelope) -> WatchEvent {
+        WatchEvent::new(WatchEventKind::MessageAdded, envelope)
+    }
+
+    /// Build a hook executing the given command.
+    fn hook(cmd: &str) -> WatchHookConfig {
+        toml::from_str(&format!("cmd = {cmd:?}")).unwrap()
+    }
+
+    fn hooks(rules: &str) -> WatchHooks {
+        #[derive(Deserialize)]
+        struct Rules {
+            rules: Vec<WatchRuleConfig>,
+        }
+
+        WatchHooks {
+            on_message_added: Some(hook("account-added")),
+            on_any_change: Some(hook("any-change")),
+            rules: toml::from_str::<Rules>(rules).unwrap().rules,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matcher_sender_glob() {
+        let matcher: WatchRuleMatchConfig =
+            toml::from_str(r#"sender = "*@pagerduty.com""#).unwrap();
+
+        assert!(matcher.matches(&envelope("alerts@PagerDuty.com", "me@localhost", "")));
+        assert!(!matcher.matches(&envelope("alerts@pagerduty.org", "me@localhost", "")));
+    }
+
+    #[test]
+    fn matcher_sender_name() {
+        let matcher: WatchRuleMatchConfig = toml::from_str(r#"sender = "sender""#).unwrap();
+        assert!(matcher.matches(&envelope("alerts@pagerduty.com", "me@localhost", "")));
+    }
+
+    #[test]
+    fn matcher_subject_regex() {
+        let matcher: WatchRuleMatchConfig = toml::from_str(r#"subject = 'invoice #\d+'"#).unwrap();
+
+        assert!(matcher.matches(&envelope("a@localhost", "b@localhost", "Re: Invoice #42")));
+        assert!(!matcher.matches(&envelope("a@localhost", "b@localhost", "Invoice #")));
+    }
+
+    #[test]
+    fn matcher_subject_regex_with_slashes() {
+        let matcher: WatchRuleMatchConfig =
+            toml::from_str(r#"subject = '/^\[newsletter\]/'"#).unwrap();
+
+        assert!(matcher.matches(&envelope("a@localhost", "b@localhost", "[Newsletter] Oct")));
+        assert!(!matcher.matches(&envelope("a@localhost", "b@localhost", "Fwd: [newsletter]")));
+    }
+
+    #[test]
+    fn matcher_invalid_subject_regex() {
+        assert!(toml::from_str::<WatchRuleMatchConfig>(r#"subject = "(""#).is_err());
+    }
+
+    #[test]
+    fn matcher_requires_all_patterns() {
+        let matcher: WatchRuleMatchConfig = toml::from_str(
+            r#"
+            sender = "*@pagerduty.com"
+            recipient = "ops@*"
+            subject = "^\\[alert\\]"
+            "#,
+        )
+        .unwrap();
+
+        assert!(matcher.matches(&envelope(
+            "a@pagerduty.com",
+            "ops@localhost",
+            "[ALERT] disk"
+        )));
+        assert!(!matcher.matches(&envelope(
+            "a@pagerduty.com",
+            "dev@localhost",
+            "[ALERT] disk"
+        )));
+        assert!(!matcher.matches(&envelope("a@pagerduty.com", "ops@localhost", "disk")));
+    }
+
+    #[test]
+    fn matching_without_rules() {
+        let hooks = hooks("rules = []");
+        let event = added(envelope("a@localhost", "b@localhost", "hello"));
+
+        assert_eq!(
+            hooks.matching("INBOX", &event),
+            [&hook("account-added"), &hook("any-change")]
+        );
+    }
+
+    #[test]
+    fn matching_rules_in_order() {
+        let hooks = hooks(
+            r#"
+            [[rules]]
+            match.subject = "alert"
+            on-message-added.cmd = "rule-1"
+
+            [[rules]]
+            on-message-added.cmd = "rule-2"
+
+            [[rules]]
+            match.sender = "nobody@*"
+            on-message-added.cmd = "rule-3"
+            "#,
+        );
+
+        let event = added(envelope("a@localhost", "b@localhost", "Disk alert"));
+        assert_eq!(
+            hooks.matching("INBOX", &event),
+            [
+                &hook("rule-1"),
+                &hook("rule-2"),
+                &hook("account-added"),
+                &hook("any-change")
+            ]
+        );
+
+        let event = added(envelope("a@localhost", "b@localhost", "hello"));
+        assert_eq!(
+            hooks.matching("INBOX", &event),
+            [&hook("rule-2"), &hook("account-added"), &hook("any-change")]
+        );
+    }
+
+    #[test]
+    fn matching_rule_stop() {
+        let hooks = hooks(
+            r#"
+            [[rules]]
+            match.sender = "*@pagerduty.com"
+            on-message-added.cmd = "rule-1"
+            stop = true
+
+            [[rules]]
+            on-message-added.cmd = "rule-2"
+            "#,
+        );
+
+        let event = added(envelope("a@pagerduty.com", "b@localhost", "hello"));
+        assert_eq!(hooks.matching("INBOX", &event), [&hook("rule-1")]);
+
+        let event = added(envelope("a@localhost", "b@localhost", "hello"));
+        assert_eq!(
+            hooks.matching("INBOX", &event),
+            [&hook("rule-2"), &hook("account-added"), &hook("any-change")]
+        );
+    }
+
+    #[test]
+    fn matching_rule_stop_without_hook() {
+        let hooks = hooks(
+            r#"
+            [[rules]]
+            match.subject = "^\\[newsletter\\]"
+            stop = true
+            "#,
+        );
+
+        let event = added(envelope("a@localhost", "b@localhost", "[Newsletter] Oct"));
+        assert!(hooks.matching("INBOX", &event).is_empty());
+    }
+
+    #[test]
+    fn matching_rule_other_kind() {
+        let hooks = hooks(
+            r#"
+            [[rules]]
+            on-message-removed.cmd = "rule-1"
+            stop = true
+            "#,
+        );
+
+        let event = WatchEvent::new(
+            WatchEventKind::MessageRemoved,
+            envelope("a@localhost", "b@localhost", "hello"),
+        );
+        assert_eq!(hooks.matching("INBOX", &event), [&hook("rule-1")]);
+
+        // the rule does not handle added messages, so it does not
+        // stop their evaluation
+        let event = added(envelope("a@localhost", "b@localhost", "hello"));
+        assert_eq!(
+            hooks.matching("INBOX", &event),
+            [&hook("account-added"), &hook("any-change")]
+        );
+    }
+
+    #[test]
+    fn reconnect_attempts_reset_after_healthy_session() {
+        let reconnect = ReconnectConfig {
+            initial_delay: Some(2),
+            max_delay: Some(60),
+            jitter: Some(0),
+            max_attempts: Some(3),
+        };
+
+        let short = Duration::from_secs(59);
+        let healthy = Duration::from_secs(60);
+
+        let attempt = reconnect.next_attempt(0, short);
+        assert_eq!(attempt, 1);
+        let attempt = reconnect.next_attempt(attempt, short);
+        assert_eq!(attempt, 2);
+        let attempt = reconnect.next_attempt(attempt, short);
+        assert_eq!(attempt, 3);
+        assert_eq!(reconnect.delay(attempt), Duration::from_secs(8));
+
+        let attempt = reconnect.next_attempt(attempt, healthy);
+        assert_eq!(attempt, 1);
+        assert_eq!(reconnect.delay(attempt), Duration::from_secs(2));
+        assert!(!reconnect.is_exhausted(attempt));
+
+        let attempt = reconnect.next_attempt(3, short);
+        assert!(reconnect.is_exhausted(attempt));
+    }
+}
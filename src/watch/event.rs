@@ -0,0 +1,229 @@
+//! # Watch event
+//!
+//! Module dedicated to envelope changes detected by watchers.
+
+use std::{collections::HashMap, fmt};
+
+use clap::ValueEnum;
+use color_eyre::Result;
+use email::{
+    envelope::{Address, Envelope},
+    message::Message,
+};
+
+/// The envelopes of a folder, indexed by identifier.
+pub type EnvelopesMap = HashMap<String, Envelope>;
+
+/// The kind of envelope change.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum WatchEventKind {
+    /// A message appeared in the folder.
+    MessageAdded,
+
+    /// A message disappeared from the folder (deleted, expunged or
+    /// moved to another folder).
+    MessageRemoved,
+
+    /// Flags of a message changed (seen, flagged…).
+    FlagsChanged,
+}
+
+impl fmt::Display for WatchEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MessageAdded => write!(f, "message-added"),
+            Self::MessageRemoved => write!(f, "message-removed"),
+            Self::FlagsChanged => write!(f, "flags-changed"),
+        }
+    }
+}
+
+/// The envelope change.
+#[derive(Clone, Debug)]
+pub struct WatchEvent {
+    pub kind: WatchEventKind,
+
+    /// The envelope concerned by the change.
+    ///
+    /// For removed messages, this is the last known envelope.
+    pub envelope: Envelope,
+
+    /// The message details, only loaded when hooks need them.
+    pub details: Option<MessageDetails>,
+}
+
+impl WatchEvent {
+    pub fn new(kind: WatchEventKind, envelope: Envelope) -> Self {
+        Self {
+            kind,
+            envelope,
+            details: None,
+        }
+    }
+
+    /// Compute the changes between two snapshots of the same folder.
+    ///
+    /// Events are sorted by envelope date, so that hooks are executed
+    /// in the order messages were sent.
+    pub fn diff(prev: &EnvelopesMap, next: &EnvelopesMap) -> Vec<Self> {
+        let mut events = Vec::new();
+
+        for (id, envelope) in next {
+            match prev.get(id) {
+                None => {
+                    events.push(Self::new(WatchEventKind::MessageAdded, envelope.clone()));
+                }
+                Some(prev) if prev.flags != envelope.flags => {
+                    events.push(Self::new(WatchEventKind::FlagsChanged, envelope.clone()));
+                }
+                Some(_) => (),
+            }
+        }
+
+        for (id, envelope) in prev {
+            if !next.contains_key(id) {
+                events.push(Self::new(WatchEventKind::MessageRemoved, envelope.clone()));
+            }
+        }
+
+        events.sort_by(|a, b| a.envelope.date.cmp(&b.envelope.date));
+        events
+    }
+}
+
+/// The message details that are not part of the envelope.
+///
+/// Loading them requires to fetch the whole message, which is why
+/// they are only loaded when hook templates use them.
+#[derive(Clone, Debug, Default)]
+pub struct MessageDetails {
+    /// The addresses from the message header Cc.
+    pub cc: Vec<Address>,
+
+    /// The beginning of the plain-text body, with whitespaces
+    /// collapsed.
+    pub preview: String,
+}
+
+impl MessageDetails {
+    /// The maximum number of characters of the preview.
+    pub const PREVIEW_LENGTH: usize = 200;
+
+    /// Parse message details from the given message.
+    pub fn from_msg(msg: &Message) -> Result<Self> {
+        let msg = msg.parsed()?;
+
+        let cc = msg
+            .cc()
+            .map(|addrs| addrs.clone().into_list())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|addr| {
+                let email = addr.address?;
+                Some(Address::new(addr.name, email))
+            })
+            .collect();
+
+        let preview = msg
+            .body_preview(Self::PREVIEW_LENGTH)
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Ok(Self { cc, preview })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use email::flag::Flags;
+
+    use super::*;
+
+    /// Build an envelope sent at the given minute, with the given
+    /// flags.
+    fn envelope(id: &str, minute: u32, flags: &str) -> (String, Envelope) {
+        let date = format!("2026-10-18T10:{minute:02}:00+00:00");
+        let envelope = Envelope {
+            id: id.to_owned(),
+            date: DateTime::parse_from_rfc3339(&date).unwrap(),
+            flags: Flags::from(flags),
+            ..Default::default()
+        };
+        (id.to_owned(), envelope)
+    }
+
+    fn changes(events: Vec<WatchEvent>) -> Vec<(WatchEventKind, String)> {
+        events
+            .into_iter()
+            .map(|event| (event.kind, event.envelope.id))
+            .collect()
+    }
+
+    #[test]
+    fn diff_added() {
+        let prev = EnvelopesMap::from([envelope("a", 0, "")]);
+        let next = EnvelopesMap::from([envelope("a", 0, ""), envelope("b", 1, "")]);
+
+        let events = WatchEvent::diff(&prev, &next);
+        assert_eq!(
+            changes(events),
+            [(WatchEventKind::MessageAdded, String::from("b"))]
+        );
+    }
+
+    #[test]
+    fn diff_removed() {
+        let prev = EnvelopesMap::from([envelope("a", 0, ""), envelope("b", 1, "seen")]);
+        let next = EnvelopesMap::from([envelope("a", 0, "")]);
+
+        let events = WatchEvent::diff(&prev, &next);
+        assert_eq!(events[0].envelope.flags, Flags::from("seen"));
+        assert_eq!(
+            changes(events),
+            [(WatchEventKind::MessageRemoved, String::from("b"))]
+        );
+    }
+
+    #[test]
+    fn diff_flags_changed() {
+        let prev = EnvelopesMap::from([envelope("a", 0, ""), envelope("b", 1, "seen")]);
+        let next = EnvelopesMap::from([envelope("a", 0, "seen"), envelope("b", 1, "seen")]);
+
+        let events = WatchEvent::diff(&prev, &next);
+        assert_eq!(
+            changes(events),
+            [(WatchEventKind::FlagsChanged, String::from("a"))]
+        );
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        let prev = EnvelopesMap::from([envelope("a", 0, "seen"), envelope("b", 1, "")]);
+        assert!(WatchEvent::diff(&prev, &prev.clone()).is_empty());
+        assert!(WatchEvent::diff(&EnvelopesMap::new(), &EnvelopesMap::new()).is_empty());
+    }
+
+    #[test]
+    fn diff_sorted_by_date() {
+        let prev = EnvelopesMap::from([envelope("a", 2, ""), envelope("b", 0, "")]);
+        let next = EnvelopesMap::from([
+            envelope("b", 0, "seen"),
+            envelope("c", 3, ""),
+            envelope("d", 1, ""),
+        ]);
+
+        let events = WatchEvent::diff(&prev, &next);
+        assert_eq!(
+            changes(events),
+            [
+                (WatchEventKind::FlagsChanged, String::from("b")),
+                (WatchEventKind::MessageAdded, String::from("d")),
+                (WatchEventKind::MessageRemoved, String::from("a")),
+                (WatchEventKind::MessageAdded, String::from("c")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,800 @@
+//! # Watch handler
+//!
+//! Module dedicated to the execution of hooks matching envelope
+//! changes.
+
+use std::{
+    mem, ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use email::account::config::AccountConfig;
+use http::Client;
+use pimalaya_tui::terminal::cli::printer::OutputFmt;
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+use tracing::{debug, info, warn};
+
+use crate::config::TomlConfig;
+
+use super::{
+    config::{DebounceConfig, WatchHooks},
+    event::{EnvelopesMap, WatchEvent, WatchEventKind},
+    hook::WatchHookConfig,
+    output::WatchEventOutput,
+    state::{WatchState, WatchStateStore},
+    status::WatchStatus,
+    template::{TemplateContext, DETAILS_PLACEHOLDERS},
+};
+
+/// The watch handler.
+///
+/// Executes the configured hooks for every change detected by a
+/// watcher. One handler is created per watched folder.
+#[derive(Clone, Debug)]
+pub struct WatchHandler {
+    account_config: Arc<AccountConfig>,
+    folder: String,
+    /// The hooks, which can be updated while watching.
+    hooks: watch::Receiver<Arc<WatchHooks>>,
+    state_store: Option<WatchStateStore>,
+    max_missed: usize,
+    output: OutputFmt,
+    debounce: Option<DebounceConfig>,
+    /// The debounce window, shared between all the handlers of the
+    /// account.
+    window: DebounceWindow,
+    /// Limits the number of hooks executed at the same time, shared
+    /// between all the handlers of the process.
+    hooks_limit: Arc<Semaphore>,
+    /// The HTTP client used by webhooks, shared between all the
+    /// handlers of the process.
+    http: Client,
+    /// The status of watched folders, shared between all the
+    /// handlers of the process.
+    status: WatchStatus,
+    /// The hooks executed in the background, shared between the
+    /// clones of the handler so that they can be waited for when
+    /// the watcher stops.
+    tasks: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl WatchHandler {
+    /// The maximum duration to wait for background hooks when the
+    /// watcher stops, if one of the hooks has no timeout.
+    pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new(
+        account_config: Arc<AccountConfig>,
+        folder: impl ToString,
+        hooks: WatchHooks,
+    ) -> Self {
+        Self {
+            account_config,
+            folder: folder.to_string(),
+            hooks: watch::channel(Arc::new(hooks)).1,
+            state_store: None,
+            max_missed: 0,
+            output: OutputFmt::Plain,
+            debounce: None,
+            window: DebounceWindow::default(),
+            hooks_limit: Arc::new(Semaphore::new(TomlConfig::DEFAULT_MAX_CONCURRENT_HOOKS)),
+            http: Client::new(),
+            status: WatchStatus::default(),
+            tasks: Default::default(),
+        }
+    }
+
+    /// Receive hooks updates from the given channel.
+    ///
+    /// Updated hooks apply to the next changes, without restarting
+    /// the watcher.
+    pub fn with_hooks_updates(mut self, hooks: watch::Receiver<Arc<WatchHooks>>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Share the given semaphore to limit the number of hooks
+    /// executed at the same time.
+    pub fn with_hooks_limit(mut self, hooks_limit: Arc<Semaphore>) -> Self {
+        self.hooks_limit = hooks_limit;
+        self
+    }
+
+    /// Share the given HTTP client to send webhooks, so that
+    /// connections are reused between requests.
+    pub fn with_http_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Report the readiness of the watched folder to the given
+    /// status.
+    pub fn with_status(mut self, status: WatchStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Collect events in the given debounce window before executing
+    /// hooks.
+    ///
+    /// Handlers sharing the same window collect the events of their
+    /// folders together, so that a burst of changes spread over
+    /// multiple folders executes hooks only once.
+    pub fn with_debounce(mut self, debounce: DebounceConfig, window: DebounceWindow) -> Self {
+        self.debounce = Some(debounce);
+        self.window = window;
+        self
+    }
+
+    /// Print events to the standard output using the given format.
+    ///
+    /// Events are only printed using the JSON format, the plain
+    /// format prints nothing.
+    pub fn with_output(mut self, output: OutputFmt) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Persist the watch state in the given store, and report at
+    /// most `max_missed` missed messages on startup.
+    pub fn with_state_store(mut self, store: WatchStateStore, max_missed: usize) -> Self {
+        self.state_store = Some(store);
+        self.max_missed = max_missed;
+        self
+    }
+
+    pub fn account_config(&self) -> &Arc<AccountConfig> {
+        &self.account_config
+    }
+
+    /// Get the current hooks.
+    pub fn hooks(&self) -> Arc<WatchHooks> {
+        self.hooks.borrow().clone()
+    }
+
+    /// Set the readiness of the watched folder.
+    ///
+    /// Watchers are expected to set it once the folder is actually
+    /// being watched (IDLE started, file system watcher set up…).
+    pub fn set_ready(&self, ready: bool) {
+        let account = &self.account_config.name;
+        self.status.set_ready(account, &self.folder, ready);
+    }
+
+    /// Tell whether hooks need message details.
+    ///
+    /// Details require to fetch whole messages, watchers only load
+    /// them when hook templates use them. See
+    /// [`MessageDetails`](super::event::MessageDetails).
+    pub fn needs_details(&self) -> bool {
+        self.hooks()
+            .iter()
+            .any(|hook| DETAILS_PLACEHOLDERS.iter().any(|p| hook.contains(p)))
+    }
+
+    /// Find messages added since the last saved state.
+    ///
+    /// Returns no event if no state store is configured. Watchers
+    /// are expected to save the given state once events are handled.
+    pub fn missed_events(&self, state: &WatchState, envelopes: &EnvelopesMap) -> Vec<WatchEvent> {
+        let Some(store) = self.state_store.as_ref() else {
+            return Vec::new();
+        };
+
+        let folder = &self.folder;
+
+        match store.load() {
+            Ok(None) => {
+                debug!(folder, "no previous watch state found");
+                Vec::new()
+            }
+            Ok(Some(prev_state)) => match prev_state.find_missed(state, envelopes) {
+                None => {
+                    warn!(
+                        folder,
+                        "watch state outdated, cannot report missed messages"
+                    );
+                    Vec::new()
+                }
+                Some(missed) => {
+                    let skipped = missed.len().saturating_sub(self.max_missed);
+
+                    if skipped > 0 {
+                        warn!(folder, "skipping {skipped} missed message(s)");
+                    }
+
+                    missed
+                        .into_iter()
+                        .skip(skipped)
+                        .map(|envelope| WatchEvent::new(WatchEventKind::MessageAdded, envelope))
+                        .collect()
+                }
+            },
+            Err(err) => {
+                warn!(folder, "cannot load watch state: {err}");
+                debug!("{err:?}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Execute the hooks matching the given events.
+    pub async fn handle_events(&self, events: &[WatchEvent]) {
+        if !events.is_empty() {
+            debug!(folder = self.folder, "executing watch hooks…");
+        }
+
+        for event in events {
+            self.handle(event).await;
+        }
+    }
+
+    /// Save the given state, if a state store is configured.
+    pub fn save_state(&self, state: &WatchState) {
+        let Some(store) = self.state_store.as_ref() else {
+            return;
+        };
+
+        if let Err(err) = store.save(state) {
+            warn!(folder = self.folder, "cannot save watch state: {err}");
+            debug!("{err:?}");
+        }
+    }
+
+    /// Execute the hooks matching the given event.
+    pub async fn handle(&self, event: &WatchEvent) {
+        let id = &event.envelope.id;
+        info!(folder = self.folder, id, "{} detected", event.kind);
+
+        if self.output == OutputFmt::Json {
+            let output = WatchEventOutput::new(&self.account_config, &self.folder, event);
+
+            if let Err(err) = output.print() {
+                warn!(folder = self.folder, "cannot print watch event: {err}");
+                debug!("{err:?}");
+            }
+        }
+
+        match self.debounce.as_ref() {
+            Some(debounce) => self.debounce(debounce, event.clone()),
+            None => self.exec_hooks(&[(self.folder.clone(), event.clone())]),
+        }
+    }
+
+    /// Add the given event to the pending ones, and schedule the
+    /// execution of hooks at the end of the debounce window.
+    fn debounce(&self, debounce: &DebounceConfig, event: WatchEvent) {
+        let mut pending = self.window.pending.lock().unwrap();
+        pending.push((self.folder.clone(), event));
+
+        // the window is already opened, hooks are already scheduled
+        if pending.len() > 1 {
+            return;
+        }
+
+        let handler = self.clone();
+        let delay = debounce.delay();
+
+        tokio::spawn(async move {
+            sleep(delay).await;
+            let events = handler.window.take();
+            debug!(
+                account = handler.account_config.name,
+                "debounce window closed"
+            );
+            handler.exec_hooks(&events);
+        });
+    }
+
+    /// Execute the hooks matching the events of the debounce window
+    /// right away, then wait for hooks to complete.
+    ///
+    /// Watchers are expected to flush the window when they stop, so
+    /// that events collected in the current window are not lost. See
+    /// [`WatchHandler::wait_for_hooks`].
+    pub async fn flush(&self) {
+        let events = self.window.take();
+
+        if events.is_empty() {
+            return;
+        }
+
+        debug!(
+            account = self.account_config.name,
+            "flushing debounce window"
+        );
+
+        self.exec_hooks(&events);
+        self.wait_for_hooks().await;
+    }
+
+    /// Wait for the hooks executed in the background to complete.
+    ///
+    /// The wait is bounded by twice the longest hook timeout, since a
+    /// failed hook executes the hook failed hook, or by
+    /// [`WatchHandler::DEFAULT_SHUTDOWN_TIMEOUT`] if one of the hooks
+    /// has no timeout. Hooks still running after that are killed.
+    pub async fn wait_for_hooks(&self) {
+        let mut tasks = mem::take(&mut *self.tasks.lock().unwrap());
+
+        if tasks.is_empty() {
+            return;
+        }
+
+        let folder = &self.folder;
+        let duration = self.shutdown_timeout();
+        debug!(folder, "waiting for {} hook(s) to complete…", tasks.len());
+
+        let res = timeout(duration, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+
+        if res.is_err() {
+            let count = tasks.len();
+            warn!(
+                folder,
+                "killing {count} hook(s) still running after {duration:?}"
+            );
+            tasks.shutdown().await;
+        }
+    }
+
+    fn shutdown_timeout(&self) -> Duration {
+        let timeouts: Option<Vec<_>> = self.hooks().iter().map(WatchHookConfig::timeout).collect();
+
+        match timeouts.and_then(|timeouts| timeouts.into_iter().max()) {
+            Some(timeout) => timeout * 2,
+            None => Self::DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+
+    /// Execute the hooks matching the given events of the given
+    /// folders.
+    ///
+    /// Events matching the same hook are grouped, so that each hook
+    /// is executed only once. Hooks are executed in the background,
+    /// see [`WatchHandler::wait_for_hooks`].
+    fn exec_hooks(&self, events: &[(String, WatchEvent)]) {
+        let hooks = self.hooks();
+        let mut groups: Vec<(&WatchHookConfig, Vec<&(String, WatchEvent)>)> = Vec::new();
+
+        for event in events {
+            for hook in hooks.matching(&event.0, &event.1) {
+                match groups.iter_mut().find(|(h, _)| ptr::eq(*h, hook)) {
+                    Some((_, events)) => events.push(event),
+                    None => groups.push((hook, vec![event])),
+                }
+            }
+        }
+
+        let batch = match self.debounce.as_ref() {
+            Some(debounce) => debounce.is_batch(),
+            None => true,
+        };
+
+        let mut tasks = self.tasks.lock().unwrap();
+
+        // results of completed hooks are not needed
+        while tasks.try_join_next().is_some() {}
+
+        for (hook, events) in groups {
+            // without batch, only the most recent event is kept
+            let events = if batch {
+                &events[..]
+            } else {
+                let dropped = events.len() - 1;
+                if dropped > 0 {
+                    info!(
+                        account = self.account_config.name,
+                        dropped, "debounce batch disabled, keeping the most recent event only"
+                    );
+                }
+                &events[dropped..]
+            };
+
+            // the folder placeholder describes the most recent event,
+            // like other placeholders
+            let mut handler = self.clone();
+            handler.folder = events[events.len() - 1].0.clone();
+
+            // hooks are executed in the background, so that a slow
+            // hook does not block the watcher
+            let hook = hook.clone();
+            let events: Vec<WatchEvent> = events.iter().map(|(_, event)| event.clone()).collect();
+
+            tasks.spawn(async move {
+                let events: Vec<_> = events.iter().collect();
+                let _ = handler.exec_hook(&hook, &events).await;
+            });
+        }
+    }
+
+    /// Execute the given hook for the given events.
+    ///
+    /// The execution waits for a slot when the maximum number of
+    /// concurrent hooks is reached. When the hook fails or times out,
+    /// the hook failed hook is executed, and the error is returned.
+    pub async fn exec_hook(&self, hook: &WatchHookConfig, events: &[&WatchEvent]) -> Result<()> {
+        let folder = &self.folder;
+
+        let _permit = match self.hooks_limit.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                debug!(folder, "too many hooks running, waiting for a slot…");
+                self.hooks_limit
+                    .acquire()
+                    .await
+                    .expect("hooks semaphore closed")
+            }
+        };
+
+        let ctx = TemplateContext::new(&self.account_config.name, folder, events);
+
+        let Err(err) = self.exec_hook_with_timeout(hook, &ctx).await else {
+            return Ok(());
+        };
+
+        let hooks = self.hooks();
+
+        let Some(fallback) = hooks.on_hook_failed.as_ref() else {
+            return Err(err);
+        };
+
+        let error = err
+            .chain()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        debug!(folder, "executing hook failed hook");
+        let ctx = ctx.with_error(&error);
+
+        // failures of the fallback are already logged, and must not
+        // trigger the fallback again
+        let _ = self.exec_hook_with_timeout(fallback, &ctx).await;
+
+        Err(err)
+    }
+
+    async fn exec_hook_with_timeout(
+        &self,
+        hook: &WatchHookConfig,
+        ctx: &TemplateContext<'_>,
+    ) -> Result<()> {
+        let exec = hook.exec(&self.account_config, &self.http, ctx);
+
+        let Some(duration) = hook.timeout() else {
+            return exec.await;
+        };
+
+        match timeout(duration, exec).await {
+            Ok(res) => res,
+            Err(_) => {
+                let secs = duration.as_secs();
+                warn!(folder = self.folder, "hook timed out after {secs}s");
+                Err(eyre!("hook timed out after {secs}s"))
+            }
+        }
+    }
+}
+
+/// The debounce window.
+///
+/// Collects the events of all the handlers sharing the window, along
+/// with the folder they come from, until the window closes.
+#[derive(Clone, Debug, Default)]
+pub struct DebounceWindow {
+    pending: Arc<Mutex<Vec<(String, WatchEvent)>>>,
+}
+
+impl DebounceWindow {
+    /// Take the pending events, which closes the window.
+    fn take(&self) -> Vec<(String, WatchEvent)> {
+        mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use email::envelope::Envelope;
+
+    use super::*;
+
+    fn envelopes(ids: &[&str]) -> EnvelopesMap {
+        ids.iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let date = format!("2026-10-18T10:0{i}:00+00:00");
+                let envelope = Envelope {
+                    id: id.to_string(),
+                    date: DateTime::parse_from_rfc3339(&date).unwrap(),
+                    ..Default::default()
+                };
+                (id.to_string(), envelope)
+            })
+            .collect()
+    }
+
+    fn handler(store: WatchStateStore, max_missed: usize) -> WatchHandler {
+        let account_config = Arc::new(AccountConfig::default());
+        WatchHandler::new(account_config, "INBOX.Alerts", WatchHooks::default())
+            .with_state_store(store, max_missed)
+    }
+
+    #[test]
+    fn missed_events_without_store() {
+        let account_config = Arc::new(AccountConfig::default());
+        let handler = WatchHandler::new(account_config, "INBOX", WatchHooks::default());
+        let envelopes = envelopes(&["a"]);
+
+        let events = handler.missed_events(&WatchState::maildir(&envelopes), &envelopes);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn missed_events_without_saved_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WatchStateStore::new(dir.path(), "work", "INBOX.Alerts").unwrap();
+        let handler = handler(store, 10);
+        let envelopes = envelopes(&["a"]);
+
+        let events = handler.missed_events(&WatchState::maildir(&envelopes), &envelopes);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn missed_events_are_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WatchStateStore::new(dir.path(), "work", "INBOX.Alerts").unwrap();
+        store
+            .save(&WatchState::maildir(&envelopes(&["a"])))
+            .unwrap();
+
+        let handler = handler(store, 2);
+        let envelopes = envelopes(&["a", "b", "c", "d"]);
+        let events = handler.missed_events(&WatchState::maildir(&envelopes), &envelopes);
+
+        // only the most recent missed messages are reported
+        let ids: Vec<_> = events.iter().map(|e| e.envelope.id.as_str()).collect();
+        assert_eq!(ids, ["c", "d"]);
+        assert!(events
+            .iter()
+            .all(|e| e.kind == WatchEventKind::MessageAdded));
+    }
+
+    #[test]
+    fn missed_events_of_dotted_folders_do_not_mix() {
+        let dir = tempfile::tempdir().unwrap();
+        let alerts = WatchStateStore::new(dir.path(), "work", "INBOX.Alerts").unwrap();
+        let bills = WatchStateStore::new(dir.path(), "work", "INBOX.Bills").unwrap();
+        alerts
+            .save(&WatchState::maildir(&envelopes(&["a"])))
+            .unwrap();
+        bills
+            .save(&WatchState::maildir(&envelopes(&["x", "y"])))
+            .unwrap();
+
+        let handler = handler(alerts, 10);
+        let envelopes = envelopes(&["a", "b"]);
+        let events = handler.missed_events(&WatchState::maildir(&envelopes), &envelopes);
+
+        let ids: Vec<_> = events.iter().map(|e| e.envelope.id.as_str()).collect();
+        assert_eq!(ids, ["b"]);
+    }
+
+    /// Build a handler sharing the given debounce window, whose hook
+    /// appends the count and the folder of events to the given file.
+    #[cfg(unix)]
+    fn debounced(
+        folder: &str,
+        window: &DebounceWindow,
+        delay: u64,
+        batch: bool,
+        log: &std::path::Path,
+    ) -> WatchHandler {
+        use crate::watch::hook::{WatchCmdConfig, WatchExecConfig};
+
+        let cmd = WatchCmdConfig::Exec(WatchExecConfig {
+            program: "sh".into(),
+            args: Some(vec![
+                "-c".into(),
+                format!("echo {{count}} {{folder}} >> {}", log.display()),
+            ]),
+        });
+
+        let hooks = WatchHooks {
+            on_message_added: Some(WatchHookConfig {
+                cmd: Some(cmd),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let debounce = DebounceConfig {
+            delay: Some(delay),
+            batch: Some(batch),
+        };
+
+        let account_config = Arc::new(AccountConfig::default());
+        WatchHandler::new(account_config, folder, hooks).with_debounce(debounce, window.clone())
+    }
+
+    #[cfg(unix)]
+    fn added(id: &str) -> WatchEvent {
+        let envelope = Envelope {
+            id: id.to_owned(),
+            ..Default::default()
+        };
+
+        WatchEvent::new(WatchEventKind::MessageAdded, envelope)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn debounce_window_is_shared_between_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let window = DebounceWindow::default();
+        let inbox = debounced("INBOX", &window, 1, true, &log);
+        let work = debounced("Work", &window, 1, true, &log);
+
+        inbox.handle(&added("a")).await;
+        work.handle(&added("b")).await;
+        inbox.handle(&added("c")).await;
+        work.handle(&added("d")).await;
+
+        // hooks are not executed before the end of the window
+        assert!(!log.exists());
+
+        sleep(std::time::Duration::from_millis(1500)).await;
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "4 Work\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn debounce_without_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let window = DebounceWindow::default();
+        let inbox = debounced("INBOX", &window, 60, false, &log);
+        let work = debounced("Work", &window, 60, false, &log);
+
+        work.handle(&added("a")).await;
+        inbox.handle(&added("b")).await;
+        inbox.flush().await;
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "1 INBOX\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn flush_executes_pending_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let window = DebounceWindow::default();
+        let inbox = debounced("INBOX", &window, 60, true, &log);
+        let work = debounced("Work", &window, 60, true, &log);
+
+        inbox.handle(&added("a")).await;
+        work.handle(&added("b")).await;
+
+        // any handler sharing the window can flush it, and hooks
+        // are completed once flushed
+        inbox.flush().await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "2 Work\n");
+
+        // the window is empty once flushed
+        work.flush().await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "2 Work\n");
+    }
+
+    #[tokio::test]
+    async fn flush_empty_window() {
+        let account_config = Arc::new(AccountConfig::default());
+        let handler = WatchHandler::new(account_config, "INBOX", WatchHooks::default())
+            .with_debounce(DebounceConfig::default(), DebounceWindow::default());
+
+        handler.flush().await;
+        assert!(handler.window.take().is_empty());
+    }
+
+    /// Build a handler executing the given shell commands on added
+    /// messages then on hook failures.
+    #[cfg(unix)]
+    fn hooked(
+        folder: &str,
+        cmd: &str,
+        timeout: Option<u64>,
+        on_failed: Option<&str>,
+    ) -> WatchHandler {
+        let hooks = WatchHooks {
+            on_message_added: Some(WatchHookConfig {
+                cmd: Some(cmd.to_owned().into()),
+                timeout,
+                ..Default::default()
+            }),
+            on_hook_failed: on_failed.map(|cmd| WatchHookConfig {
+                cmd: Some(cmd.to_owned().into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let account_config = Arc::new(AccountConfig::default());
+        WatchHandler::new(account_config, folder, hooks)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hook_timeout_kills_process_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let log = log.display();
+
+        // the background process belongs to the group of the hook
+        let cmd = format!("(sleep 2; echo late >> {log}) & wait");
+        let handler = hooked(
+            "INBOX",
+            &cmd,
+            Some(1),
+            Some(&format!("echo {{error}} >> {log}")),
+        );
+
+        let start = std::time::Instant::now();
+        handler.handle(&added("a")).await;
+        handler.wait_for_hooks().await;
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+        sleep(std::time::Duration::from_millis(1500)).await;
+        let log = std::fs::read_to_string(dir.path().join("hooks.log")).unwrap();
+        assert_eq!(log, "hook timed out after 1s\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hook_failed_receives_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let on_failed = format!("echo {{error}} >> {}", log.display());
+        let handler = hooked("INBOX", "echo oops >&2; exit 3", None, Some(&on_failed));
+
+        handler.handle(&added("a")).await;
+        handler.wait_for_hooks().await;
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert!(log.contains("failed with exit status: 3: oops"), "{log}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hooks_limit_is_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let cmd = format!(
+            "echo start >> {log}; sleep 0.2; echo end >> {log}",
+            log = log.display()
+        );
+
+        let limit = Arc::new(Semaphore::new(1));
+        let inbox = hooked("INBOX", &cmd, None, None).with_hooks_limit(limit.clone());
+        let work = hooked("Work", &cmd, None, None).with_hooks_limit(limit);
+
+        inbox.handle(&added("a")).await;
+        work.handle(&added("b")).await;
+        tokio::join!(inbox.wait_for_hooks(), work.wait_for_hooks());
+
+        // hooks are executed one after the other
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "start\nend\nstart\nend\n");
+    }
+}
@@ -0,0 +1,989 @@
+//! # Watch hook
+//!
+//! Module dedicated to watch hooks. A hook describes what should be
+//! done when a change occurs: executing a command, sending a system
+//! notification and/or calling a webhook.
+
+use std::{cell::Cell, collections::BTreeMap, fmt, process::Stdio, time::Duration};
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use email::{account::config::AccountConfig, watch::config::WatchNotifyConfig};
+use http::{
+    ureq::http::{Method, Request},
+    Client,
+};
+use notify_rust::Notification;
+use process::Command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{io::AsyncWriteExt, time::sleep};
+use tracing::{debug, warn};
+
+use super::{
+    event::WatchEvent,
+    output::WatchEventOutput,
+    template::{self, TemplateContext},
+};
+
+/// The watch hook configuration.
+///
+/// All the defined actions are executed, in order: command, system
+/// notification, then webhook.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WatchHookConfig {
+    /// Execute the given command.
+    pub cmd: Option<WatchCmdConfig>,
+
+    /// Send a system notification.
+    pub notify: Option<WatchNotifyConfig>,
+
+    /// Send a HTTP request.
+    pub webhook: Option<WebhookConfig>,
+
+    /// The maximum duration of the hook execution, in seconds.
+    ///
+    /// A command still running after this delay is killed. Defaults
+    /// to no timeout.
+    pub timeout: Option<u64>,
+}
+
+impl WatchHookConfig {
+    /// Apply the given function to all the templates of the current
+    /// hook.
+    pub fn map_templates(mut self, f: impl Fn(&str) -> String) -> Self {
+        if let Some(cmd) = self.cmd.as_mut() {
+            cmd.map_templates(&f);
+        }
+
+        if let Some(notify) = self.notify.as_mut() {
+            notify.summary = f(&notify.summary);
+            notify.body = f(&notify.body);
+        }
+
+        if let Some(webhook) = self.webhook.as_mut() {
+            webhook.map_templates(&f);
+        }
+
+        self
+    }
+
+    /// Tell whether one of the templates of the current hook
+    /// contains the given pattern.
+    pub fn contains(&self, pattern: &str) -> bool {
+        let found = Cell::new(false);
+
+        self.clone().map_templates(|template| {
+            if template.contains(pattern) {
+                found.set(true);
+            }
+
+            template.to_owned()
+        });
+
+        found.get()
+    }
+
+    /// Get the maximum duration of the hook execution, if any.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    /// Render all the templates of the current hook.
+    ///
+    /// See [`TemplateContext`] for the placeholders and filters, and
+    /// [`WatchCmdConfig::render`] for commands.
+    pub fn render(&self, ctx: &TemplateContext<'_>) -> Self {
+        if ctx.events.is_empty() {
+            return self.clone();
+        }
+
+        let mut hook = self.clone();
+        let cmd = hook.cmd.take();
+        let mut hook = hook.map_templates(|template| ctx.render(template));
+        hook.cmd = cmd.map(|cmd| cmd.render(ctx));
+        hook
+    }
+
+    /// Execute the current hook for the events of the given context.
+    ///
+    /// All the actions are executed, even if one of them fails. Each
+    /// failure is logged, and the first one is returned. Webhooks are
+    /// sent using the given HTTP client.
+    pub async fn exec(
+        &self,
+        config: &AccountConfig,
+        http: &Client,
+        ctx: &TemplateContext<'_>,
+    ) -> Result<()> {
+        let Some(event) = ctx.events.last() else {
+            return Ok(());
+        };
+
+        let folder = ctx.folder;
+        let id = &event.envelope.id;
+        let hook = self.render(ctx);
+        let mut res = Ok(());
+
+        if let Some(cmd) = hook.cmd.as_ref() {
+            let output = WatchEventOutput::new(config, folder, event);
+            let cmd_res = match serde_json::to_vec(&output) {
+                Ok(input) => cmd.run(ctx.env(), input).await,
+                Err(err) => Err(err.into()),
+            };
+
+            if let Err(err) = cmd_res {
+                warn!(folder, id, "command hook failed: {err}");
+                debug!("{err:?}");
+                res = res.and(Err(err));
+            }
+        }
+
+        if let Some(notify) = hook.notify.clone() {
+            if let Err(err) = send_notification(notify).await {
+                warn!(folder, id, "cannot send system notification: {err}");
+                debug!("{err:?}");
+                res = res.and(Err(err));
+            }
+        }
+
+        if let Some(webhook) = hook.webhook.as_ref() {
+            if let Err(err) = webhook.send(http, config, folder, event).await {
+                warn!(folder, id, "cannot send webhook: {err}");
+                debug!("{err:?}");
+                res = res.and(Err(err));
+            }
+        }
+
+        res
+    }
+}
+
+/// The command hook configuration.
+///
+/// Envelope metadata is exported to the command as environment
+/// variables (see [`TemplateContext::env`]), and the event is written
+/// to its standard input as JSON, as printed by `watch --output json`.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum WatchCmdConfig {
+    /// Execute the given command using the shell.
+    ///
+    /// Placeholder values are quoted for a POSIX shell, so that each
+    /// of them stays one argument. Quoting is not possible with the
+    /// Windows command interpreter, where [`WatchCmdConfig::Exec`]
+    /// should be preferred.
+    Shell(Command),
+
+    /// Execute the given program with the given arguments, without
+    /// shell.
+    Exec(WatchExecConfig),
+}
+
+impl WatchCmdConfig {
+    /// Apply the given function to the command, or to the program
+    /// and each of its arguments.
+    pub fn map_templates(&mut self, f: &impl Fn(&str) -> String) {
+        match self {
+            Self::Shell(cmd) => **cmd = f(cmd),
+            Self::Exec(exec) => {
+                exec.program = f(&exec.program);
+
+                for arg in exec.args.iter_mut().flatten() {
+                    *arg = f(arg);
+                }
+            }
+        }
+    }
+
+    /// Find the placeholders surrounded by quotes in the current
+    /// shell command, see [`template::quoted_placeholders`].
+    ///
+    /// Placeholders of programs and arguments, as well as those of
+    /// commands executed by cmd.exe, are not quoted when rendered:
+    /// none is reported for them.
+    pub fn quoted_placeholders(&self) -> Vec<&str> {
+        match self {
+            Self::Shell(cmd) if !is_cmd_shell() => template::quoted_placeholders(cmd.as_str()),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Render the templates of the current command.
+    ///
+    /// Placeholders of shell commands are quoted, see
+    /// [`TemplateContext::render_shell`]. Placeholders of programs
+    /// and arguments are replaced as is.
+    pub fn render(&self, ctx: &TemplateContext<'_>) -> Self {
+        match self {
+            Self::Shell(cmd) if is_cmd_shell() => {
+                let rendered = ctx.render(cmd);
+
+                if rendered != cmd.as_str() {
+                    let hint = "use cmd.program and cmd.args instead";
+                    warn!("placeholders cannot be quoted for cmd.exe, {hint}");
+                }
+
+                Self::Shell(rendered.into())
+            }
+            Self::Shell(cmd) => Self::Shell(ctx.render_shell(cmd).into()),
+            Self::Exec(_) => {
+                let mut cmd = self.clone();
+                cmd.map_templates(&|template| ctx.render(template));
+                cmd
+            }
+        }
+    }
+
+    /// Run the current command with the given environment variables
+    /// and standard input.
+    ///
+    /// Templates are expected to be already rendered, see
+    /// [`WatchHookConfig::render`].
+    pub async fn run(&self, env: Vec<(String, String)>, input: Vec<u8>) -> Result<()> {
+        let mut cmd = match self {
+            Self::Shell(cmd) => {
+                let mut shell = shell_command();
+                shell.arg(cmd.as_str());
+                shell
+            }
+            Self::Exec(exec) => {
+                let mut cmd = tokio::process::Command::new(&exec.program);
+                cmd.args(exec.args.iter().flatten());
+                cmd
+            }
+        };
+
+        debug!(cmd = %self, "executing watch command hook");
+
+        // the command runs in its own process group, so that all its
+        // processes can be killed if the hook times out
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .envs(env)
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| eyre!(err).wrap_err(format!("cannot execute command {self}")))?;
+
+        #[cfg(unix)]
+        let mut group = ProcessGroupGuard(child.id());
+
+        let mut stdin = child.stdin.take();
+
+        // the input is written while waiting for the command, so that
+        // commands not reading their standard input do not block
+        let write = async move {
+            if let Some(stdin) = stdin.as_mut() {
+                if let Err(err) = stdin.write_all(&input).await {
+                    debug!("cannot write command standard input: {err}");
+                }
+            }
+        };
+
+        let (_, output) = tokio::join!(write, child.wait_with_output());
+
+        // the command completed, processes it left in the background
+        // are not killed
+        #[cfg(unix)]
+        group.0.take();
+
+        let output = output?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+
+        if !output.status.success() {
+            if stderr.is_empty() {
+                bail!("command {self} failed with {}", output.status);
+            }
+
+            bail!("command {self} failed with {}: {stderr}", output.status);
+        }
+
+        if !stderr.is_empty() {
+            debug!(cmd = %self, stderr, "command hook succeeded with errors");
+        }
+
+        Ok(())
+    }
+}
+
+/// Kills the given process group when dropped.
+///
+/// Killing the command process is not enough when it is a shell,
+/// since programs it started would keep running.
+#[cfg(unix)]
+struct ProcessGroupGuard(Option<u32>);
+
+#[cfg(unix)]
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        let Some(pgid) = self.0.and_then(|pid| i32::try_from(pid).ok()) else {
+            return;
+        };
+
+        // SAFETY: kill does not access memory, a negative pid targets
+        // the process group
+        unsafe {
+            libc::kill(-pgid, libc::SIGKILL);
+        }
+    }
+}
+
+impl From<String> for WatchCmdConfig {
+    fn from(cmd: String) -> Self {
+        Self::Shell(cmd.into())
+    }
+}
+
+impl fmt::Display for WatchCmdConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Shell(cmd) => write!(f, "{}", cmd.as_str()),
+            Self::Exec(exec) => {
+                write!(f, "{}", exec.program)?;
+
+                for arg in exec.args.iter().flatten() {
+                    write!(f, " {arg:?}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The configuration of a command executed without shell.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WatchExecConfig {
+    /// The program to execute, either a path or a name found in
+    /// `PATH`.
+    pub program: String,
+
+    /// The arguments given to the program.
+    ///
+    /// Placeholders are replaced in each argument, which cannot be
+    /// split or interpreted, whatever their content.
+    pub args: Option<Vec<String>>,
+}
+
+/// The webhook configuration.
+///
+/// The request URL, headers and body accept the same placeholders and
+/// filters as shell commands and notifications.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// The URL the request is sent to.
+    pub url: String,
+
+    /// The HTTP method of the request.
+    ///
+    /// Defaults to `POST`.
+    pub method: Option<String>,
+
+    /// The HTTP headers of the request.
+    pub headers: Option<BTreeMap<String, String>>,
+
+    /// The JSON body of the request.
+    ///
+    /// Placeholders are replaced in all the strings of the body.
+    /// Defaults to the JSON representation of the event, as printed
+    /// by `watch --output json`.
+    pub body: Option<Value>,
+
+    /// The maximum duration of a request, in seconds.
+    ///
+    /// Defaults to 10 seconds.
+    pub timeout: Option<u64>,
+
+    /// The number of retries when a request fails.
+    ///
+    /// Only network errors, server errors (5xx) and rate limits (429)
+    /// are retried. Defaults to 2.
+    pub retries: Option<u32>,
+}
+
+impl WebhookConfig {
+    pub const DEFAULT_METHOD: &'static str = "POST";
+    pub const DEFAULT_TIMEOUT: u64 = 10;
+    pub const DEFAULT_RETRIES: u32 = 2;
+
+    pub fn method(&self) -> Result<Method> {
+        let method = self.method.as_deref().unwrap_or(Self::DEFAULT_METHOD);
+        let method = Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| eyre!("invalid webhook method {method}"))?;
+        Ok(method)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(Self::DEFAULT_TIMEOUT))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(Self::DEFAULT_RETRIES)
+    }
+
+    /// Apply the given function to the URL, the headers and the body
+    /// strings.
+    pub fn map_templates(&mut self, f: &impl Fn(&str) -> String) {
+        self.url = f(&self.url);
+
+        for header in self.headers.iter_mut().flat_map(|h| h.values_mut()) {
+            *header = f(header);
+        }
+
+        if let Some(body) = self.body.as_mut() {
+            map_json_strings(body, f);
+        }
+    }
+
+    /// Send the request matching the given event, using the given
+    /// HTTP client.
+    ///
+    /// Templates are expected to be already rendered, see
+    /// [`WatchHookConfig::render`].
+    pub async fn send(
+        &self,
+        client: &Client,
+        config: &AccountConfig,
+        folder: &str,
+        event: &WatchEvent,
+    ) -> Result<()> {
+        let method = self.method()?;
+        let url = self.url.clone();
+        let headers: Vec<_> = self.headers.clone().into_iter().flatten().collect();
+
+        let body = match self.body.as_ref() {
+            Some(body) => serde_json::to_vec(body)?,
+            None => serde_json::to_vec(&WatchEventOutput::new(config, folder, event))?,
+        };
+
+        let timeout = self.timeout();
+        let retries = self.retries();
+        let mut attempt = 0;
+
+        loop {
+            let method = method.clone();
+            let uri = url.clone();
+            let headers = headers.clone();
+            let body = body.clone();
+
+            let res = client
+                .send(move |agent| {
+                    let mut req = Request::builder().method(method).uri(uri);
+
+                    for (key, val) in headers {
+                        req = req.header(key, val);
+                    }
+
+                    let has_content_type = req
+                        .headers_ref()
+                        .is_some_and(|headers| headers.contains_key("content-type"));
+
+                    if !has_content_type {
+                        req = req.header("content-type", "application/json");
+                    }
+
+                    let req = agent
+                        .configure_request(req.body(body)?)
+                        .http_status_as_error(false)
+                        .timeout_global(Some(timeout))
+                        .build();
+
+                    agent.run(req)
+                })
+                .await;
+
+            let err = match res {
+                Ok(res) if res.status().is_success() => {
+                    debug!(folder, url, status = %res.status(), "webhook sent");
+                    break Ok(());
+                }
+                Ok(res) => {
+                    let status = res.status();
+                    let err = eyre!("webhook {url} answered with status {status}");
+
+                    if !status.is_server_error() && status.as_u16() != 429 {
+                        break Err(err);
+                    }
+
+                    err
+                }
+                Err(err) => eyre!(err).wrap_err(format!("cannot send webhook to {url}")),
+            };
+
+            if attempt >= retries {
+                if retries > 0 {
+                    break Err(err.wrap_err(format!("cannot send webhook after {retries} retries")));
+                }
+
+                break Err(err);
+            }
+
+            attempt += 1;
+
+            let delay = Duration::from_secs(1 << attempt.min(6));
+            debug!(folder, url, attempt, ?delay, "retrying webhook: {err}");
+            sleep(delay).await;
+        }
+    }
+}
+
+/// Send the given system notification.
+#[cfg(target_os = "linux")]
+async fn send_notification(notify: WatchNotifyConfig) -> Result<()> {
+    Notification::new()
+        .summary(&notify.summary)
+        .body(&notify.body)
+        .show_async()
+        .await?;
+    Ok(())
+}
+
+/// Send the given system notification.
+#[cfg(not(target_os = "linux"))]
+async fn send_notification(notify: WatchNotifyConfig) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        Notification::new()
+            .summary(&notify.summary)
+            .body(&notify.body)
+            .show()
+    })
+    .await??;
+    Ok(())
+}
+
+/// Tell whether shell commands are executed by the Windows command
+/// interpreter rather than by a POSIX shell.
+fn is_cmd_shell() -> bool {
+    cfg!(windows)
+        && !std::env::var("MSYSTEM")
+            .map(|env| env.starts_with("MINGW"))
+            .unwrap_or_default()
+}
+
+/// Prepare a new command executed by the shell.
+fn shell_command() -> tokio::process::Command {
+    let (shell, arg) = if is_cmd_shell() {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    let mut cmd = tokio::process::Command::new(shell);
+    cmd.arg(arg);
+    cmd
+}
+
+/// Apply the given function to all the strings of the given JSON
+/// value, recursively.
+fn map_json_strings(value: &mut Value, f: &impl Fn(&str) -> String) {
+    match value {
+        Value::String(s) => *s = f(s),
+        Value::Array(values) => values.iter_mut().for_each(|v| map_json_strings(v, f)),
+        Value::Object(map) => map.values_mut().for_each(|v| map_json_strings(v, f)),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use email::envelope::{Address, Envelope};
+    use serde_json::json;
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::watch::event::WatchEventKind;
+
+    /// A request received by the local HTTP server.
+    #[derive(Debug)]
+    struct Request {
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: Value,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, val)| val.as_str())
+        }
+    }
+
+    type Requests = Arc<Mutex<Vec<Request>>>;
+
+    /// Start a local HTTP server answering requests with the given
+    /// statuses, in order. The last status is repeated, and `None`
+    /// never answers.
+    async fn serve(statuses: Vec<Option<u16>>) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Requests::default();
+
+        tokio::spawn({
+            let requests = requests.clone();
+
+            async move {
+                for i in 0.. {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let status = statuses[i.min(statuses.len() - 1)];
+                    let requests = requests.clone();
+                    tokio::spawn(answer(stream, status, requests));
+                }
+            }
+        });
+
+        (url, requests)
+    }
+
+    async fn answer(mut stream: TcpStream, status: Option<u16>, requests: Requests) {
+        let mut buf = Vec::new();
+
+        let head_len = loop {
+            let mut chunk = [0; 1024];
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before end of request");
+            buf.extend_from_slice(&chunk[..n]);
+
+            if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+        };
+
+        let head = String::from_utf8(buf[..head_len].to_vec()).unwrap();
+        let mut lines = head.lines();
+        let mut start = lines.next().unwrap().split(' ');
+        let method = start.next().unwrap().to_owned();
+        let path = start.next().unwrap().to_owned();
+
+        let headers: Vec<_> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, val)| (key.trim().to_lowercase(), val.trim().to_owned()))
+            .collect();
+
+        let len: usize = headers
+            .iter()
+            .find(|(key, _)| key == "content-length")
+            .map(|(_, val)| val.parse().unwrap())
+            .unwrap_or_default();
+
+        while buf.len() < head_len + len {
+            let mut chunk = [0; 1024];
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        let body = serde_json::from_slice(&buf[head_len..]).unwrap();
+
+        requests.lock().unwrap().push(Request {
+            method,
+            path,
+            headers,
+            body,
+        });
+
+        let Some(status) = status else {
+            // keep the connection opened without answering
+            sleep(Duration::from_secs(60)).await;
+            return;
+        };
+
+        let res =
+            format!("HTTP/1.1 {status} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+        stream.write_all(res.as_bytes()).await.unwrap();
+    }
+
+    fn event(subject: &str) -> WatchEvent {
+        let envelope = Envelope {
+            id: "42".into(),
+            from: Address::new(Some("Alice"), "alice@localhost"),
+            to: Address::new_nameless("bob@localhost"),
+            subject: subject.into(),
+            ..Default::default()
+        };
+
+        WatchEvent::new(WatchEventKind::MessageAdded, envelope)
+    }
+
+    async fn exec_hook(hook: WatchHookConfig, subject: &str) -> Result<()> {
+        let event = event(subject);
+        let events = [&event];
+        let ctx = TemplateContext::new("example", "INBOX", &events);
+
+        hook.exec(&AccountConfig::default(), &Client::new(), &ctx)
+            .await
+    }
+
+    async fn exec(webhook: WebhookConfig) -> Result<()> {
+        let hook = WatchHookConfig {
+            webhook: Some(webhook),
+            ..Default::default()
+        };
+
+        exec_hook(hook, "Hello \"world\"").await
+    }
+
+    async fn exec_cmd(cmd: WatchCmdConfig, subject: &str) -> Result<()> {
+        let hook = WatchHookConfig {
+            cmd: Some(cmd),
+            ..Default::default()
+        };
+
+        exec_hook(hook, subject).await
+    }
+
+    /// A subject trying to escape quotes and to execute commands.
+    #[cfg(unix)]
+    const MALICIOUS_SUBJECT: &str = r#"a' "b" $(touch pwned) `touch pwned`; touch pwned"#;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shell_cmd_quotes_placeholders() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+
+        // each argument is printed on its own line
+        let cmd = format!(
+            "cd {} && printf '%s\\n' {{subject}} {{sender|shell}} > {}",
+            dir.path().display(),
+            args.display()
+        );
+
+        exec_cmd(WatchCmdConfig::Shell(cmd.into()), MALICIOUS_SUBJECT)
+            .await
+            .unwrap();
+
+        let args = fs::read_to_string(args).unwrap();
+        assert_eq!(args, format!("{MALICIOUS_SUBJECT}\nAlice\n"));
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn exec_cmd_keeps_placeholders_as_one_argument() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+
+        let cmd = WatchCmdConfig::Exec(WatchExecConfig {
+            program: "sh".into(),
+            args: Some(vec![
+                "-c".into(),
+                format!(
+                    "cd {} && printf '%s\\n' \"$@\" > {}",
+                    dir.path().display(),
+                    args.display()
+                ),
+                "sh".into(),
+                "{subject}".into(),
+                "{sender}".into(),
+            ]),
+        });
+
+        exec_cmd(cmd, MALICIOUS_SUBJECT).await.unwrap();
+
+        let args = fs::read_to_string(args).unwrap();
+        assert_eq!(args, format!("{MALICIOUS_SUBJECT}\nAlice\n"));
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cmd_env_and_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join("env");
+        let stdin = dir.path().join("stdin.json");
+
+        let cmd = format!(
+            "env | grep ^MIRADOR_ > {} && cat > {}",
+            env.display(),
+            stdin.display()
+        );
+
+        exec_cmd(WatchCmdConfig::Shell(cmd.into()), "Hello")
+            .await
+            .unwrap();
+
+        let env = fs::read_to_string(env).unwrap();
+        let env: Vec<_> = env.lines().collect();
+        assert!(env.contains(&"MIRADOR_ACCOUNT=example"));
+        assert!(env.contains(&"MIRADOR_FOLDER=INBOX"));
+        assert!(env.contains(&"MIRADOR_EVENT=message-added"));
+        assert!(env.contains(&"MIRADOR_ID=42"));
+        assert!(env.contains(&"MIRADOR_SUBJECT=Hello"));
+        assert!(env.contains(&"MIRADOR_SENDER=Alice"));
+        assert!(env.contains(&"MIRADOR_SENDER_ADDRESS=alice@localhost"));
+        assert!(env.contains(&"MIRADOR_RECIPIENT_ADDRESS=bob@localhost"));
+        assert!(env.contains(&"MIRADOR_COUNT=1"));
+
+        let stdin: Value = serde_json::from_slice(&fs::read(stdin).unwrap()).unwrap();
+        assert_eq!(stdin["folder"], "INBOX");
+        assert_eq!(stdin["id"], "42");
+        assert_eq!(stdin["subject"], "Hello");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cmd_env_is_not_interpreted() {
+        let dir = tempfile::tempdir().unwrap();
+        let subject = dir.path().join("subject");
+
+        let cmd = format!(
+            "cd {} && printf '%s' \"$MIRADOR_SUBJECT\" > {}",
+            dir.path().display(),
+            subject.display()
+        );
+
+        exec_cmd(WatchCmdConfig::Shell(cmd.into()), MALICIOUS_SUBJECT)
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(subject).unwrap(), MALICIOUS_SUBJECT);
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[tokio::test]
+    async fn webhook_request() {
+        let (url, requests) = serve(vec![Some(200)]).await;
+
+        let webhook = WebhookConfig {
+            url: format!("{url}?folder={{folder}}"),
+            method: Some("put".into()),
+            headers: Some(BTreeMap::from_iter([(
+                "x-account".to_owned(),
+                "{account}".to_owned(),
+            )])),
+            body: Some(json!({
+                "text": "{sender.name}: {subject}",
+                "ids": ["{id}"],
+                "count": 1,
+            })),
+            ..Default::default()
+        };
+
+        exec(webhook).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+
+        let req = &requests[0];
+        assert_eq!(req.method, "PUT");
+        assert_eq!(req.path, "/hook?folder=INBOX");
+        assert_eq!(req.header("x-account"), Some("example"));
+        assert_eq!(req.header("content-type"), Some("application/json"));
+        assert_eq!(
+            req.body,
+            json!({
+                "text": "Alice: Hello \"world\"",
+                "ids": ["42"],
+                "count": 1,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn webhook_default_body() {
+        let (url, requests) = serve(vec![Some(204)]).await;
+
+        let webhook = WebhookConfig {
+            url,
+            headers: Some(BTreeMap::from_iter([(
+                "content-type".to_owned(),
+                "application/x-event+json".to_owned(),
+            )])),
+            ..Default::default()
+        };
+
+        exec(webhook).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let req = &requests[0];
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.header("content-type"), Some("application/x-event+json"));
+        assert_eq!(req.body["folder"], "INBOX");
+        assert_eq!(req.body["subject"], "Hello \"world\"");
+    }
+
+    #[tokio::test]
+    async fn webhook_retries_server_errors() {
+        let (url, requests) = serve(vec![Some(503), Some(200)]).await;
+
+        let webhook = WebhookConfig {
+            url,
+            retries: Some(1),
+            ..Default::default()
+        };
+
+        exec(webhook).await.unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn webhook_gives_up_after_retries() {
+        let (url, requests) = serve(vec![Some(429), Some(500)]).await;
+
+        let webhook = WebhookConfig {
+            url,
+            retries: Some(1),
+            ..Default::default()
+        };
+
+        let err = exec(webhook).await.unwrap_err();
+        assert!(err.to_string().contains("after 1 retries"));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn webhook_does_not_retry_client_errors() {
+        let (url, requests) = serve(vec![Some(404), Some(200)]).await;
+
+        let webhook = WebhookConfig {
+            url,
+            retries: Some(2),
+            ..Default::default()
+        };
+
+        let err = exec(webhook).await.unwrap_err();
+        assert!(err.to_string().contains("404"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn webhook_timeout() {
+        let (url, requests) = serve(vec![None]).await;
+
+        let webhook = WebhookConfig {
+            url,
+            timeout: Some(1),
+            retries: Some(0),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let err = exec(webhook).await.unwrap_err();
+
+        assert!(err.to_string().contains("cannot send webhook"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,567 @@
+//! # IMAP watcher
+//!
+//! Module dedicated to watching IMAP folders. When the server
+//! supports the NOTIFY extension, changes of all the watched folders
+//! of an account are received over a single connection, see
+//! [`ImapNotifyListener`]. Otherwise, each folder is watched over its
+//! own connection using the IDLE extension, see [`ImapIdleClient`],
+//! or polled when the server does not support it either. See
+//! [`WatchMode`].
+
+use std::{fmt, num::NonZeroU32, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use color_eyre::{
+    eyre::{bail, OptionExt, WrapErr},
+    Result,
+};
+use email::{
+    backend::context::BackendContextBuilder,
+    imap::{ImapClient, ImapContext, ImapContextBuilder},
+};
+use imap_client::{client::tokio::Client, imap_next::imap_types::sequence::SequenceSet};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        oneshot, watch, Mutex,
+    },
+    task::JoinHandle,
+    time::sleep,
+};
+use tracing::{debug, info, warn};
+use utf7_imap::encode_utf7_imap as encode_utf7;
+
+use crate::imap::{
+    idle::ImapIdleClient,
+    notify::{notify_supported, ImapNotification, ImapNotifyClient},
+};
+
+use super::{
+    config::{WatchConfig, WatchMode},
+    event::{EnvelopesMap, MessageDetails, WatchEvent, WatchEventKind},
+    handler::WatchHandler,
+    shutdown_requested,
+    state::WatchState,
+    WatchChanges,
+};
+
+/// The maximum number of notifications kept for watchers that did
+/// not receive them yet.
+const NOTIFICATIONS_CAPACITY: usize = 64;
+
+/// The IMAP NOTIFY listener.
+///
+/// The listener is shared by the watchers of all the folders of an
+/// account. It is started by the first watcher needing it, then
+/// forwards notifications to all of them. After a failure, it is
+/// started again by the next watcher needing it. The listening task
+/// stops when the listener is dropped.
+#[derive(Clone)]
+pub struct ImapNotifyListener {
+    ctx_builder: ImapContextBuilder,
+    mailboxes: Arc<Vec<String>>,
+    state: Arc<Mutex<ImapNotifyState>>,
+}
+
+impl ImapNotifyListener {
+    pub fn new(ctx_builder: ImapContextBuilder, folders: &[String]) -> Self {
+        let config = &ctx_builder.account_config;
+        let mailboxes = folders
+            .iter()
+            .map(|folder| encode_utf7(config.get_folder_alias(folder)))
+            .collect();
+
+        Self {
+            ctx_builder,
+            mailboxes: Arc::new(mailboxes),
+            state: Arc::new(Mutex::new(ImapNotifyState::Stopped)),
+        }
+    }
+
+    /// Subscribe to notifications, starting the listening task if
+    /// needed.
+    ///
+    /// Notifications are set before returning, so that changes
+    /// happening after the subscription cannot be missed. When
+    /// `notify` is `false`, server capabilities are only checked.
+    ///
+    /// Capabilities are checked once per account, on a session
+    /// built from the context shared by watchers. This session then
+    /// becomes the NOTIFY session, or is given to the watcher that
+    /// checked them, so that it can idle without connecting again.
+    async fn subscribe(&self, notify: bool) -> Result<ImapNotifySubscription> {
+        let mut state = self.state.lock().await;
+
+        match &*state {
+            ImapNotifyState::Unused { idle, ctx } => {
+                return Ok(ImapNotifySubscription::Unused {
+                    idle: *idle,
+                    ctx: ctx.clone(),
+                    client: None,
+                });
+            }
+            ImapNotifyState::Listening { ctx, changes, task } if !task.0.is_finished() => {
+                return Ok(ImapNotifySubscription::Listening {
+                    ctx: ctx.clone(),
+                    changes: changes.resubscribe(),
+                });
+            }
+            _ => *state = ImapNotifyState::Stopped,
+        }
+
+        // the context is only used to list envelopes, by all the
+        // watchers, while the NOTIFY and IDLE sessions get their own
+        // connection
+        let ctx = self.ctx_builder.clone().build().await?;
+        let mut client = session(&ctx).await?;
+        client
+            .refresh_capabilities()
+            .await
+            .wrap_err("cannot get IMAP server capabilities")?;
+
+        let idle = client.state.ext_idle_supported();
+        let unused = ImapNotifyState::Unused {
+            idle,
+            ctx: ctx.clone(),
+        };
+
+        if notify && !notify_supported(&client) {
+            info!("IMAP server does not advertise NOTIFY, watching each folder over its own connection");
+        }
+
+        if !notify || !notify_supported(&client) {
+            *state = unused;
+            return Ok(ImapNotifySubscription::Unused {
+                idle,
+                ctx,
+                client: Some(client),
+            });
+        }
+
+        let mut client = ImapNotifyClient::new(client).await?;
+
+        if !client.set(&self.mailboxes).await? {
+            warn!("IMAP server rejected NOTIFY, watching each folder over its own connection");
+            *state = unused;
+            // the session cannot be used for IDLE anymore, having
+            // notifications possibly set
+            return Ok(ImapNotifySubscription::Unused {
+                idle,
+                ctx,
+                client: None,
+            });
+        }
+
+        info!(
+            "listening to IMAP notifications of {} folder(s)",
+            self.mailboxes.len()
+        );
+
+        let (notify, changes) = broadcast::channel(NOTIFICATIONS_CAPACITY);
+
+        let task = tokio::spawn(async move {
+            loop {
+                match client.next().await {
+                    Ok(notification) => {
+                        debug!(?notification, "received IMAP notification");
+                        let _ = notify.send(notification);
+                    }
+                    Err(err) => {
+                        warn!("IMAP NOTIFY session failed: {err}");
+                        debug!("{err:?}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        *state = ImapNotifyState::Listening {
+            ctx: ctx.clone(),
+            changes: changes.resubscribe(),
+            task: ImapNotifyTask(task),
+        };
+
+        Ok(ImapNotifySubscription::Listening { ctx, changes })
+    }
+}
+
+/// The state of the IMAP NOTIFY listener.
+enum ImapNotifyState {
+    /// Not listening yet, or not anymore.
+    Stopped,
+
+    /// NOTIFY is not used, because the server does not support it
+    /// or because the watch mode does not need it.
+    ///
+    /// Tells whether the server supports IDLE, which saves watchers
+    /// from checking it again, and contains the context shared by
+    /// watchers.
+    Unused { idle: bool, ctx: ImapContext },
+
+    /// Listening to notifications.
+    ///
+    /// Contains the context shared by watchers, as well as a receiver
+    /// that new watchers subscribe from.
+    Listening {
+        ctx: ImapContext,
+        changes: broadcast::Receiver<ImapNotification>,
+        task: ImapNotifyTask,
+    },
+}
+
+/// The IMAP NOTIFY subscription.
+enum ImapNotifySubscription {
+    Listening {
+        ctx: ImapContext,
+        changes: broadcast::Receiver<ImapNotification>,
+    },
+    /// Contains the session built while checking capabilities, for
+    /// the watcher that checked them only.
+    Unused {
+        idle: bool,
+        ctx: ImapContext,
+        client: Option<Client>,
+    },
+}
+
+/// The task forwarding IMAP notifications, aborted when dropped.
+struct ImapNotifyTask(JoinHandle<()>);
+
+impl Drop for ImapNotifyTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The way changes of a folder are waited for.
+enum ImapWait {
+    Notify(broadcast::Receiver<ImapNotification>),
+    Idle(ImapIdleClient),
+    Poll(Duration),
+}
+
+impl fmt::Display for ImapWait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Notify(_) => write!(f, "NOTIFY"),
+            Self::Idle(_) => write!(f, "IDLE"),
+            Self::Poll(_) => write!(f, "poll"),
+        }
+    }
+}
+
+/// The IMAP watcher.
+pub struct WatchImapChanges {
+    ctx_builder: ImapContextBuilder,
+    listener: ImapNotifyListener,
+    handler: WatchHandler,
+    config: WatchConfig,
+}
+
+impl WatchImapChanges {
+    pub fn new(
+        ctx_builder: ImapContextBuilder,
+        listener: ImapNotifyListener,
+        handler: WatchHandler,
+        config: WatchConfig,
+    ) -> Self {
+        Self {
+            ctx_builder,
+            listener,
+            handler,
+            config,
+        }
+    }
+
+    pub fn new_boxed(
+        ctx_builder: ImapContextBuilder,
+        listener: ImapNotifyListener,
+        handler: WatchHandler,
+        config: WatchConfig,
+    ) -> Box<dyn WatchChanges> {
+        Box::new(Self::new(ctx_builder, listener, handler, config))
+    }
+
+    /// Resolve the watch mode.
+    ///
+    /// Returns the way changes are waited for, along with the context
+    /// used to list envelopes. The context is shared by the watchers
+    /// of all the folders of the account, except in poll mode where
+    /// every folder gets its own context. In IDLE mode, the given
+    /// folder is examined by the IDLE session.
+    async fn resolve_mode(&self, folder: &str) -> Result<(ImapContext, ImapWait)> {
+        let mode = self.config.mode();
+        let interval = self.config.poll_interval();
+
+        if mode == WatchMode::Poll {
+            let ctx = self.ctx_builder.clone().build().await?;
+            return Ok((ctx, ImapWait::Poll(interval)));
+        }
+
+        let notify = mode != WatchMode::Idle;
+
+        let (idle, ctx, client) = match self.listener.subscribe(notify).await? {
+            ImapNotifySubscription::Listening { ctx, changes } => {
+                return Ok((ctx, ImapWait::Notify(changes)));
+            }
+            ImapNotifySubscription::Unused { idle, ctx, client } => (idle, ctx, client),
+        };
+
+        let wait = match mode {
+            WatchMode::Notify => {
+                bail!("IMAP server does not support NOTIFY, please set watch.mode to auto");
+            }
+            _ if idle => {
+                let client = match client {
+                    Some(client) => client,
+                    None => session(&ctx).await?,
+                };
+
+                let mut client = ImapIdleClient::new(client).await?;
+                client.examine(folder).await?;
+                ImapWait::Idle(client)
+            }
+            WatchMode::Idle => {
+                bail!("IMAP server does not support IDLE, please set watch.mode to auto or poll");
+            }
+            _ => {
+                info!("IMAP server does not advertise IDLE, falling back to polling");
+                ImapWait::Poll(interval)
+            }
+        };
+
+        Ok((ctx, wait))
+    }
+
+    async fn watch_changes_loop(
+        &self,
+        folder: &str,
+        wait_for_idle_done_request: &mut oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let config = self.handler.account_config();
+
+        let folder = config.get_folder_alias(folder);
+        let folder_encoded = encode_utf7(folder.clone());
+        debug!("utf7 encoded folder: {folder_encoded}");
+
+        let (ctx, mut wait) = self.resolve_mode(&folder_encoded).await?;
+        info!("using IMAP {wait} mode");
+
+        let (uid_validity, mut envelopes, events) =
+            self.list_changes(&ctx, &folder_encoded, None).await?;
+        self.handler.handle_events(&events).await;
+        self.handler
+            .save_state(&WatchState::imap(uid_validity, &envelopes));
+
+        loop {
+            // watchers are ready once changes cannot be missed
+            // anymore: notifications are set before subscribing, while
+            // IDLE needs to be accepted by the server first
+            match &mut wait {
+                ImapWait::Notify(changes) => {
+                    self.handler.set_ready(true);
+                    debug!("waiting for IMAP notifications…");
+                    let notified =
+                        wait_for_notification(changes, &folder_encoded, wait_for_idle_done_request)
+                            .await?;
+
+                    if !notified {
+                        return Ok(());
+                    }
+                }
+                ImapWait::Idle(client) => {
+                    info!("starting new IMAP IDLE loop…");
+                    client.idle().await?;
+                    self.handler.set_ready(true);
+
+                    if !client.wait(wait_for_idle_done_request).await? {
+                        return Ok(());
+                    }
+
+                    info!("received IDLE change notification or timeout");
+                }
+                ImapWait::Poll(interval) => {
+                    self.handler.set_ready(true);
+                    debug!("waiting {interval:?} before next IMAP poll…");
+
+                    tokio::select! {
+                        _ = sleep(*interval) => (),
+                        _ = &mut *wait_for_idle_done_request => return Ok(()),
+                    };
+                }
+            }
+
+            // the folder is examined again, which refreshes the
+            // number of messages and supports empty folders
+            let (uid_validity, next_envelopes, events) = self
+                .list_changes(&ctx, &folder_encoded, Some(&envelopes))
+                .await?;
+            self.handler.handle_events(&events).await;
+
+            if !events.is_empty() {
+                let state = WatchState::imap(uid_validity, &next_envelopes);
+                self.handler.save_state(&state);
+            }
+
+            envelopes = next_envelopes;
+        }
+    }
+
+    /// List the envelopes of the given folder, then compute the
+    /// events since the given previous envelopes, or since the saved
+    /// state when there is none.
+    ///
+    /// Returns the UIDVALIDITY of the folder, the envelopes and the
+    /// events.
+    async fn list_changes(
+        &self,
+        ctx: &ImapContext,
+        folder: &str,
+        prev_envelopes: Option<&EnvelopesMap>,
+    ) -> Result<(u32, EnvelopesMap, Vec<WatchEvent>)> {
+        let mut client = ctx.client().await;
+        let (uid_validity, envelopes) = list_envelopes(&mut client, folder).await?;
+
+        let mut events = match prev_envelopes {
+            Some(prev_envelopes) => WatchEvent::diff(prev_envelopes, &envelopes),
+            None => {
+                let state = WatchState::imap(uid_validity, &envelopes);
+                self.handler.missed_events(&state, &envelopes)
+            }
+        };
+
+        self.load_details(&mut client, &mut events).await;
+        Ok((uid_validity, envelopes, events))
+    }
+
+    /// Load the details of the given events, if hooks need them.
+    async fn load_details(&self, client: &mut ImapClient, events: &mut [WatchEvent]) {
+        if !self.handler.needs_details() {
+            return;
+        }
+
+        for event in events {
+            // removed messages cannot be fetched anymore
+            if event.kind == WatchEventKind::MessageRemoved {
+                continue;
+            }
+
+            let id = &event.envelope.id;
+
+            match fetch_details(client, id).await {
+                Ok(details) => event.details = Some(details),
+                Err(err) => {
+                    warn!(id, "cannot load message details: {err}");
+                    debug!("{err:?}");
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl WatchChanges for WatchImapChanges {
+    async fn watch_changes(
+        &self,
+        folder: &str,
+        mut wait_for_shutdown_request: watch::Receiver<bool>,
+    ) -> Result<()> {
+        info!("watching IMAP folder {folder} for envelope changes");
+
+        let (request_idle_done, mut wait_for_idle_done_request) = oneshot::channel();
+
+        let watch = self.watch_changes_loop(folder, &mut wait_for_idle_done_request);
+        tokio::pin!(watch);
+
+        tokio::select! {
+            res = &mut watch => return res,
+            _ = shutdown_requested(&mut wait_for_shutdown_request) => (),
+        };
+
+        // the IDLE command (or the poll delay, or the wait for
+        // notifications) needs to be terminated before stopping
+        let _ = request_idle_done.send(());
+
+        if let Err(err) = watch.await {
+            debug!("IMAP watcher stopped: {err}");
+            debug!("{err:?}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Wait for a notification concerning the given folder.
+///
+/// Returns `false` if the wait was interrupted by the done request.
+async fn wait_for_notification(
+    changes: &mut broadcast::Receiver<ImapNotification>,
+    folder: &str,
+    wait_for_done_request: &mut oneshot::Receiver<()>,
+) -> Result<bool> {
+    loop {
+        let change = tokio::select! {
+            change = changes.recv() => change,
+            _ = &mut *wait_for_done_request => return Ok(false),
+        };
+
+        match change {
+            Ok(ImapNotification::MailboxChanged(mailbox)) if is_same_mailbox(&mailbox, folder) => {
+                return Ok(true);
+            }
+            Ok(ImapNotification::MailboxChanged(_)) => continue,
+            Ok(ImapNotification::Overflow) => return Ok(true),
+            Err(RecvError::Lagged(count)) => {
+                debug!("missed {count} IMAP notification(s)");
+                return Ok(true);
+            }
+            Err(RecvError::Closed) => bail!("IMAP NOTIFY session closed"),
+        }
+    }
+}
+
+/// Return `true` if the given mailbox names are the same.
+///
+/// Mailbox names are case-sensitive, except INBOX.
+fn is_same_mailbox(a: &str, b: &str) -> bool {
+    a == b || (a.eq_ignore_ascii_case("INBOX") && b.eq_ignore_ascii_case("INBOX"))
+}
+
+/// Build a new authenticated session from the configuration of the
+/// given context.
+async fn session(ctx: &ImapContext) -> Result<Client> {
+    let mut client_builder = ctx.client().await.client_builder.clone();
+    Ok(client_builder.build().await?)
+}
+
+/// Fetch the details of the message matching the given UID, without
+/// marking it as seen.
+async fn fetch_details(client: &mut ImapClient, id: &str) -> Result<MessageDetails> {
+    let uid = SequenceSet::try_from(id).wrap_err_with(|| format!("invalid IMAP UID {id}"))?;
+    let msgs = client.peek_messages(uid).await?;
+    let msg = msgs
+        .first()
+        .ok_or_eyre(format!("cannot find IMAP message {id}"))?;
+    MessageDetails::from_msg(msg)
+}
+
+/// Examine the given folder, then fetch all its envelopes.
+///
+/// Returns the UIDVALIDITY of the folder along with the envelopes.
+async fn list_envelopes(client: &mut ImapClient, folder: &str) -> Result<(u32, EnvelopesMap)> {
+    let data = client.examine_mailbox(folder).await?;
+    let envelopes_count = data.exists.unwrap_or_default();
+    let uid_validity = data.uid_validity.map(NonZeroU32::get).unwrap_or_default();
+
+    let envelopes = if envelopes_count == 0 {
+        EnvelopesMap::default()
+    } else {
+        to_envelopes_map(client.fetch_all_envelopes().await?)
+    };
+
+    Ok((uid_validity, envelopes))
+}
+
+fn to_envelopes_map(envelopes: email::envelope::Envelopes) -> EnvelopesMap {
+    envelopes.into_iter().map(|e| (e.id.clone(), e)).collect()
+}
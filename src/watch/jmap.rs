@@ -0,0 +1,405 @@
+//! # JMAP watcher
+//!
+//! Module dedicated to watching JMAP mailboxes, based on the
+//! EventSource push channel. Every time the server pushes a new
+//! email state, the email changes since the previous state are
+//! fetched and applied to the previous listing. The mailbox is only
+//! listed again when the server cannot calculate those changes, or
+//! when the folder points to another mailbox.
+
+use async_trait::async_trait;
+use color_eyre::Result;
+use email::message::Message;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::jmap::{
+    client::{JmapClient, JmapMailbox},
+    push::PushEvent,
+};
+
+use super::{
+    event::{EnvelopesMap, MessageDetails, WatchEvent, WatchEventKind},
+    handler::WatchHandler,
+    shutdown_requested,
+    state::WatchState,
+    WatchChanges,
+};
+
+/// The mailbox properties that can change without the mailbox
+/// itself changing.
+const MAILBOX_COUNT_PROPERTIES: &[&str] = &[
+    "totalEmails",
+    "unreadEmails",
+    "totalThreads",
+    "unreadThreads",
+];
+
+/// The JMAP watcher.
+pub struct WatchJmapChanges {
+    client: JmapClient,
+    handler: WatchHandler,
+}
+
+impl WatchJmapChanges {
+    pub fn new(client: JmapClient, handler: WatchHandler) -> Self {
+        Self { client, handler }
+    }
+
+    pub fn new_boxed(client: JmapClient, handler: WatchHandler) -> Box<dyn WatchChanges> {
+        Box::new(Self::new(client, handler))
+    }
+
+    /// Load the details of the given events, if hooks need them.
+    async fn load_details(&self, events: &mut [WatchEvent]) {
+        if !self.handler.needs_details() {
+            return;
+        }
+
+        for event in events {
+            // removed emails may not exist anymore
+            if event.kind == WatchEventKind::MessageRemoved {
+                continue;
+            }
+
+            let id = &event.envelope.id;
+
+            let details = match self.client.read_msg(id).await {
+                Ok(raw) => MessageDetails::from_msg(&Message::from(raw)),
+                Err(err) => Err(err),
+            };
+
+            match details {
+                Ok(details) => event.details = Some(details),
+                Err(err) => {
+                    warn!(id, "cannot load message details: {err}");
+                    debug!("{err:?}");
+                }
+            }
+        }
+    }
+
+    /// Apply the email changes since the given state to the given
+    /// envelopes, or list the mailbox again if the server cannot
+    /// calculate them.
+    async fn update_envelopes(
+        &self,
+        mailbox: &JmapMailbox,
+        email_state: &str,
+        envelopes: &EnvelopesMap,
+    ) -> Result<(String, EnvelopesMap)> {
+        let updated = self
+            .client
+            .update_envelopes(&mailbox.id, email_state, envelopes)
+            .await?;
+
+        match updated {
+            Some(updated) => Ok(updated),
+            None => {
+                info!("cannot calculate JMAP email changes, listing mailbox again");
+                self.client.list_envelopes(&mailbox.id).await
+            }
+        }
+    }
+
+    /// Find the mailbox matching the given folder again if the
+    /// watched mailbox changed since the given state.
+    ///
+    /// Returns the next mailbox state, together with the mailbox if
+    /// it needed to be found again.
+    async fn update_mailbox(
+        &self,
+        folder: &str,
+        mailbox: &JmapMailbox,
+        mailbox_state: &str,
+    ) -> Result<(String, Option<JmapMailbox>)> {
+        let changes = self.client.changes("Mailbox", mailbox_state).await?;
+
+        if let Some(changes) = changes {
+            let only_counts = changes.updated_properties.as_ref().is_some_and(|props| {
+                props
+                    .iter()
+                    .all(|prop| MAILBOX_COUNT_PROPERTIES.contains(&prop.as_str()))
+            });
+
+            // renaming or moving any mailbox can change the one
+            // matching the folder, so only count updates are skipped
+            if only_counts || changes.updated.is_empty() && changes.destroyed.is_empty() {
+                return Ok((changes.state, None));
+            }
+        }
+
+        let (state, next_mailbox) = self.client.find_mailbox_with_state(folder).await?;
+
+        if next_mailbox.id != mailbox.id {
+            info!(
+                folder,
+                id = next_mailbox.id,
+                "JMAP folder now matches another mailbox"
+            );
+        }
+
+        Ok((state, Some(next_mailbox)))
+    }
+}
+
+#[async_trait]
+impl WatchChanges for WatchJmapChanges {
+    async fn watch_changes(
+        &self,
+        folder: &str,
+        mut wait_for_shutdown_request: watch::Receiver<bool>,
+    ) -> Result<()> {
+        info!("watching JMAP mailbox {folder} for envelope changes");
+
+        let (mut mailbox_state, mut mailbox) = self.client.find_mailbox_with_state(folder).await?;
+        debug!(folder, id = mailbox.id, "JMAP mailbox found");
+
+        // the push channel is opened before listing emails, so that
+        // changes happening in between are not missed
+        let mut push = self.client.subscribe().await?;
+        let (mut email_state, mut envelopes) = self.client.list_envelopes(&mailbox.id).await?;
+
+        let state = WatchState::jmap(&envelopes);
+        let mut events = self.handler.missed_events(&state, &envelopes);
+        self.load_details(&mut events).await;
+        self.handler.handle_events(&events).await;
+        self.handler.save_state(&state);
+        self.handler.set_ready(true);
+
+        loop {
+            let changed = tokio::select! {
+                _ = shutdown_requested(&mut wait_for_shutdown_request) => {
+                    break Ok(());
+                }
+                event = push.next() => match event? {
+                    PushEvent::Ping => continue,
+                    PushEvent::StateChange(changed) => changed,
+                },
+            };
+
+            let Some(states) = changed.get(self.client.account_id()) else {
+                continue;
+            };
+
+            let mailbox_changed = states
+                .get("Mailbox")
+                .is_some_and(|state| *state != mailbox_state);
+            let email_changed = states
+                .get("Email")
+                .is_some_and(|state| *state != email_state);
+
+            let mut mailbox_replaced = false;
+
+            if mailbox_changed {
+                debug!("received JMAP mailbox state change");
+
+                let (next_mailbox_state, next_mailbox) = self
+                    .update_mailbox(folder, &mailbox, &mailbox_state)
+                    .await?;
+
+                mailbox_state = next_mailbox_state;
+
+                if let Some(next_mailbox) = next_mailbox {
+                    mailbox_replaced = next_mailbox.id != mailbox.id;
+                    mailbox = next_mailbox;
+                }
+            }
+
+            let (next_email_state, next_envelopes) = if mailbox_replaced {
+                self.client.list_envelopes(&mailbox.id).await?
+            } else if email_changed {
+                info!("received JMAP email state change");
+                self.update_envelopes(&mailbox, &email_state, &envelopes)
+                    .await?
+            } else {
+                continue;
+            };
+
+            let mut events = WatchEvent::diff(&envelopes, &next_envelopes);
+            self.load_details(&mut events).await;
+            self.handler.handle_events(&events).await;
+            self.handler.save_state(&WatchState::jmap(&next_envelopes));
+            email_state = next_email_state;
+            envelopes = next_envelopes;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use email::account::config::AccountConfig;
+    use tokio::time::{sleep, timeout};
+
+    use crate::{
+        jmap::mock::JmapServer,
+        watch::{
+            config::WatchHooks,
+            hook::{WatchCmdConfig, WatchExecConfig, WatchHookConfig},
+            status::{WatchStatus, WatchStatusSummary},
+        },
+    };
+
+    use super::*;
+
+    /// Build a handler whose hook appends the kind and the id of
+    /// events to the given file.
+    fn handler(folder: &str, log: &Path) -> WatchHandler {
+        let cmd = WatchCmdConfig::Exec(WatchExecConfig {
+            program: "sh".into(),
+            args: Some(vec![
+                "-c".into(),
+                format!("echo $MIRADOR_EVENT $MIRADOR_ID >> {}", log.display()),
+            ]),
+        });
+
+        let hooks = WatchHooks {
+            on_any_change: Some(WatchHookConfig {
+                cmd: Some(cmd),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let account_config = Arc::new(AccountConfig::default());
+        WatchHandler::new(account_config, folder, hooks)
+    }
+
+    /// Wait for the given number of lines in the given file, then
+    /// return them sorted and truncate the file.
+    async fn wait_for_lines(log: &Path, count: usize) -> Vec<String> {
+        let lines = timeout(Duration::from_secs(5), async {
+            loop {
+                let log = std::fs::read_to_string(log).unwrap_or_default();
+                let mut lines: Vec<_> = log.lines().map(ToOwned::to_owned).collect();
+
+                if lines.len() >= count {
+                    lines.sort();
+                    return lines;
+                }
+
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("hooks not executed in time");
+
+        std::fs::write(log, "").unwrap();
+        lines
+    }
+
+    #[tokio::test]
+    async fn watch_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+
+        let server = JmapServer::start().await;
+        server.create_mailbox("work", "Work", None);
+        server.add_email("e1", "work", "first");
+        server.add_email("e2", "inbox", "second");
+
+        let status = WatchStatus::default();
+        let _guard = status.register("", "Work");
+        let mut ready = status.subscribe();
+        let handler = handler("Work", &log).with_status(status);
+        let watcher = WatchJmapChanges::new(server.client().await, handler);
+
+        let (shutdown, wait_for_shutdown_request) = watch::channel(false);
+        let task = tokio::spawn(async move {
+            watcher
+                .watch_changes("Work", wait_for_shutdown_request)
+                .await
+        });
+
+        ready
+            .wait_for(|status| WatchStatusSummary(status).is_ready())
+            .await
+            .unwrap();
+        server.take_calls();
+
+        // changes are fetched since the previous state
+        server.add_email("e3", "work", "third");
+        server.add_email("e4", "inbox", "fourth");
+        server.mark_seen("e1");
+        server.push();
+
+        let lines = wait_for_lines(&log, 2).await;
+        assert_eq!(lines, ["flags-changed e1", "message-added e3"]);
+        let calls = server.take_calls();
+        assert!(calls.contains(&String::from("Email/changes")));
+        assert!(!calls.contains(&String::from("Email/query")));
+
+        // the folder now matches another mailbox
+        server.rename_mailbox("work", "Old");
+        server.create_mailbox("work2", "Work", None);
+        server.move_email("e4", "work2");
+        server.push();
+
+        let lines = wait_for_lines(&log, 3).await;
+        assert_eq!(
+            lines,
+            [
+                "message-added e4",
+                "message-removed e1",
+                "message-removed e3"
+            ]
+        );
+
+        // the mailbox is listed again when changes are lost
+        server.add_email("e5", "work2", "fifth");
+        server.forget_changes();
+        server.push();
+
+        let lines = wait_for_lines(&log, 1).await;
+        assert_eq!(lines, ["message-added e5"]);
+        assert!(server.take_calls().contains(&String::from("Email/query")));
+
+        shutdown.send(true).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn shutdown_does_not_wait_for_push() {
+        // the server runs on its own runtime, so that its push
+        // connection stays open while the watcher shuts down
+        let server_rt = tokio::runtime::Runtime::new().unwrap();
+        let server = server_rt.block_on(JmapServer::start());
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let status = WatchStatus::default();
+            let _guard = status.register("", "Inbox");
+            let mut ready = status.subscribe();
+            let handler = handler("Inbox", &log).with_status(status.clone());
+            let watcher = WatchJmapChanges::new(server.client().await, handler);
+
+            let (shutdown, wait_for_shutdown_request) = watch::channel(false);
+            let task = tokio::spawn(async move {
+                watcher
+                    .watch_changes("Inbox", wait_for_shutdown_request)
+                    .await
+            });
+
+            ready
+                .wait_for(|status| WatchStatusSummary(status).is_ready())
+                .await
+                .unwrap();
+
+            shutdown.send(true).unwrap();
+            task.await.unwrap().unwrap();
+        });
+
+        // the push stream is still open and no ping is sent, the
+        // runtime must not wait for its reader
+        let start = std::time::Instant::now();
+        drop(rt);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        drop(server_rt);
+    }
+}
@@ -0,0 +1,140 @@
+//! # Maildir watcher
+//!
+//! Module dedicated to watching Maildir folders, based on file
+//! system notifications.
+
+use async_trait::async_trait;
+use color_eyre::{eyre::OptionExt, Result};
+use email::{envelope::Envelopes, maildir::MaildirContextSync, message::Message};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, trace, warn};
+
+use super::{
+    event::{EnvelopesMap, MessageDetails, WatchEvent, WatchEventKind},
+    handler::WatchHandler,
+    shutdown_requested,
+    state::WatchState,
+    WatchChanges,
+};
+
+/// The Maildir watcher.
+pub struct WatchMaildirChanges {
+    ctx: MaildirContextSync,
+    handler: WatchHandler,
+}
+
+impl WatchMaildirChanges {
+    pub fn new(ctx: &MaildirContextSync, handler: WatchHandler) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            handler,
+        }
+    }
+
+    pub fn new_boxed(ctx: &MaildirContextSync, handler: WatchHandler) -> Box<dyn WatchChanges> {
+        Box::new(Self::new(ctx, handler))
+    }
+
+    /// Load the details of the given events, if hooks need them.
+    ///
+    /// The given function reads the raw message matching the given
+    /// identifier.
+    fn load_details(&self, read_msg: impl Fn(&str) -> Result<Vec<u8>>, events: &mut [WatchEvent]) {
+        if !self.handler.needs_details() {
+            return;
+        }
+
+        for event in events {
+            // removed messages cannot be read anymore
+            if event.kind == WatchEventKind::MessageRemoved {
+                continue;
+            }
+
+            let id = &event.envelope.id;
+
+            let details =
+                read_msg(id).and_then(|raw| MessageDetails::from_msg(&Message::from(raw)));
+
+            match details {
+                Ok(details) => event.details = Some(details),
+                Err(err) => {
+                    warn!(id, "cannot load message details: {err}");
+                    debug!("{err:?}");
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl WatchChanges for WatchMaildirChanges {
+    async fn watch_changes(
+        &self,
+        folder: &str,
+        mut wait_for_shutdown_request: watch::Receiver<bool>,
+    ) -> Result<()> {
+        info!("watching Maildir folder {folder} for envelope changes");
+
+        let session = self.ctx.lock().await;
+        let mdir = session.get_maildir_from_folder_alias(folder)?;
+
+        let read_envelopes = || -> Result<EnvelopesMap> {
+            let entries = mdir.read()?;
+            let envelopes = Envelopes::from_mdir_entries(entries, None);
+            Ok(envelopes.into_iter().map(|e| (e.id.clone(), e)).collect())
+        };
+
+        let read_msg = |id: &str| -> Result<Vec<u8>> {
+            let entry = mdir
+                .find(id)?
+                .ok_or_eyre(format!("cannot find Maildir message {id}"))?;
+            Ok(entry.read()?)
+        };
+
+        let mut envelopes = read_envelopes()?;
+
+        let state = WatchState::maildir(&envelopes);
+        let mut events = self.handler.missed_events(&state, &envelopes);
+        self.load_details(read_msg, &mut events);
+        self.handler.handle_events(&events).await;
+        self.handler.save_state(&state);
+
+        // file system events are forwarded to an async channel, so
+        // that the runtime is not blocked while waiting for them
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = RecommendedWatcher::new(
+            move |res| {
+                let _ = tx.send(res);
+            },
+            Default::default(),
+        )?;
+        watcher.watch(mdir.path(), RecursiveMode::Recursive)?;
+        debug!("watching maildir folder {folder:?}…");
+        self.handler.set_ready(true);
+
+        loop {
+            tokio::select! {
+                _ = shutdown_requested(&mut wait_for_shutdown_request) => {
+                    break Ok(());
+                }
+                res = rx.recv() => match res {
+                    None => break Ok(()),
+                    Some(Ok(_evt)) => {
+                        trace!("received filesystem change event: {_evt:?}");
+                        let next_envelopes = read_envelopes()?;
+                        let mut events = WatchEvent::diff(&envelopes, &next_envelopes);
+                        self.load_details(read_msg, &mut events);
+                        self.handler.handle_events(&events).await;
+                        self.handler.save_state(&WatchState::maildir(&next_envelopes));
+                        envelopes = next_envelopes;
+                    }
+                    Some(Err(_err)) => {
+                        debug!("error while receiving filesystem change event: {_err}");
+                        debug!("{_err:?}");
+                    }
+                }
+            }
+        }
+    }
+}
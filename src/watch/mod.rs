@@ -0,0 +1,63 @@
+//! # Watch
+//!
+//! Module dedicated to watching mailboxes. The [`config`] module
+//! contains watch-related user configuration, the [`event`] module
+//! contains envelope changes detected by watchers, and the
+//! [`handler`] module reacts to those changes by executing
+//! [`hook`]s, rendered by the [`template`] module. Hooks can be
+//! filtered by rules, using [`pattern`]s.
+//! The [`state`] module persists the state of watched folders, the
+//! [`status`] module reports whether they are being watched, and
+//! the [`output`] module prints events in a machine-readable format.
+//!
+//! Backend-specific watchers live in the [`imap`], [`maildir`],
+//! [`notmuch`], [`jmap`] and [`pop3`] modules. They all implement the
+//! [`WatchChanges`] trait.
+
+pub mod config;
+pub mod event;
+pub mod handler;
+pub mod hook;
+#[cfg(feature = "imap")]
+pub mod imap;
+#[cfg(feature = "jmap")]
+pub mod jmap;
+#[cfg(feature = "maildir")]
+pub mod maildir;
+#[cfg(feature = "notmuch")]
+pub mod notmuch;
+pub mod output;
+pub mod pattern;
+#[cfg(feature = "pop3")]
+pub mod pop3;
+pub mod state;
+pub mod status;
+pub mod template;
+
+use async_trait::async_trait;
+use color_eyre::Result;
+use tokio::sync::watch;
+
+/// The backend-agnostic watcher.
+#[async_trait]
+pub trait WatchChanges: Send + Sync {
+    /// Watch the given folder for envelope changes.
+    ///
+    /// The function runs until either an error occurs or a shutdown
+    /// is requested, in which case the watcher gracefully stops and
+    /// returns `Ok`.
+    async fn watch_changes(
+        &self,
+        folder: &str,
+        wait_for_shutdown_request: watch::Receiver<bool>,
+    ) -> Result<()>;
+}
+
+/// Wait until a shutdown is requested.
+///
+/// Also resolves when the shutdown request sender is dropped.
+pub async fn shutdown_requested(wait_for_shutdown_request: &mut watch::Receiver<bool>) {
+    let _ = wait_for_shutdown_request
+        .wait_for(|requested| *requested)
+        .await;
+}
@@ -0,0 +1,361 @@
+//! # Notmuch watcher
+//!
+//! Module dedicated to watching Notmuch queries. Watched folders are
+//! Notmuch queries, for example `tag:inbox and tag:unread`: messages
+//! starting to match the query are reported as added, messages not
+//! matching it anymore as removed, and tags changes as flags
+//! changes.
+//!
+//! The database revision is polled, and the query is only executed
+//! again when the revision changes.
+
+use std::{fs, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use color_eyre::{
+    eyre::{eyre, OptionExt},
+    Result,
+};
+use email::{
+    envelope::{Envelope, Envelopes},
+    message::Message,
+    notmuch::config::NotmuchConfig,
+};
+use notmuch::{Database, DatabaseMode};
+use shellexpand_utils::shellexpand_path;
+use tokio::{sync::watch, task::spawn_blocking, time::sleep};
+use tracing::{debug, info, warn};
+
+use crate::account::config::DEFAULT_FOLDER;
+
+use super::{
+    event::{EnvelopesMap, MessageDetails, WatchEvent, WatchEventKind},
+    handler::WatchHandler,
+    shutdown_requested,
+    state::WatchState,
+    WatchChanges,
+};
+
+/// The query watched when no folder is configured.
+pub const DEFAULT_QUERY: &str = "tag:inbox";
+
+/// The interval between two checks of the database revision.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The Notmuch watcher.
+pub struct WatchNotmuchChanges {
+    config: Arc<NotmuchConfig>,
+    handler: WatchHandler,
+}
+
+impl WatchNotmuchChanges {
+    pub fn new(config: Arc<NotmuchConfig>, handler: WatchHandler) -> Self {
+        Self { config, handler }
+    }
+
+    pub fn new_boxed(config: Arc<NotmuchConfig>, handler: WatchHandler) -> Box<dyn WatchChanges> {
+        Box::new(Self::new(config, handler))
+    }
+
+    /// Search messages matching the given query, in the background.
+    ///
+    /// Returns `None` if the database did not change since the given
+    /// revision.
+    async fn search(
+        &self,
+        query: &str,
+        revision: Option<u64>,
+    ) -> Result<Option<(u64, EnvelopesMap)>> {
+        let config = self.config.clone();
+        let query = query.to_owned();
+
+        spawn_blocking(move || {
+            let db = open_db(&config)?;
+            let next_revision = db.revision().revision as u64;
+
+            if revision == Some(next_revision) {
+                return Ok(None);
+            }
+
+            let envelopes = search(&db, &query)?;
+            debug!(query, revision = next_revision, "notmuch database changed");
+            Ok(Some((next_revision, envelopes)))
+        })
+        .await?
+    }
+
+    /// Load the details of the given events, if hooks need them.
+    async fn load_details(&self, events: &mut [WatchEvent]) {
+        if !self.handler.needs_details() {
+            return;
+        }
+
+        for event in events {
+            // removed messages may not exist anymore
+            if event.kind == WatchEventKind::MessageRemoved {
+                continue;
+            }
+
+            let config = self.config.clone();
+            let id = event.envelope.id.clone();
+
+            let details = spawn_blocking(move || {
+                let raw = read_msg(&open_db(&config)?, &id)?;
+                MessageDetails::from_msg(&Message::from(raw))
+            })
+            .await
+            .map_err(Into::into)
+            .and_then(|res| res);
+
+            let id = &event.envelope.id;
+
+            match details {
+                Ok(details) => event.details = Some(details),
+                Err(err) => {
+                    warn!(id, "cannot load message details: {err}");
+                    debug!("{err:?}");
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl WatchChanges for WatchNotmuchChanges {
+    async fn watch_changes(
+        &self,
+        folder: &str,
+        mut wait_for_shutdown_request: watch::Receiver<bool>,
+    ) -> Result<()> {
+        let query = to_query(folder);
+        info!("watching Notmuch query {query} for envelope changes");
+
+        // without previous revision, the search always returns
+        // envelopes
+        let (mut revision, mut envelopes) = self
+            .search(&query, None)
+            .await?
+            .ok_or_eyre(format!("cannot search Notmuch query {query}"))?;
+
+        let state = WatchState::notmuch(&envelopes);
+        let mut events = self.handler.missed_events(&state, &envelopes);
+        self.load_details(&mut events).await;
+        self.handler.handle_events(&events).await;
+        self.handler.save_state(&state);
+        self.handler.set_ready(true);
+
+        loop {
+            tokio::select! {
+                _ = shutdown_requested(&mut wait_for_shutdown_request) => {
+                    break Ok(());
+                }
+                _ = sleep(POLL_INTERVAL) => {
+                    let Some((next_revision, next_envelopes)) =
+                        self.search(&query, Some(revision)).await?
+                    else {
+                        continue;
+                    };
+
+                    let mut events = WatchEvent::diff(&envelopes, &next_envelopes);
+                    self.load_details(&mut events).await;
+                    self.handler.handle_events(&events).await;
+                    self.handler.save_state(&WatchState::notmuch(&next_envelopes));
+                    revision = next_revision;
+                    envelopes = next_envelopes;
+                }
+            }
+        }
+    }
+}
+
+/// Get the Notmuch query matching the given folder.
+///
+/// Folders are queries, except for the default folder which matches
+/// [`DEFAULT_QUERY`].
+pub fn to_query(folder: &str) -> String {
+    if folder.eq_ignore_ascii_case(DEFAULT_FOLDER) {
+        DEFAULT_QUERY.to_owned()
+    } else {
+        folder.to_owned()
+    }
+}
+
+/// Open the Notmuch database, in read-only mode.
+///
+/// Watching must not prevent other programs like `notmuch new` from
+/// writing to the database.
+pub fn open_db(config: &NotmuchConfig) -> Result<Database> {
+    let db_path = config.database_path.as_ref().map(shellexpand_path);
+    let config_path = config.find_config_path();
+    let profile = config.find_profile();
+
+    let db = Database::open_with_config(db_path, DatabaseMode::ReadOnly, config_path, profile)
+        .map_err(|err| eyre!(err).wrap_err("cannot open Notmuch database"))?;
+
+    Ok(db)
+}
+
+/// Search envelopes matching the given query.
+pub fn search(db: &Database, query: &str) -> Result<EnvelopesMap> {
+    let msgs = db
+        .create_query(query)
+        .and_then(|query| query.search_messages())
+        .map_err(|err| eyre!(err).wrap_err(format!("cannot search Notmuch query {query}")))?;
+
+    let envelopes = Envelopes::from_notmuch_msgs(msgs);
+    Ok(envelopes.into_iter().map(|e| (e.id.clone(), e)).collect())
+}
+
+/// Read the raw message matching the given identifier.
+pub fn read_msg(db: &Database, id: &str) -> Result<Vec<u8>> {
+    let msg = db
+        .find_message(id)
+        .map_err(|err| eyre!(err))?
+        .ok_or_eyre(format!("cannot find Notmuch message {id}"))?;
+
+    Ok(fs::read(msg.filename())?)
+}
+
+/// Find the most recent envelope matching the given query.
+pub fn find_newest(db: &Database, query: &str) -> Result<Option<Envelope>> {
+    let envelopes = search(db, query)?;
+    Ok(envelopes.into_values().max_by_key(|e| e.date))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use email::account::config::AccountConfig;
+
+    use super::*;
+    use crate::watch::config::WatchHooks;
+
+    /// Create a Notmuch database containing one message per given
+    /// tag, whose identifier is `<tag>@localhost`.
+    fn create_db(dir: &Path, tags: &[&str]) -> Arc<NotmuchConfig> {
+        for subdir in ["cur", "new", "tmp"] {
+            fs::create_dir_all(dir.join(subdir)).unwrap();
+        }
+
+        let db = notmuch::Database::create(dir).unwrap();
+
+        for (i, tag) in tags.iter().enumerate() {
+            let path = dir.join("cur").join(format!("{i}:2,S"));
+            let msg = format!(
+                "Message-ID: <{tag}@localhost>\r\n\
+                 From: {tag}@localhost\r\n\
+                 To: me@localhost\r\n\
+                 Subject: {tag}\r\n\
+                 Date: Sun, 18 Oct 2026 10:0{i}:00 +0000\r\n\
+                 \r\n\
+                 Body of {tag}.\r\n"
+            );
+            fs::write(&path, msg).unwrap();
+
+            let msg = db.index_file(&path, None).unwrap();
+            msg.add_tag(tag).unwrap();
+        }
+
+        db.close().unwrap();
+
+        Arc::new(NotmuchConfig {
+            database_path: Some(dir.to_owned()),
+            // an empty path prevents Notmuch from reading the user
+            // configuration
+            config_path: Some(PathBuf::new()),
+            ..Default::default()
+        })
+    }
+
+    fn tag(config: &NotmuchConfig, id: &str, tag: &str) {
+        let db = Database::open_with_config(
+            config.database_path.as_ref(),
+            DatabaseMode::ReadWrite,
+            config.find_config_path(),
+            None,
+        )
+        .unwrap();
+
+        db.find_message(id).unwrap().unwrap().add_tag(tag).unwrap();
+        db.close().unwrap();
+    }
+
+    fn watcher(config: Arc<NotmuchConfig>) -> WatchNotmuchChanges {
+        let account_config = Arc::new(AccountConfig::default());
+        let handler = WatchHandler::new(account_config, "INBOX", WatchHooks::default());
+        WatchNotmuchChanges::new(config, handler)
+    }
+
+    fn ids(envelopes: &EnvelopesMap) -> Vec<&str> {
+        let mut ids: Vec<_> = envelopes.keys().map(String::as_str).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn default_query() {
+        assert_eq!(to_query("INBOX"), DEFAULT_QUERY);
+        assert_eq!(to_query("inbox"), DEFAULT_QUERY);
+        assert_eq!(to_query("tag:unread"), "tag:unread");
+    }
+
+    #[test]
+    fn search_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = create_db(dir.path(), &["inbox", "work", "spam"]);
+        let db = open_db(&config).unwrap();
+
+        let envelopes = search(&db, "tag:inbox or tag:work").unwrap();
+        assert_eq!(ids(&envelopes), ["inbox@localhost", "work@localhost"]);
+
+        let newest = find_newest(&db, "not tag:inbox").unwrap().unwrap();
+        assert_eq!(newest.id, "spam@localhost");
+
+        let raw = read_msg(&db, "work@localhost").unwrap();
+        assert!(String::from_utf8(raw).unwrap().contains("Body of work."));
+        assert!(read_msg(&db, "unknown@localhost").is_err());
+    }
+
+    #[tokio::test]
+    async fn search_only_when_revision_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = create_db(dir.path(), &["inbox", "work"]);
+        let watcher = watcher(config.clone());
+        let query = to_query("INBOX");
+
+        let (revision, envelopes) = watcher.search(&query, None).await.unwrap().unwrap();
+        assert_eq!(ids(&envelopes), ["inbox@localhost"]);
+
+        // the database did not change
+        let res = watcher.search(&query, Some(revision)).await.unwrap();
+        assert!(res.is_none());
+
+        // the message starts to match the query
+        tag(&config, "work@localhost", "inbox");
+
+        let (next_revision, next_envelopes) = watcher
+            .search(&query, Some(revision))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(next_revision > revision);
+
+        let events = WatchEvent::diff(&envelopes, &next_envelopes);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, WatchEventKind::MessageAdded);
+        assert_eq!(events[0].envelope.id, "work@localhost");
+    }
+
+    #[test]
+    fn open_missing_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NotmuchConfig {
+            database_path: Some(dir.path().join("missing")),
+            config_path: Some(PathBuf::new()),
+            ..Default::default()
+        };
+
+        assert!(open_db(&config).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! # Watch output
+//!
+//! Module dedicated to the machine-readable output of watch events.
+//! When enabled, every event is printed to the standard output as a
+//! JSON object, one per line (JSON Lines).
+
+use std::io::{stdout, Write};
+
+use color_eyre::Result;
+use email::{account::config::AccountConfig, envelope::Address};
+use serde::Serialize;
+
+use super::event::WatchEvent;
+
+/// The JSON representation of a watch event.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchEventOutput {
+    pub account: String,
+    pub folder: String,
+    pub event: String,
+    pub id: String,
+    pub message_id: String,
+    pub subject: String,
+    pub sender: AddressOutput,
+    /// The first address of the message header To, if any.
+    pub to: Option<AddressOutput>,
+    pub date: String,
+    pub flags: Vec<String>,
+}
+
+impl WatchEventOutput {
+    pub fn new(config: &AccountConfig, folder: &str, event: &WatchEvent) -> Self {
+        let envelope = &event.envelope;
+
+        Self {
+            account: config.name.clone(),
+            folder: folder.to_owned(),
+            event: event.kind.to_string(),
+            id: envelope.id.clone(),
+            message_id: envelope.message_id.clone(),
+            subject: envelope.subject.clone(),
+            sender: AddressOutput::from(&envelope.from),
+            to: Some(&envelope.to)
+                .filter(|addr| !addr.addr.is_empty())
+                .map(AddressOutput::from),
+            date: envelope.date.to_rfc3339(),
+            flags: envelope.flags.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// Print the current event as a JSON line to the standard
+    /// output.
+    pub fn print(&self) -> Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+
+        // the whole line is written at once, so that events coming
+        // from different watchers do not interleave
+        let mut stdout = stdout().lock();
+        stdout.write_all(&line)?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+}
+
+/// The JSON representation of an email address.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct AddressOutput {
+    pub name: Option<String>,
+    pub address: String,
+}
+
+impl From<&Address> for AddressOutput {
+    fn from(addr: &Address) -> Self {
+        Self {
+            name: addr.name.clone(),
+            address: addr.addr.clone(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! # Watch pattern
+//!
+//! Module dedicated to patterns used by watch rules to match
+//! envelope headers.
+
+use std::fmt;
+
+use regex::{Regex, RegexBuilder};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The envelope header pattern.
+///
+/// Patterns built with [`Pattern::new`] are globs, where `*` matches
+/// any sequence of characters and `?` matches any single character
+/// (`*@pagerduty.com`), unless surrounded by slashes
+/// (`/^\[alert\]/`), in which case they are regular expressions.
+/// Patterns built with [`Pattern::regex`] are always regular
+/// expressions. In all cases, matching is case-insensitive.
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: impl ToString) -> Result<Self, regex::Error> {
+        let source = source.to_string();
+
+        let regex = match strip_slashes(&source) {
+            Some(regex) => regex.to_owned(),
+            None => glob_to_regex(&source),
+        };
+
+        let regex = RegexBuilder::new(&regex).case_insensitive(true).build()?;
+
+        Ok(Self { source, regex })
+    }
+
+    /// Build a pattern from the given regular expression.
+    ///
+    /// The regular expression is not anchored, and surrounding
+    /// slashes are optional.
+    pub fn regex(source: impl ToString) -> Result<Self, regex::Error> {
+        let source = source.to_string();
+        let regex = strip_slashes(&source).unwrap_or(&source);
+        let regex = RegexBuilder::new(regex).case_insensitive(true).build()?;
+
+        Ok(Self { source, regex })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.regex.is_match(haystack)
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pattern({:?})", self.source)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Eq for Pattern {}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::new(source).map_err(de::Error::custom)
+    }
+}
+
+/// Deserialize an optional pattern as a regular expression, see
+/// [`Pattern::regex`].
+pub fn deserialize_regex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Pattern>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(Pattern::regex)
+        .transpose()
+        .map_err(de::Error::custom)
+}
+
+/// Get the content of the given pattern surrounded by slashes.
+fn strip_slashes(pattern: &str) -> Option<&str> {
+    pattern.strip_prefix('/').and_then(|s| s.strip_suffix('/'))
+}
+
+/// Turn the given glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        let pattern = Pattern::new("*@pagerduty.com").unwrap();
+
+        assert!(pattern.is_match("alerts@pagerduty.com"));
+        assert!(pattern.is_match("Alerts@PagerDuty.com"));
+        assert!(!pattern.is_match("alerts@pagerduty.com.evil.org"));
+        assert!(!pattern.is_match("alerts@pagerdutyXcom"));
+    }
+
+    #[test]
+    fn glob_single_char() {
+        let pattern = Pattern::new("invoice-??").unwrap();
+
+        assert!(pattern.is_match("invoice-42"));
+        assert!(!pattern.is_match("invoice-421"));
+    }
+
+    #[test]
+    fn glob_with_slashes_is_regex() {
+        let pattern = Pattern::new("/^\\[alert\\]/").unwrap();
+
+        assert!(pattern.is_match("[ALERT] disk full"));
+        assert!(!pattern.is_match("re: [alert] disk full"));
+    }
+
+    #[test]
+    fn regex() {
+        let pattern = Pattern::regex("invoice #[0-9]+").unwrap();
+
+        assert!(pattern.is_match("Your Invoice #1234 is ready"));
+        assert!(!pattern.is_match("Your invoice is ready"));
+    }
+
+    #[test]
+    fn regex_with_slashes() {
+        let pattern = Pattern::regex("/^\\[newsletter\\]/").unwrap();
+
+        assert!(pattern.is_match("[Newsletter] October"));
+        assert!(!pattern.is_match("Fwd: [newsletter] October"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Pattern::regex("(").is_err());
+        assert!(Pattern::new("/(/").is_err());
+    }
+
+    #[test]
+    fn eq_compares_sources() {
+        assert_eq!(Pattern::new("a*").unwrap(), Pattern::new("a*").unwrap());
+        assert_ne!(Pattern::new("a*").unwrap(), Pattern::new("b*").unwrap());
+    }
+}
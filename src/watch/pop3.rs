@@ -0,0 +1,336 @@
+//! # POP3 watcher
+//!
+//! Module dedicated to watching POP3 mailboxes. POP3 has no push
+//! mechanism, so the mailbox is polled: a new session is opened at
+//! every poll, and the unique identifiers (UIDL) of messages are
+//! compared to the ones of the previous poll. Only message additions
+//! are reported, since POP3 exposes neither flags nor folders.
+
+use std::{collections::BTreeSet, time::Duration};
+
+use async_trait::async_trait;
+use color_eyre::{eyre::bail, Result};
+use email::{
+    envelope::{Envelope, Flags},
+    message::Message,
+};
+use tokio::{sync::watch, time::sleep};
+use tracing::{debug, info, warn};
+
+use crate::pop3::client::{Pop3Client, Pop3ClientBuilder};
+
+use super::{
+    event::{EnvelopesMap, MessageDetails, WatchEvent, WatchEventKind},
+    handler::WatchHandler,
+    shutdown_requested,
+    state::WatchState,
+    WatchChanges,
+};
+
+/// The only folder a POP3 mailbox has.
+pub const INBOX: &str = "INBOX";
+
+/// The POP3 watcher.
+pub struct WatchPop3Changes {
+    client_builder: Pop3ClientBuilder,
+    poll_interval: Duration,
+    handler: WatchHandler,
+}
+
+impl WatchPop3Changes {
+    pub fn new(
+        client_builder: Pop3ClientBuilder,
+        poll_interval: Duration,
+        handler: WatchHandler,
+    ) -> Self {
+        Self {
+            client_builder,
+            poll_interval,
+            handler,
+        }
+    }
+
+    pub fn new_boxed(
+        client_builder: Pop3ClientBuilder,
+        poll_interval: Duration,
+        handler: WatchHandler,
+    ) -> Box<dyn WatchChanges> {
+        Box::new(Self::new(client_builder, poll_interval, handler))
+    }
+
+    /// Poll the mailbox once.
+    ///
+    /// Returns the unique identifiers of all messages, as well as
+    /// events for the ones not part of the given known identifiers.
+    /// On first poll, known identifiers are taken from the saved
+    /// state instead.
+    async fn poll(
+        &self,
+        known: Option<&BTreeSet<String>>,
+    ) -> Result<(EnvelopesMap, Vec<WatchEvent>)> {
+        let mut client = self.client_builder.clone().build().await?;
+        let uids = client.list_uids().await?;
+        debug!("found {} POP3 message(s)", uids.len());
+
+        // envelopes are placeholders until headers are fetched,
+        // which is only done for new messages
+        let envelopes: EnvelopesMap = uids
+            .iter()
+            .map(|(_, uid)| {
+                let envelope = Envelope {
+                    id: uid.clone(),
+                    ..Default::default()
+                };
+                (uid.clone(), envelope)
+            })
+            .collect();
+
+        let mut events = match known {
+            None => self
+                .handler
+                .missed_events(&WatchState::pop3(&envelopes), &envelopes),
+            Some(known) => envelopes
+                .values()
+                .filter(|envelope| !known.contains(&envelope.id))
+                .map(|envelope| WatchEvent::new(WatchEventKind::MessageAdded, envelope.clone()))
+                .collect(),
+        };
+
+        for event in &mut events {
+            let uid = event.envelope.id.clone();
+            let Some((num, _)) = uids.iter().find(|(_, id)| *id == uid) else {
+                continue;
+            };
+
+            if let Err(err) = self.load_envelope(&mut client, *num, event).await {
+                warn!(uid, "cannot load message envelope: {err}");
+                debug!("{err:?}");
+            }
+        }
+
+        client.quit().await;
+
+        events.sort_by(|a, b| a.envelope.date.cmp(&b.envelope.date));
+        Ok((envelopes, events))
+    }
+
+    /// Fetch the headers of the given message, or the whole message
+    /// if hooks need details.
+    async fn load_envelope(
+        &self,
+        client: &mut Pop3Client,
+        num: u32,
+        event: &mut WatchEvent,
+    ) -> Result<()> {
+        let raw = if self.handler.needs_details() {
+            client.read_msg(num).await?
+        } else {
+            client.read_headers(num).await?
+        };
+
+        let id = event.envelope.id.clone();
+        event.envelope = Envelope::from_msg(id, Flags::default(), Message::from(raw.as_slice()));
+
+        if self.handler.needs_details() {
+            event.details = Some(MessageDetails::from_msg(&Message::from(raw))?);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WatchChanges for WatchPop3Changes {
+    async fn watch_changes(
+        &self,
+        folder: &str,
+        mut wait_for_shutdown_request: watch::Receiver<bool>,
+    ) -> Result<()> {
+        if !folder.eq_ignore_ascii_case(INBOX) {
+            bail!("POP3 mailboxes only have an {INBOX} folder");
+        }
+
+        let interval = self.poll_interval;
+        info!("polling POP3 mailbox every {interval:?} for new messages");
+
+        let (envelopes, events) = self.poll(None).await?;
+        self.handler.handle_events(&events).await;
+        self.handler.save_state(&WatchState::pop3(&envelopes));
+        self.handler.set_ready(true);
+
+        let mut known: BTreeSet<String> = envelopes.into_keys().collect();
+
+        loop {
+            tokio::select! {
+                _ = shutdown_requested(&mut wait_for_shutdown_request) => {
+                    break Ok(());
+                }
+                _ = sleep(interval) => (),
+            };
+
+            let (envelopes, events) = self.poll(Some(&known)).await?;
+            self.handler.handle_events(&events).await;
+
+            let next_known: BTreeSet<String> = envelopes.keys().cloned().collect();
+
+            if next_known != known {
+                self.handler.save_state(&WatchState::pop3(&envelopes));
+                known = next_known;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use email::account::config::AccountConfig;
+    use tokio::{task::JoinHandle, time::timeout};
+
+    use crate::{
+        pop3::mock::Pop3Server,
+        watch::{
+            config::WatchHooks,
+            hook::{WatchCmdConfig, WatchExecConfig, WatchHookConfig},
+            state::WatchStateStore,
+            status::{WatchStatus, WatchStatusSummary},
+        },
+    };
+
+    use super::*;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Start a watcher whose hook appends the id and the subject of
+    /// added messages to the given log file. Returns once the
+    /// watcher is ready.
+    async fn start(
+        server: &Pop3Server,
+        store: &WatchStateStore,
+        log: &Path,
+    ) -> (watch::Sender<bool>, JoinHandle<Result<()>>) {
+        let cmd = WatchCmdConfig::Exec(WatchExecConfig {
+            program: "sh".into(),
+            args: Some(vec![
+                "-c".into(),
+                format!("echo $MIRADOR_ID $MIRADOR_SUBJECT >> {}", log.display()),
+            ]),
+        });
+
+        let hooks = WatchHooks {
+            on_message_added: Some(WatchHookConfig {
+                cmd: Some(cmd),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let status = WatchStatus::default();
+        let guard = status.register("", INBOX);
+        let mut ready = status.subscribe();
+
+        let account_config = Arc::new(AccountConfig::default());
+        let handler = WatchHandler::new(account_config, INBOX, hooks)
+            .with_state_store(store.clone(), 10)
+            .with_status(status);
+        let watcher = WatchPop3Changes::new(server.client_builder(), POLL_INTERVAL, handler);
+
+        let (shutdown, wait_for_shutdown_request) = watch::channel(false);
+        let task = tokio::spawn(async move {
+            let _guard = guard;
+            watcher
+                .watch_changes(INBOX, wait_for_shutdown_request)
+                .await
+        });
+
+        timeout(Duration::from_secs(5), async {
+            ready
+                .wait_for(|status| WatchStatusSummary(status).is_ready())
+                .await
+                .unwrap();
+        })
+        .await
+        .expect("watcher not ready in time");
+
+        (shutdown, task)
+    }
+
+    /// Wait for the given number of lines in the given file, then
+    /// return them and truncate the file.
+    async fn wait_for_lines(log: &Path, count: usize) -> Vec<String> {
+        let lines = timeout(Duration::from_secs(5), async {
+            loop {
+                let log = std::fs::read_to_string(log).unwrap_or_default();
+                let lines: Vec<_> = log.lines().map(ToOwned::to_owned).collect();
+
+                if lines.len() >= count {
+                    return lines;
+                }
+
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("hooks not executed in time");
+
+        std::fs::write(log, "").unwrap();
+        lines
+    }
+
+    #[tokio::test]
+    async fn watch_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let store = WatchStateStore::new(dir.path(), "pop3", INBOX).unwrap();
+
+        let server = Pop3Server::start().await;
+        server.add_message("uid-1", "first", "hello");
+
+        // messages found on first start are not notified
+        let (shutdown, task) = start(&server, &store, &log).await;
+        server.take_commands();
+
+        // new unique identifiers are notified, with headers
+        server.add_message("uid-2", "second", "hello");
+        let lines = wait_for_lines(&log, 1).await;
+        assert_eq!(lines, ["uid-2 second"]);
+
+        let commands = server.take_commands();
+        assert!(commands.contains(&String::from("TOP 2 0")), "{commands:?}");
+        assert!(!commands.iter().any(|cmd| cmd.starts_with("RETR")));
+        assert!(!commands.iter().any(|cmd| cmd == "TOP 1 0"));
+
+        shutdown.send(true).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_from_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let store = WatchStateStore::new(dir.path(), "pop3", INBOX).unwrap();
+
+        let server = Pop3Server::start().await;
+        server.add_message("uid-1", "first", "hello");
+
+        let (shutdown, task) = start(&server, &store, &log).await;
+        server.add_message("uid-2", "second", "hello");
+        assert_eq!(wait_for_lines(&log, 1).await, ["uid-2 second"]);
+        shutdown.send(true).unwrap();
+        task.await.unwrap().unwrap();
+
+        // only the message that arrived while stopped is notified
+        // on restart
+        server.add_message("uid-3", "third", "hello");
+        let (shutdown, task) = start(&server, &store, &log).await;
+        assert_eq!(wait_for_lines(&log, 1).await, ["uid-3 third"]);
+
+        // known messages are not notified again by later polls
+        sleep(POLL_INTERVAL * 4).await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "");
+
+        shutdown.send(true).unwrap();
+        task.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+//! # Watch state
+//!
+//! Module dedicated to the persistence of watch state. The state of
+//! a watched folder is saved after every change, so that messages
+//! that arrived while Mirador was not running can be detected on
+//! startup.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use email::envelope::Envelope;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::event::EnvelopesMap;
+
+/// The watch state of a folder.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum WatchState {
+    /// The IMAP state, based on UIDs.
+    ///
+    /// The state is only valid as long as the UIDVALIDITY of the
+    /// folder does not change.
+    #[serde(rename_all = "kebab-case")]
+    Imap { uid_validity: u32, last_uid: u32 },
+
+    /// The Maildir state, based on the set of known identifiers.
+    Maildir { ids: BTreeSet<String> },
+
+    /// The Notmuch state, based on the set of message identifiers
+    /// matching the watched query.
+    Notmuch { ids: BTreeSet<String> },
+
+    /// The JMAP state, based on the set of email identifiers of the
+    /// watched mailbox.
+    Jmap { ids: BTreeSet<String> },
+
+    /// The POP3 state, based on the set of unique identifiers (UIDL)
+    /// of the mailbox.
+    Pop3 { ids: BTreeSet<String> },
+}
+
+impl WatchState {
+    pub fn imap(uid_validity: u32, envelopes: &EnvelopesMap) -> Self {
+        let last_uid = envelopes
+            .keys()
+            .filter_map(|id| id.parse().ok())
+            .max()
+            .unwrap_or_default();
+
+        Self::Imap {
+            uid_validity,
+            last_uid,
+        }
+    }
+
+    pub fn maildir(envelopes: &EnvelopesMap) -> Self {
+        Self::Maildir {
+            ids: envelopes.keys().cloned().collect(),
+        }
+    }
+
+    pub fn notmuch(envelopes: &EnvelopesMap) -> Self {
+        Self::Notmuch {
+            ids: envelopes.keys().cloned().collect(),
+        }
+    }
+
+    pub fn jmap(envelopes: &EnvelopesMap) -> Self {
+        Self::Jmap {
+            ids: envelopes.keys().cloned().collect(),
+        }
+    }
+
+    pub fn pop3(envelopes: &EnvelopesMap) -> Self {
+        Self::Pop3 {
+            ids: envelopes.keys().cloned().collect(),
+        }
+    }
+
+    /// Find envelopes that are not part of the current state.
+    ///
+    /// The given state is the one of the given envelopes. Returns
+    /// `None` if both states cannot be compared, for example when
+    /// the IMAP UIDVALIDITY changed.
+    pub fn find_missed(&self, next: &Self, envelopes: &EnvelopesMap) -> Option<Vec<Envelope>> {
+        let mut missed: Vec<Envelope> = match (self, next) {
+            (
+                Self::Imap {
+                    uid_validity,
+                    last_uid,
+                },
+                Self::Imap {
+                    uid_validity: next_uid_validity,
+                    ..
+                },
+            ) if uid_validity == next_uid_validity => envelopes
+                .values()
+                .filter(|e| matches!(e.id.parse::<u32>(), Ok(uid) if uid > *last_uid))
+                .cloned()
+                .collect(),
+            (Self::Maildir { ids }, Self::Maildir { .. })
+            | (Self::Notmuch { ids }, Self::Notmuch { .. })
+            | (Self::Jmap { ids }, Self::Jmap { .. })
+            | (Self::Pop3 { ids }, Self::Pop3 { .. }) => envelopes
+                .values()
+                .filter(|e| !ids.contains(&e.id))
+                .cloned()
+                .collect(),
+            _ => return None,
+        };
+
+        missed.sort_by(|a, b| a.date.cmp(&b.date));
+        Some(missed)
+    }
+}
+
+/// The watch state store.
+///
+/// Each watched folder has its own state file, located at
+/// `<dir>/<account>/<folder>.json`. Account and folder names are
+/// percent-encoded, so that distinct names never share a state file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WatchStateStore {
+    path: PathBuf,
+}
+
+impl WatchStateStore {
+    pub fn new(dir: impl AsRef<Path>, account: &str, folder: &str) -> Result<Self> {
+        // the extension is appended rather than set, since folder
+        // names can contain dots (INBOX.Alerts, INBOX.Bills…)
+        let path = dir
+            .as_ref()
+            .join(encode(account)?)
+            .join(format!("{}.json", encode(folder)?));
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load the state from the state file.
+    ///
+    /// Returns `None` if the state file does not exist yet.
+    pub fn load(&self) -> Result<Option<WatchState>> {
+        let path = &self.path;
+
+        if !path.exists() {
+            debug!(?path, "state file not found");
+            return Ok(None);
+        }
+
+        let state = fs::read(path).wrap_err(format!("cannot read state file {path:?}"))?;
+        let state =
+            serde_json::from_slice(&state).wrap_err(format!("cannot parse state file {path:?}"))?;
+
+        Ok(Some(state))
+    }
+
+    /// Save the given state to the state file.
+    pub fn save(&self, state: &WatchState) -> Result<()> {
+        let path = &self.path;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).wrap_err(format!("cannot create state dir {dir:?}"))?;
+        }
+
+        // the state is written to a temporary file first, then
+        // renamed, so that a crash never leaves a truncated state
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let state = serde_json::to_vec(state)?;
+        fs::write(&tmp, state).wrap_err(format!("cannot write state file {tmp:?}"))?;
+        fs::rename(&tmp, path).wrap_err(format!("cannot rename state file to {path:?}"))?;
+
+        Ok(())
+    }
+}
+
+/// Turn the given name into a valid file name.
+///
+/// Characters that are not valid in file names on every platform are
+/// percent-encoded, as well as the percent sign itself, which makes
+/// the encoding reversible.
+fn encode(name: &str) -> Result<String> {
+    if name.is_empty() || name == "." || name == ".." {
+        bail!("cannot use {name:?} as state file name");
+    }
+
+    let mut encoded = String::with_capacity(name.len());
+
+    for c in name.chars() {
+        match c {
+            '%' | '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => {
+                encoded.push_str(&format!("%{:02X}", c as u32))
+            }
+            c if c.is_control() => {
+                let mut buf = [0; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    encoded.push_str(&format!("%{byte:02X}"));
+                }
+            }
+            c => encoded.push(c),
+        }
+    }
+
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use email::envelope::Envelope;
+
+    use super::*;
+
+    fn envelopes(ids: &[&str]) -> EnvelopesMap {
+        ids.iter()
+            .map(|id| {
+                let envelope = Envelope {
+                    id: id.to_string(),
+                    ..Default::default()
+                };
+                (id.to_string(), envelope)
+            })
+            .collect()
+    }
+
+    fn ids(envelopes: &[Envelope]) -> Vec<&str> {
+        let mut ids: Vec<_> = envelopes.iter().map(|e| e.id.as_str()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn store_path_keeps_dotted_folder_names() {
+        let alerts = WatchStateStore::new("/state", "work", "INBOX.Alerts").unwrap();
+        let bills = WatchStateStore::new("/state", "work", "INBOX.Bills").unwrap();
+
+        assert_eq!(alerts.path(), Path::new("/state/work/INBOX.Alerts.json"));
+        assert_eq!(bills.path(), Path::new("/state/work/INBOX.Bills.json"));
+    }
+
+    #[test]
+    fn store_path_encodes_names() {
+        let store = WatchStateStore::new("/state", "a/b", "Lists/Rust").unwrap();
+        assert_eq!(store.path(), Path::new("/state/a%2Fb/Lists%2FRust.json"));
+    }
+
+    #[test]
+    fn store_path_does_not_collide() {
+        let names = ["a/b", "a_b", "a:b", "a%2Fb", "a\\b"];
+        let paths: BTreeSet<_> = names
+            .iter()
+            .map(|name| {
+                let store = WatchStateStore::new("/state", "work", name).unwrap();
+                store.path().to_owned()
+            })
+            .collect();
+
+        assert_eq!(paths.len(), names.len());
+    }
+
+    #[test]
+    fn store_path_rejects_relative_names() {
+        assert!(WatchStateStore::new("/state", ".", "INBOX").is_err());
+        assert!(WatchStateStore::new("/state", "..", "INBOX").is_err());
+        assert!(WatchStateStore::new("/state", "work", "..").is_err());
+        assert!(WatchStateStore::new("/state", "work", "").is_err());
+    }
+
+    #[test]
+    fn store_save_replaces_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WatchStateStore::new(dir.path(), "work", "INBOX").unwrap();
+
+        store
+            .save(&WatchState::maildir(&envelopes(&["a", "b"])))
+            .unwrap();
+        let state = WatchState::maildir(&envelopes(&["c"]));
+        store.save(&state).unwrap();
+
+        assert_eq!(store.load().unwrap(), Some(state));
+        // no temporary file is left behind
+        let files = fs::read_dir(dir.path().join("work")).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn store_keeps_dotted_folders_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let alerts = WatchStateStore::new(dir.path(), "work", "INBOX.Alerts").unwrap();
+        let bills = WatchStateStore::new(dir.path(), "work", "INBOX.Bills").unwrap();
+
+        let alerts_state = WatchState::maildir(&envelopes(&["a1"]));
+        let bills_state = WatchState::maildir(&envelopes(&["b1", "b2"]));
+        alerts.save(&alerts_state).unwrap();
+        bills.save(&bills_state).unwrap();
+
+        assert_eq!(alerts.load().unwrap(), Some(alerts_state));
+        assert_eq!(bills.load().unwrap(), Some(bills_state));
+    }
+
+    #[test]
+    fn store_load_without_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WatchStateStore::new(dir.path(), "work", "INBOX").unwrap();
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn find_missed_imap() {
+        let prev = WatchState::imap(7, &envelopes(&["1", "2"]));
+        let next_envelopes = envelopes(&["2", "3", "4"]);
+        let next = WatchState::imap(7, &next_envelopes);
+
+        let missed = prev.find_missed(&next, &next_envelopes).unwrap();
+        assert_eq!(ids(&missed), ["3", "4"]);
+    }
+
+    #[test]
+    fn find_missed_imap_uid_validity_changed() {
+        let prev = WatchState::imap(7, &envelopes(&["1"]));
+        let next_envelopes = envelopes(&["1", "2"]);
+        let next = WatchState::imap(8, &next_envelopes);
+
+        assert!(prev.find_missed(&next, &next_envelopes).is_none());
+    }
+
+    #[test]
+    fn find_missed_ids() {
+        let prev = WatchState::maildir(&envelopes(&["a", "b"]));
+        let next_envelopes = envelopes(&["b", "c"]);
+        let next = WatchState::maildir(&next_envelopes);
+
+        let missed = prev.find_missed(&next, &next_envelopes).unwrap();
+        assert_eq!(ids(&missed), ["c"]);
+    }
+
+    #[test]
+    fn find_missed_different_backends() {
+        let prev = WatchState::maildir(&envelopes(&["a"]));
+        let next_envelopes = envelopes(&["a", "b"]);
+        let next = WatchState::jmap(&next_envelopes);
+
+        assert!(prev.find_missed(&next, &next_envelopes).is_none());
+    }
+}
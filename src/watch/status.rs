@@ -0,0 +1,130 @@
+//! # Watch status
+//!
+//! Module dedicated to the status of watched folders. Watchers
+//! report whether their folder is actually being watched, so that
+//! the status can be reported to a service manager.
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use tokio::sync::watch;
+
+/// The watched folders, with their readiness.
+///
+/// Folders are identified by their account name and folder name.
+pub type WatchStatusMap = BTreeMap<(String, String), bool>;
+
+/// The shared status of all watched folders.
+///
+/// Clones share the same status.
+#[derive(Clone, Debug)]
+pub struct WatchStatus(Arc<watch::Sender<WatchStatusMap>>);
+
+impl Default for WatchStatus {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(WatchStatusMap::default()).0))
+    }
+}
+
+impl WatchStatus {
+    /// Register the given folder, not ready yet.
+    ///
+    /// The folder is unregistered when the returned guard is
+    /// dropped.
+    pub fn register(&self, account: &str, folder: &str) -> WatchStatusGuard {
+        let key = (account.to_owned(), folder.to_owned());
+
+        self.0.send_modify(|status| {
+            status.insert(key.clone(), false);
+        });
+
+        WatchStatusGuard {
+            status: self.clone(),
+            key,
+        }
+    }
+
+    /// Set the readiness of the given folder.
+    ///
+    /// Subscribers are only notified when the readiness changes.
+    pub fn set_ready(&self, account: &str, folder: &str, ready: bool) {
+        self.0.send_if_modified(|status| {
+            let key = (account.to_owned(), folder.to_owned());
+
+            match status.get_mut(&key) {
+                Some(prev) if *prev != ready => {
+                    *prev = ready;
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+
+    /// Subscribe to status changes.
+    pub fn subscribe(&self) -> watch::Receiver<WatchStatusMap> {
+        self.0.subscribe()
+    }
+}
+
+/// Unregisters a folder from the status when dropped.
+#[derive(Debug)]
+pub struct WatchStatusGuard {
+    status: WatchStatus,
+    key: (String, String),
+}
+
+impl Drop for WatchStatusGuard {
+    fn drop(&mut self) {
+        self.status.0.send_modify(|status| {
+            status.remove(&self.key);
+        });
+    }
+}
+
+/// Human-readable summary of a status, for example `Watching
+/// INBOX, Sent of account perso`.
+pub struct WatchStatusSummary<'a>(pub &'a WatchStatusMap);
+
+impl WatchStatusSummary<'_> {
+    /// Tell whether all the folders are being watched.
+    pub fn is_ready(&self) -> bool {
+        !self.0.is_empty() && self.0.values().all(|ready| *ready)
+    }
+}
+
+impl fmt::Display for WatchStatusSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut watching: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        let mut waiting: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+
+        for ((account, folder), ready) in self.0 {
+            let folders = if *ready {
+                watching.entry(account).or_default()
+            } else {
+                waiting.entry(account).or_default()
+            };
+
+            folders.push(folder);
+        }
+
+        let describe = |folders: BTreeMap<&str, Vec<&str>>| {
+            folders
+                .into_iter()
+                .map(|(account, folders)| format!("{} of account {account}", folders.join(", ")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match (watching.is_empty(), waiting.is_empty()) {
+            (true, true) => write!(f, "Not watching any folder"),
+            (false, true) => write!(f, "Watching {}", describe(watching)),
+            (true, false) => write!(f, "Connecting to {}", describe(waiting)),
+            (false, false) => write!(
+                f,
+                "Watching {}, connecting to {}",
+                describe(watching),
+                describe(waiting)
+            ),
+        }
+    }
+}
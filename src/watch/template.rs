@@ -0,0 +1,582 @@
+//! # Watch template
+//!
+//! Module dedicated to hook templates. A template is a string
+//! containing placeholders like `{subject}`, optionally followed by
+//! filters separated by pipes, like `{subject|truncate:30|shell}`.
+//!
+//! Unknown placeholders are kept as is, so that shell constructs
+//! like `${HOME}` or `awk '{print $1}'` are not altered.
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
+use email::envelope::Address;
+use tracing::debug;
+
+use super::event::WatchEvent;
+
+/// The default format of the `{date}` placeholder.
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// The maximum number of senders listed by the `{senders}`
+/// placeholder.
+pub const MAX_SENDERS: usize = 3;
+
+/// The placeholders that require to fetch the whole message.
+pub const DETAILS_PLACEHOLDERS: [&str; 2] = ["{cc", "{preview"];
+
+/// The placeholders exported to commands as environment variables.
+const ENV_PLACEHOLDERS: &[&str] = &[
+    "account",
+    "folder",
+    "count",
+    "senders",
+    "id",
+    "message-id",
+    "subject",
+    "sender",
+    "sender.name",
+    "sender.address",
+    "recipient",
+    "recipient.name",
+    "recipient.address",
+    "cc",
+    "flags",
+    "has-attachment",
+    "preview",
+    "error",
+];
+
+/// The context templates are rendered with.
+///
+/// The `{count}` and `{senders}` placeholders describe all the
+/// events, while other placeholders describe the most recent one.
+pub struct TemplateContext<'a> {
+    pub account: &'a str,
+    pub folder: &'a str,
+    pub events: &'a [&'a WatchEvent],
+
+    /// The error of the failed hook, only defined for the hook
+    /// failed hook.
+    pub error: Option<&'a str>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(account: &'a str, folder: &'a str, events: &'a [&'a WatchEvent]) -> Self {
+        Self {
+            account,
+            folder,
+            events,
+            error: None,
+        }
+    }
+
+    /// Expose the given error through the `{error}` placeholder.
+    pub fn with_error(mut self, error: &'a str) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    /// Render the given template.
+    pub fn render(&self, template: &str) -> String {
+        self.render_with(template, false)
+    }
+
+    /// Render the given shell command template.
+    ///
+    /// Placeholder values are quoted for a POSIX shell, as if the
+    /// `shell` filter was applied last, so that each value stays one
+    /// argument whatever its content.
+    pub fn render_shell(&self, template: &str) -> String {
+        self.render_with(template, true)
+    }
+
+    fn render_with(&self, template: &str, quote: bool) -> String {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+
+            let inner = &rest[1..end];
+
+            // nested braces cannot be a placeholder, only the
+            // opening one is kept as is
+            if inner.contains('{') {
+                output.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+
+            match self.render_placeholder(inner, quote) {
+                Some(value) => output.push_str(&value),
+                None => output.push_str(&rest[..=end]),
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        output.push_str(rest);
+        output
+    }
+
+    /// Get the environment variables exported to commands.
+    ///
+    /// Placeholders are exported with the `MIRADOR_` prefix, in
+    /// uppercase, with dots and dashes replaced by underscores: for
+    /// example `{sender.address}` becomes `MIRADOR_SENDER_ADDRESS`.
+    /// The kind of event is exported as `MIRADOR_EVENT`, and the date
+    /// as `MIRADOR_DATE` using the RFC 3339 format.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+
+        for name in ENV_PLACEHOLDERS {
+            if let Some(value) = self.value(name) {
+                let key = name.to_uppercase().replace(['.', '-'], "_");
+                env.push((format!("MIRADOR_{key}"), value));
+            }
+        }
+
+        if let Some(event) = self.events.last() {
+            env.push((String::from("MIRADOR_EVENT"), event.kind.to_string()));
+            let date = event.envelope.date.to_rfc3339();
+            env.push((String::from("MIRADOR_DATE"), date));
+        }
+
+        env
+    }
+
+    /// Render the given placeholder, without braces.
+    ///
+    /// Returns `None` if the placeholder is unknown.
+    fn render_placeholder(&self, placeholder: &str, quote: bool) -> Option<String> {
+        let mut filters = placeholder.split('|');
+        let name = filters.next()?.trim();
+        let filters: Vec<_> = filters.map(str::trim).collect();
+
+        let mut value = if name == "date" {
+            let format = filters
+                .iter()
+                .find_map(|filter| filter.strip_prefix("format:"))
+                .unwrap_or(DEFAULT_DATE_FORMAT);
+            self.date(format)
+        } else {
+            self.value(name)?
+        };
+
+        // values already quoted must not be quoted twice
+        let quote = quote && filters.last() != Some(&"shell");
+
+        for filter in filters {
+            value = apply_filter(filter, value);
+        }
+
+        if quote {
+            value = apply_filter("shell", value);
+        }
+
+        Some(value)
+    }
+
+    /// Get the value of the given placeholder name.
+    fn value(&self, name: &str) -> Option<String> {
+        let event = self.events.last();
+        let envelope = event.map(|event| &event.envelope);
+        let details = event.and_then(|event| event.details.as_ref());
+
+        let value = match name {
+            "account" => self.account.to_owned(),
+            "folder" => self.folder.to_owned(),
+            "count" => self.events.len().to_string(),
+            "senders" => self.senders(),
+            "error" => self.error?.to_owned(),
+            "id" => envelope?.id.clone(),
+            "message-id" => envelope?.message_id.clone(),
+            "subject" => envelope?.subject.clone(),
+            "sender" => display_name(&envelope?.from),
+            "sender.name" => name_or_unknown(&envelope?.from),
+            "sender.address" => envelope?.from.addr.clone(),
+            "recipient" => display_name(&envelope?.to),
+            "recipient.name" => name_or_unknown(&envelope?.to),
+            "recipient.address" => envelope?.to.addr.clone(),
+            "flags" => {
+                let flags = envelope?.flags.iter().map(|flag| flag.to_string());
+                flags.collect::<Vec<_>>().join(", ")
+            }
+            "has-attachment" => envelope?.has_attachment.to_string(),
+            "cc" => match details {
+                Some(details) => {
+                    let cc = details.cc.iter().map(display_name);
+                    cc.collect::<Vec<_>>().join(", ")
+                }
+                None => String::new(),
+            },
+            "preview" => match details {
+                Some(details) => details.preview.clone(),
+                None => String::new(),
+            },
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Format the date of the most recent envelope, in local time.
+    fn date(&self, format: &str) -> String {
+        let Some(event) = self.events.last() else {
+            return String::new();
+        };
+
+        let items: Vec<_> = StrftimeItems::new(format).collect();
+
+        // formatting a date with an invalid format panics
+        if items.iter().any(|item| matches!(item, Item::Error)) {
+            debug!(format, "invalid date format, using default one");
+            return self.date(DEFAULT_DATE_FORMAT);
+        }
+
+        let date = event.envelope.date.with_timezone(&Local);
+        date.format_with_items(items.into_iter()).to_string()
+    }
+
+    /// Join the distinct senders of all the events.
+    ///
+    /// Senders are listed from the most recent one, and the list is
+    /// truncated after [`MAX_SENDERS`] senders.
+    fn senders(&self) -> String {
+        let mut senders: Vec<String> = Vec::new();
+
+        for event in self.events.iter().rev() {
+            let sender = display_name(&event.envelope.from);
+
+            if !senders.contains(&sender) {
+                senders.push(sender);
+            }
+        }
+
+        let truncated = senders.len() > MAX_SENDERS;
+        senders.truncate(MAX_SENDERS);
+
+        let mut senders = senders.join(", ");
+
+        if truncated {
+            senders.push('…');
+        }
+
+        senders
+    }
+}
+
+/// Find the placeholders of the given shell command template that
+/// are surrounded by single or double quotes.
+///
+/// Placeholders of shell commands are already quoted when rendered.
+/// Inside double quotes, the added quotes show up literally and the
+/// shell still expands `$(…)` from values. Inside single quotes,
+/// values end the quoting.
+pub fn quoted_placeholders(template: &str) -> Vec<&str> {
+    let mut placeholders = Vec::new();
+    let mut quote = None;
+    let mut chars = template.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '{' {
+            let rest = &template[i..];
+
+            if let Some(placeholder) = rest.find('}').map(|end| &rest[..=end]) {
+                if is_placeholder(&placeholder[1..placeholder.len() - 1]) {
+                    if quote.is_some() {
+                        placeholders.push(placeholder);
+                    }
+
+                    // placeholders cannot end the quoting
+                    chars.nth(placeholder.chars().count() - 2);
+                    continue;
+                }
+            }
+        }
+
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => {
+                chars.next();
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            _ => (),
+        }
+    }
+
+    placeholders
+}
+
+/// Tell whether the given placeholder, without braces, is known.
+fn is_placeholder(placeholder: &str) -> bool {
+    let name = placeholder.split('|').next().unwrap_or_default().trim();
+    name == "date" || ENV_PLACEHOLDERS.contains(&name)
+}
+
+/// Apply the given filter to the given value.
+///
+/// Available filters:
+///  - `truncate:N`: keep at most N characters, including the ellipsis
+///  - `shell`: quote the value for a POSIX shell
+///  - `html`: escape HTML special characters
+///  - `json`: escape the value for a JSON string
+///  - `format:FORMAT`: format of the `{date}` placeholder
+fn apply_filter(filter: &str, value: String) -> String {
+    let (name, arg) = match filter.split_once(':') {
+        Some((name, arg)) => (name.trim(), Some(arg)),
+        None => (filter, None),
+    };
+
+    match (name, arg) {
+        ("truncate", Some(len)) => match len.trim().parse::<usize>() {
+            Ok(len) => truncate(value, len),
+            Err(_) => {
+                debug!(filter, "invalid truncate length, skipping filter");
+                value
+            }
+        },
+        ("shell", None) => format!("'{}'", value.replace('\'', r"'\''")),
+        ("html", None) => value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;"),
+        ("json", None) => {
+            let json = serde_json::to_string(&value).unwrap_or_default();
+
+            // the value is serialized as a JSON string, only its
+            // surrounding quotes are removed
+            match json.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(json) => json.to_owned(),
+                None => json,
+            }
+        }
+        // only applies to dates, see TemplateContext::render_placeholder
+        ("format", Some(_)) => value,
+        _ => {
+            debug!(filter, "unknown template filter, skipping it");
+            value
+        }
+    }
+}
+
+fn truncate(value: String, len: usize) -> String {
+    if value.chars().count() <= len {
+        return value;
+    }
+
+    let mut value: String = value.chars().take(len.saturating_sub(1)).collect();
+    value.push('…');
+    value
+}
+
+/// Get the name of the given address, or the address itself.
+fn display_name(addr: &Address) -> String {
+    addr.name.clone().unwrap_or_else(|| addr.addr.clone())
+}
+
+fn name_or_unknown(addr: &Address) -> String {
+    addr.name.clone().unwrap_or_else(|| String::from("unknown"))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use email::envelope::Envelope;
+
+    use super::*;
+    use crate::watch::event::WatchEventKind;
+
+    fn event(subject: &str) -> WatchEvent {
+        let envelope = Envelope {
+            id: "42".into(),
+            subject: subject.into(),
+            from: Address::new(Some("Alice"), "alice@localhost"),
+            date: DateTime::parse_from_rfc3339("2026-10-18T09:30:00+00:00").unwrap(),
+            ..Default::default()
+        };
+
+        WatchEvent::new(WatchEventKind::MessageAdded, envelope)
+    }
+
+    fn render(subject: &str, template: &str) -> String {
+        let event = event(subject);
+        let events = [&event];
+        TemplateContext::new("example", "INBOX", &events).render(template)
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            render(
+                "Hello",
+                "{account}/{folder}: {sender} wrote {subject} ({count})"
+            ),
+            "example/INBOX: Alice wrote Hello (1)"
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept() {
+        assert_eq!(render("Hello", "${HOME} {unknown}"), "${HOME} {unknown}");
+        assert_eq!(render("Hello", "awk '{print $1}'"), "awk '{print $1}'");
+        assert_eq!(render("Hello", "{{subject}"), "{Hello");
+    }
+
+    #[test]
+    fn filter_truncate() {
+        assert_eq!(render("Hello world", "{subject|truncate:5}"), "Hell…");
+        assert_eq!(render("Hello", "{subject|truncate:5}"), "Hello");
+        assert_eq!(render("Héllo wörld", "{subject|truncate:8}"), "Héllo w…");
+        assert_eq!(render("Hello", "{subject|truncate:0}"), "…");
+        assert_eq!(render("Hello", "{subject|truncate:abc}"), "Hello");
+    }
+
+    #[test]
+    fn filter_shell() {
+        assert_eq!(
+            render("it's $(rm -rf ~)", "{subject|shell}"),
+            r"'it'\''s $(rm -rf ~)'"
+        );
+    }
+
+    #[test]
+    fn render_shell_quotes_values() {
+        let event = event("it's $(rm -rf ~)");
+        let events = [&event];
+        let ctx = TemplateContext::new("example", "INBOX", &events);
+
+        assert_eq!(
+            ctx.render_shell("notify-send {sender} {subject|truncate:6}"),
+            r"notify-send 'Alice' 'it'\''s …'"
+        );
+        assert_eq!(
+            ctx.render_shell("echo {subject|shell} ${HOME}"),
+            r"echo 'it'\''s $(rm -rf ~)' ${HOME}"
+        );
+    }
+
+    #[test]
+    fn render_shell_in_double_quotes() {
+        let event = event("it's $(rm -rf ~)");
+        let events = [&event];
+        let ctx = TemplateContext::new("example", "INBOX", &events);
+
+        // the added quotes are kept by the shell, and the command
+        // substitution is still performed: see quoted_placeholders
+        assert_eq!(
+            ctx.render_shell(r#"notify-send "{subject}""#),
+            r#"notify-send "'it'\''s $(rm -rf ~)'""#
+        );
+    }
+
+    #[test]
+    fn find_quoted_placeholders() {
+        let find = quoted_placeholders;
+
+        assert_eq!(find(r#"notify-send "{subject}""#), ["{subject}"]);
+        assert_eq!(
+            find(r#"notify-send "New: {subject|truncate:9}""#),
+            ["{subject|truncate:9}"]
+        );
+        assert_eq!(find("notify-send '{sender}' {subject}"), ["{sender}"]);
+        assert_eq!(
+            find(r#"echo "{folder}" '{count}'"#),
+            ["{folder}", "{count}"]
+        );
+
+        assert!(find("notify-send {sender} {subject}").is_empty());
+        assert!(find(r#"echo "a" {subject} 'b'"#).is_empty());
+        assert!(find(r#"echo \"{subject}\""#).is_empty());
+        assert!(find(r#"echo "it's" {subject}"#).is_empty());
+
+        // unknown placeholders are shell or awk constructs
+        assert!(find(r#"awk '{print $1}' "${HOME}""#).is_empty());
+    }
+
+    #[test]
+    fn filter_html() {
+        assert_eq!(
+            render(r#"<b>"Tom" & 'Jerry'</b>"#, "{subject|html}"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn filter_json() {
+        assert_eq!(render("Hello", "{subject|json}"), "Hello");
+        assert_eq!(render("a\tb\nc\\d", "{subject|json}"), r"a\tb\nc\\d");
+    }
+
+    #[test]
+    fn filter_json_keeps_inner_quotes() {
+        assert_eq!(render(r#"say "hi""#, "{subject|json}"), r#"say \"hi\""#);
+        assert_eq!(render(r#""quoted""#, "{subject|json}"), r#"\"quoted\""#);
+        assert_eq!(render(r#"""#, "{subject|json}"), r#"\""#);
+        assert_eq!(render("", "{subject|json}"), "");
+    }
+
+    #[test]
+    fn filters_chain() {
+        assert_eq!(
+            render(r#"say "hello world""#, "{subject|truncate:9|json}"),
+            r#"say \"hel…"#
+        );
+    }
+
+    #[test]
+    fn filter_unknown() {
+        assert_eq!(render("Hello", "{subject|unknown}"), "Hello");
+    }
+
+    #[test]
+    fn date_format() {
+        let date = event("").envelope.date.with_timezone(&Local);
+
+        assert_eq!(
+            render("", "{date}"),
+            date.format(DEFAULT_DATE_FORMAT).to_string()
+        );
+        assert_eq!(
+            render("", "{date|format:%d/%m/%Y}"),
+            date.format("%d/%m/%Y").to_string()
+        );
+        assert_eq!(render("", "{date|format:%Y|truncate:3}"), "20…");
+    }
+
+    #[test]
+    fn date_invalid_format() {
+        let date = event("").envelope.date.with_timezone(&Local);
+
+        assert_eq!(
+            render("", "{date|format:%Q}"),
+            date.format(DEFAULT_DATE_FORMAT).to_string()
+        );
+    }
+
+    #[test]
+    fn senders() {
+        let events: Vec<_> = ["a", "b", "a", "c", "d"]
+            .into_iter()
+            .map(|name| {
+                let mut event = event("");
+                event.envelope.from = Address::new_nameless(name);
+                event
+            })
+            .collect();
+        let events: Vec<_> = events.iter().collect();
+        let ctx = TemplateContext::new("example", "INBOX", &events);
+
+        assert_eq!(ctx.render("{count} from {senders}"), "5 from d, c, a…");
+    }
+}